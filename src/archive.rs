@@ -0,0 +1,196 @@
+// CSV files inside ZIP archives, for the `zip` cargo feature. The zip crate
+// only reads the archive's directory. The entry's bytes are then read
+// straight from the archive file and inflated with flate2, which gives an
+// owned, streaming reader (a ZipFile borrows its ZipArchive, so it can't
+// outlive the method that opened it). Seeking is emulated by reading
+// forward, or by starting over to go back, so every method works on an
+// entry, only slower where it would seek.
+
+use pyo3::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::files;
+
+// Where an entry's data is and how it is stored
+#[derive(Clone)]
+pub struct Entry {
+    archive: String,
+    pub name: String,
+    data_start: u64,
+    compressed_size: u64,
+    // Uncompressed size
+    pub size: u64,
+    deflated: bool,
+}
+
+// The entry `name` of the archive at `archive`, or its only CSV entry (or
+// only entry) when `name` is None
+pub fn locate(archive: &str, name: Option<&str>) -> PyResult<Entry> {
+    let mut zip = open(archive)?;
+    let index = match name {
+        Some(name) => match zip.index_for_name(name) {
+            Some(index) => index,
+            None => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{} has no entry named {}",
+                    archive, name
+                )));
+            }
+        },
+        None => {
+            let names = file_names(&zip);
+            let csvs: Vec<&String> = names
+                .iter()
+                .filter(|n| n.to_ascii_lowercase().ends_with(".csv"))
+                .collect();
+            let only = match (csvs.as_slice(), names.as_slice()) {
+                ([csv], _) => *csv,
+                ([], [file]) => file,
+                _ => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} has {} CSV entries; pass entry_name to pick one of {:?}",
+                        archive,
+                        csvs.len(),
+                        if csvs.is_empty() {
+                            names.iter().collect()
+                        } else {
+                            csvs
+                        }
+                    )));
+                }
+            };
+            zip.index_for_name(only).unwrap_or_default()
+        }
+    };
+
+    let file = zip.by_index_raw(index).map_err(|e| zip_error(archive, e))?;
+    // The zip crate's decompressors aren't built in (see Cargo.toml), so
+    // compare with its constants rather than the feature-gated variants
+    let deflated = match file.compression() {
+        m if m == zip::CompressionMethod::STORE => false,
+        m if m == zip::CompressionMethod::DEFLATE => true,
+        #[allow(deprecated)]
+        other => {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "{} in {} uses compression method {}; only stored (0) and deflated (8) \
+                 entries are supported",
+                file.name(),
+                archive,
+                other.to_u16()
+            )));
+        }
+    };
+    if file.encrypted() {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "{} in {} is encrypted, which is not supported",
+            file.name(),
+            archive
+        )));
+    }
+    Ok(Entry {
+        archive: archive.to_string(),
+        name: file.name().to_string(),
+        data_start: file.data_start(),
+        compressed_size: file.compressed_size(),
+        size: file.size(),
+        deflated,
+    })
+}
+
+// Names of the files in the archive at `archive`, in archive order
+pub fn entries(archive: &str) -> PyResult<Vec<String>> {
+    Ok(file_names(&open(archive)?))
+}
+
+fn open(archive: &str) -> PyResult<zip::ZipArchive<File>> {
+    let file = match files::open(Path::new(archive)) {
+        Ok(file) => file,
+        Err(e) => return Err(files::io_error("open file", e)),
+    };
+    zip::ZipArchive::new(file).map_err(|e| zip_error(archive, e))
+}
+
+fn file_names(zip: &zip::ZipArchive<File>) -> Vec<String> {
+    let mut names: Vec<(usize, String)> = zip
+        .file_names()
+        .filter(|n| !n.ends_with('/'))
+        .map(|n| (zip.index_for_name(n).unwrap_or_default(), n.to_string()))
+        .collect();
+    names.sort();
+    names.into_iter().map(|(_, n)| n).collect()
+}
+
+fn zip_error(archive: &str, e: zip::result::ZipError) -> PyErr {
+    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+        "{} is not a readable zip archive: {}",
+        archive, e
+    ))
+}
+
+impl Entry {
+    pub fn open(&self) -> io::Result<EntryReader> {
+        Ok(EntryReader {
+            inner: self.start()?,
+            entry: self.clone(),
+            pos: 0,
+        })
+    }
+
+    // The entry's uncompressed bytes from the beginning
+    fn start(&self) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = files::open(Path::new(&self.archive))?;
+        file.seek(SeekFrom::Start(self.data_start))?;
+        let raw = BufReader::new(file.take(self.compressed_size));
+        if self.deflated {
+            Ok(Box::new(flate2::bufread::DeflateDecoder::new(raw)))
+        } else {
+            Ok(Box::new(raw))
+        }
+    }
+}
+
+// An open entry, read from the start and seekable by re-reading
+pub struct EntryReader {
+    entry: Entry,
+    inner: Box<dyn Read + Send>,
+    pos: u64,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => self.entry.size.checked_add_signed(d),
+        };
+        let target = match target {
+            Some(t) => t,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "seek to a negative position",
+                ));
+            }
+        };
+        if target < self.pos {
+            self.inner = self.entry.start()?;
+            self.pos = 0;
+        }
+        let skipped = io::copy(
+            &mut (&mut self.inner).take(target - self.pos),
+            &mut io::sink(),
+        )?;
+        self.pos += skipped;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,58 @@
+// Ctrl-C for the scans that run without the GIL (py.allow_threads), where
+// CSVParser::check_signals can't run. Every signal_check_interval records
+// a scan takes the GIL just long enough to run Python's signal handlers.
+// An exception they raise is kept and the flag set, so the scan, and any
+// other loop polling the same Cancel, stops at its next check; the caller
+// raises the exception once allow_threads returns, before it looks at
+// what the scan returned.
+
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+pub struct Cancel {
+    // Records between checks; 0 never checks
+    interval: usize,
+    cancelled: AtomicBool,
+    error: Mutex<Option<PyErr>>,
+}
+
+impl Cancel {
+    pub fn new(interval: usize) -> Self {
+        Cancel {
+            interval,
+            cancelled: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }
+    }
+
+    // Whether the scan should stop, after its `records`-th record
+    pub fn check(&self, records: usize) -> bool {
+        if self.cancelled() {
+            return true;
+        }
+        if self.interval == 0 || records == 0 || !records.is_multiple_of(self.interval) {
+            return false;
+        }
+        match Python::with_gil(|py| py.check_signals()) {
+            Ok(()) => false,
+            Err(e) => {
+                *self.error.lock().unwrap() = Some(e);
+                self.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // The exception that stopped the scan, if one did
+    pub fn raise(self) -> PyResult<()> {
+        match self.error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,498 @@
+// Compressed input: gzip, for the `bgzf` cargo feature, and zstd, for the
+// `zstd` one. Reading from the start is plain stream decompression. Two
+// formats can also seek: BGZF (blocked gzip, as written by bgzip and used
+// for bioinformatics tables) is gzip made of independent members of at
+// most 64 KB, each stating its own compressed size, and zstd's seekable
+// format is independent frames followed by a table of their sizes. An
+// index of where every block starts, in the file and in the uncompressed
+// data, makes any uncompressed offset one block's decompression away. It
+// is built by the first seek, so a file only ever read from the start
+// never needs it.
+//
+// Other gzip and zstd files can only seek by decompressing up to the
+// target, or from the start again to go back, as for a ZIP entry.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+// A BGZF block's fixed header: gzip's 10 bytes, XLEN, and the "BC"
+// subfield holding BSIZE (the block's size less one)
+#[cfg(feature = "bgzf")]
+const HEADER_LEN: usize = 18;
+// CRC32 and ISIZE (the uncompressed length) end every BGZF block
+#[cfg(feature = "bgzf")]
+const TRAILER_LEN: u64 = 8;
+
+// The seekable zstd format's footer, the last 9 bytes of the file: the
+// number of frames, a descriptor byte and this magic number
+#[cfg(feature = "zstd")]
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+#[cfg(feature = "zstd")]
+const FOOTER_LEN: u64 = 9;
+// The seek table is a skippable frame with this magic number
+#[cfg(feature = "zstd")]
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Codec {
+    #[cfg(feature = "bgzf")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+// A compressed file's format, shared by every reader of the file, and its
+// index once a seek has built it
+pub struct Compressed {
+    codec: Codec,
+    // BGZF or seekable zstd
+    seekable: bool,
+    index: Mutex<Option<Arc<Index>>>,
+    // Uncompressed size of a file without an index, once something asked
+    size: Mutex<Option<u64>>,
+}
+
+// Where each non-empty block is, built from the BGZF block headers and
+// trailers, or the zstd seek table, without decompressing anything
+pub struct Index {
+    blocks: Vec<Block>,
+    // Length of the uncompressed data
+    size: u64,
+}
+
+struct Block {
+    // Offset of the block in the file, and its compressed size
+    offset: u64,
+    len: u64,
+    // Offset of the block's data in the uncompressed data
+    start: u64,
+}
+
+impl Compressed {
+    // The format of `file`, if it is compressed in one this build reads;
+    // None for anything else. Reads the file's first bytes and, for zstd,
+    // its last ones, and leaves it anywhere.
+    pub fn detect(file: &mut File) -> io::Result<Option<Self>> {
+        let mut head = [0u8; 18];
+        file.seek(SeekFrom::Start(0))?;
+        let n = read_full(file, &mut head)?;
+        let (codec, seekable) = match &head[..n] {
+            #[cfg(feature = "bgzf")]
+            [0x1f, 0x8b, ..] => (Codec::Gzip, n == head.len() && block_size(&head).is_some()),
+            #[cfg(feature = "zstd")]
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => (Codec::Zstd, seek_table_footer(file)?.is_some()),
+            _ => return Ok(None),
+        };
+        Ok(Some(Compressed {
+            codec,
+            seekable,
+            index: Mutex::new(None),
+            size: Mutex::new(None),
+        }))
+    }
+
+    pub fn seekable(&self) -> bool {
+        self.seekable
+    }
+
+    // The format's name, for messages
+    pub fn name(&self) -> &'static str {
+        match (self.codec, self.seekable) {
+            #[cfg(feature = "bgzf")]
+            (Codec::Gzip, true) => "BGZF",
+            #[cfg(feature = "bgzf")]
+            (Codec::Gzip, false) => "gzip",
+            #[cfg(feature = "zstd")]
+            (Codec::Zstd, true) => "seekable zstd",
+            #[cfg(feature = "zstd")]
+            (Codec::Zstd, false) => "zstd",
+        }
+    }
+
+    // The index, built from `file` by the first caller
+    fn index(&self, file: &mut File) -> io::Result<Arc<Index>> {
+        let mut index = self.index.lock().unwrap();
+        if let Some(index) = &*index {
+            return Ok(index.clone());
+        }
+        let built = Arc::new(match self.codec {
+            #[cfg(feature = "bgzf")]
+            Codec::Gzip => bgzf_index(file)?,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd_index(file)?,
+        });
+        *index = Some(built.clone());
+        Ok(built)
+    }
+
+    // Length of the uncompressed data of `file`, newly opened: from the
+    // index, or, for a file without one, by decompressing it all once
+    pub fn size(&self, mut file: File) -> io::Result<u64> {
+        if self.seekable {
+            return Ok(self.index(&mut file)?.size);
+        }
+        let mut size = self.size.lock().unwrap();
+        if let Some(size) = *size {
+            return Ok(size);
+        }
+        let counted = io::copy(&mut self.decoder(file)?, &mut io::sink())?;
+        *size = Some(counted);
+        Ok(counted)
+    }
+
+    // A stream decompressing `file` from where it is
+    fn decoder(&self, file: File) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self.codec {
+            #[cfg(feature = "bgzf")]
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(BufReader::new(
+                file,
+            ))?),
+        })
+    }
+
+    // Decompress one block of a seekable file into `data`
+    fn decompress(&self, compressed: &[u8], data: &mut Vec<u8>) -> io::Result<()> {
+        data.clear();
+        match self.codec {
+            #[cfg(feature = "bgzf")]
+            Codec::Gzip => {
+                flate2::read::GzDecoder::new(compressed).read_to_end(data)?;
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::stream::copy_decode(compressed, &mut *data)?,
+        }
+        Ok(())
+    }
+}
+
+// The BGZF index of `file`, read block header by block header
+#[cfg(feature = "bgzf")]
+fn bgzf_index(file: &mut File) -> io::Result<Index> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    let mut start = 0;
+    let mut header = [0u8; HEADER_LEN];
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        match read_full(file, &mut header)? {
+            0 => break,
+            HEADER_LEN => {}
+            _ => return Err(truncated(offset)),
+        }
+        let len = match block_size(&header) {
+            Some(len) => len,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the BGZF block at offset {} has no valid header", offset),
+                ));
+            }
+        };
+        file.seek(SeekFrom::Start(offset + len - TRAILER_LEN))?;
+        if read_full(file, &mut trailer)? < trailer.len() {
+            return Err(truncated(offset));
+        }
+        let data_len = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        // Empty blocks, such as the end-of-file marker, hold no data
+        if data_len > 0 {
+            blocks.push(Block { offset, len, start });
+        }
+        offset += len;
+        start += data_len as u64;
+    }
+    Ok(Index {
+        blocks,
+        size: start,
+    })
+}
+
+// The compressed size BSIZE + 1 of the block whose header is `header`, or
+// None if it isn't a BGZF block header
+#[cfg(feature = "bgzf")]
+fn block_size(header: &[u8; HEADER_LEN]) -> Option<u64> {
+    // Deflate, FEXTRA set, a 6-byte extra field holding one "BC" subfield
+    // of 2 bytes
+    let bgzf = header[..4] == [0x1f, 0x8b, 0x08, 0x04]
+        && header[10..16] == [0x06, 0x00, b'B', b'C', 0x02, 0x00];
+    if !bgzf {
+        return None;
+    }
+    let len = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+    (len >= HEADER_LEN as u64 + TRAILER_LEN).then_some(len)
+}
+
+#[cfg(feature = "bgzf")]
+fn truncated(offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("the BGZF block at offset {} is cut off", offset),
+    )
+}
+
+// The number of frames and the size of each seek table entry (8 bytes,
+// or 12 with checksums), if `file` ends with a seekable zstd footer
+#[cfg(feature = "zstd")]
+fn seek_table_footer(file: &mut File) -> io::Result<Option<(u64, u64)>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < FOOTER_LEN + 8 {
+        return Ok(None);
+    }
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    file.seek(SeekFrom::Start(len - FOOTER_LEN))?;
+    file.read_exact(&mut footer)?;
+    let magic = u32::from_le_bytes([footer[5], footer[6], footer[7], footer[8]]);
+    // Bits 2 to 6 of the descriptor are reserved and must be clear
+    if magic != SEEKABLE_MAGIC || footer[4] & 0x7c != 0 {
+        return Ok(None);
+    }
+    let frames = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]) as u64;
+    let entry_len = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+    Ok(Some((frames, entry_len)))
+}
+
+// The index of a seekable zstd file, from its seek table
+#[cfg(feature = "zstd")]
+fn zstd_index(file: &mut File) -> io::Result<Index> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the zstd seek table {}", what),
+        )
+    };
+    let (frames, entry_len) = match seek_table_footer(file)? {
+        Some(footer) => footer,
+        None => return Err(invalid("is missing")),
+    };
+    let len = file.seek(SeekFrom::End(0))?;
+    let table_len = frames
+        .checked_mul(entry_len)
+        .and_then(|n| n.checked_add(8 + FOOTER_LEN))
+        .filter(|&n| n <= len)
+        .ok_or_else(|| invalid("is longer than the file"))?;
+    let table_start = len - table_len;
+    let mut table = vec![0u8; table_len as usize];
+    file.seek(SeekFrom::Start(table_start))?;
+    file.read_exact(&mut table)?;
+    let magic = u32::from_le_bytes([table[0], table[1], table[2], table[3]]);
+    let frame_len = u32::from_le_bytes([table[4], table[5], table[6], table[7]]) as u64;
+    if magic != SKIPPABLE_MAGIC || frame_len != table_len - 8 {
+        return Err(invalid("has no valid frame header"));
+    }
+
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    let mut start = 0;
+    for entry in table[8..]
+        .chunks_exact(entry_len as usize)
+        .take(frames as usize)
+    {
+        let len = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as u64;
+        let data_len = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]) as u64;
+        if data_len > 0 {
+            blocks.push(Block { offset, len, start });
+        }
+        offset += len;
+        start += data_len;
+    }
+    if offset != table_start {
+        return Err(invalid("doesn't add up to the frames before it"));
+    }
+    Ok(Index {
+        blocks,
+        size: start,
+    })
+}
+
+// Fill `buf` unless the file ends first; the number of bytes read
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+// An open compressed file, read as its uncompressed data: decompressed as
+// a stream until the first seek, then, if the file is seekable, one block
+// at a time. Reading on from a block needs no seek; seeking decompresses
+// just the block holding the target.
+pub struct Reader {
+    file: File,
+    format: Arc<Compressed>,
+    mode: Mode,
+    // Offset in the uncompressed data
+    pos: u64,
+}
+
+enum Mode {
+    // A stream from the start of the file, over a clone of `file`
+    Stream(Box<dyn Read + Send>),
+    Blocks(Blocks),
+}
+
+struct Blocks {
+    index: Arc<Index>,
+    // The next block to decompress
+    next: usize,
+    // Where `file` is, to skip the seek when blocks are read in order
+    file_pos: Option<u64>,
+    compressed: Vec<u8>,
+    // The current block's data, and how much of it has been read
+    data: Vec<u8>,
+    consumed: usize,
+}
+
+impl Reader {
+    pub fn new(file: File, format: Arc<Compressed>) -> io::Result<Self> {
+        let stream = format.decoder(file.try_clone()?)?;
+        Ok(Reader {
+            file,
+            format,
+            mode: Mode::Stream(stream),
+            pos: 0,
+        })
+    }
+
+    // Seek a file that has no index by decompressing up to `target`, from
+    // the start again if it is behind
+    fn skip_to(&mut self, target: u64) -> io::Result<u64> {
+        if target < self.pos {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.mode = Mode::Stream(self.format.decoder(self.file.try_clone()?)?);
+            self.pos = 0;
+        }
+        if let Mode::Stream(stream) = &mut self.mode {
+            self.pos += io::copy(&mut stream.take(target - self.pos), &mut io::sink())?;
+        }
+        Ok(self.pos)
+    }
+
+    // Length of the data of a file without an index: unless already
+    // known, found by decompressing the rest of the stream, which a seek
+    // from the end does anyway
+    fn stream_size(&mut self) -> io::Result<u64> {
+        if let Some(size) = *self.format.size.lock().unwrap() {
+            return Ok(size);
+        }
+        if let Mode::Stream(stream) = &mut self.mode {
+            self.pos += io::copy(stream, &mut io::sink())?;
+        }
+        *self.format.size.lock().unwrap() = Some(self.pos);
+        Ok(self.pos)
+    }
+}
+
+impl Blocks {
+    // Decompress block `i` of `file` into `data`
+    fn load(&mut self, i: usize, file: &mut File, format: &Compressed) -> io::Result<()> {
+        let block = &self.index.blocks[i];
+        if self.file_pos != Some(block.offset) {
+            file.seek(SeekFrom::Start(block.offset))?;
+        }
+        self.compressed.resize(block.len as usize, 0);
+        file.read_exact(&mut self.compressed)?;
+        self.file_pos = Some(block.offset + block.len);
+
+        format.decompress(&self.compressed, &mut self.data)?;
+        self.consumed = 0;
+        self.next = i + 1;
+        Ok(())
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let blocks = match &mut self.mode {
+            Mode::Stream(stream) => {
+                let n = stream.read(buf)?;
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            Mode::Blocks(blocks) => blocks,
+        };
+        if blocks.consumed == blocks.data.len() {
+            if blocks.next == blocks.index.blocks.len() {
+                return Ok(0);
+            }
+            blocks.load(blocks.next, &mut self.file, &self.format)?;
+        }
+        let n = buf.len().min(blocks.data.len() - blocks.consumed);
+        buf[..n].copy_from_slice(&blocks.data[blocks.consumed..blocks.consumed + n]);
+        blocks.consumed += n;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if !self.format.seekable {
+            let target = match pos {
+                SeekFrom::End(d) => offset(self.stream_size()?, d)?,
+                SeekFrom::Start(n) => n,
+                SeekFrom::Current(d) => offset(self.pos, d)?,
+            };
+            return self.skip_to(target);
+        }
+
+        // Building the index moves `file` under the stream, so the first
+        // seek switches to blocks whatever its target
+        let switched = matches!(self.mode, Mode::Stream(_));
+        if switched {
+            let index = self.format.index(&mut self.file)?;
+            self.mode = Mode::Blocks(Blocks {
+                index,
+                next: 0,
+                file_pos: None,
+                compressed: Vec::new(),
+                data: Vec::new(),
+                consumed: 0,
+            });
+        }
+        let blocks = match &mut self.mode {
+            Mode::Blocks(blocks) => blocks,
+            Mode::Stream(_) => unreachable!("a seekable file switches to blocks above"),
+        };
+        let target = match pos {
+            SeekFrom::End(d) => offset(blocks.index.size, d)?,
+            SeekFrom::Start(n) => n,
+            SeekFrom::Current(d) => offset(self.pos, d)?,
+        };
+        if target == self.pos && !switched {
+            return Ok(target);
+        }
+
+        // The block holding `target`; past the end, nothing is left to read
+        let i = blocks
+            .index
+            .blocks
+            .partition_point(|b| b.start <= target)
+            .saturating_sub(1);
+        if target >= blocks.index.size {
+            blocks.next = blocks.index.blocks.len();
+            blocks.data.clear();
+            blocks.consumed = 0;
+        } else {
+            // Unless it is the block already decompressed
+            if blocks.next != i + 1 || blocks.data.is_empty() {
+                blocks.load(i, &mut self.file, &self.format)?;
+            }
+            blocks.consumed = (target - blocks.index.blocks[i].start) as usize;
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+// `base` moved by `delta`, or an error if that is before the start
+fn offset(base: u64, delta: i64) -> io::Result<u64> {
+    base.checked_add_signed(delta)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position"))
+}
@@ -0,0 +1,155 @@
+// Where cell text stops being a valid number, for the errors raised when a
+// typed conversion (decimal_columns, read_column_array) fails. Only the
+// failure path comes here: a cell that converts never pays for it.
+//
+// The position is found by walking the text with the grammar the converter
+// accepts, so it is where a reader would look, not necessarily where the
+// converter itself gave up: for "12,34 EUR" that is the comma.
+
+use pyo3::prelude::*;
+
+// Characters of the cell shown on each side of the stop position
+const SNIPPET_CONTEXT: usize = 20;
+
+#[derive(Clone, Copy)]
+pub enum Converter {
+    // decimal.Decimal, which also takes "_" between digits
+    Decimal,
+    // Rust's f64 parser
+    Float,
+    // Rust's i64 parser; a value past i64's range stops at the digit that
+    // overflows
+    Int,
+}
+
+impl Converter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Converter::Decimal => "decimal",
+            Converter::Float => "float",
+            Converter::Int => "int",
+        }
+    }
+}
+
+pub struct ConversionError {
+    pub converter: Converter,
+    // Character index in the cell where parsing stopped
+    pub stopped_at: usize,
+    // The cell (cut to SNIPPET_CONTEXT characters around the stop, with
+    // "..." marking cuts) and a caret line under the stop position
+    pub snippet: String,
+}
+
+impl ConversionError {
+    pub fn new(converter: Converter, field: &str) -> Self {
+        let chars: Vec<char> = field.chars().collect();
+        let stopped_at = match converter {
+            Converter::Int => int_prefix(&chars),
+            Converter::Float => float_prefix(&chars, false),
+            Converter::Decimal => float_prefix(&chars, true),
+        };
+        ConversionError {
+            converter,
+            stopped_at,
+            snippet: snippet(&chars, stopped_at),
+        }
+    }
+
+    // Set `converter`, `parse_stopped_at` and `value_snippet` on `err`
+    pub fn attach(&self, py: Python, err: &PyErr) -> PyResult<()> {
+        let value = err.value(py);
+        value.setattr("converter", self.converter.name())?;
+        value.setattr("parse_stopped_at", self.stopped_at)?;
+        value.setattr("value_snippet", &self.snippet)?;
+        Ok(())
+    }
+}
+
+// Length of the longest prefix of `chars` that is a valid start of an i64
+fn int_prefix(chars: &[char]) -> usize {
+    let mut i = usize::from(matches!(chars.first(), Some('+' | '-')));
+    let negative = chars.first() == Some(&'-');
+    let mut value: i64 = 0;
+    while let Some(digit) = chars.get(i).and_then(|c| c.to_digit(10)) {
+        let next = value.checked_mul(10).and_then(|v| {
+            if negative {
+                v.checked_sub(digit as i64)
+            } else {
+                v.checked_add(digit as i64)
+            }
+        });
+        match next {
+            Some(v) => value = v,
+            None => return i,
+        }
+        i += 1;
+    }
+    i
+}
+
+// Length of the longest prefix of `chars` that is a valid start of a
+// float or decimal: sign, digits, point, digits, exponent, or one of the
+// special values
+fn float_prefix(chars: &[char], underscores: bool) -> usize {
+    let mut i = usize::from(matches!(chars.first(), Some('+' | '-')));
+
+    let rest: String = chars[i..].iter().collect::<String>().to_ascii_lowercase();
+    for special in ["infinity", "inf", "nan", "snan"] {
+        if underscores || special != "snan" {
+            let matched = rest
+                .chars()
+                .zip(special.chars())
+                .take_while(|(a, b)| a == b);
+            let n = matched.count();
+            if n > 0 && (n == special.len() || n == rest.len()) {
+                return i + n;
+            }
+        }
+    }
+
+    let digits = |i: &mut usize| {
+        while let Some(&c) = chars.get(*i) {
+            let separator = underscores
+                && c == '_'
+                && *i > 0
+                && chars[*i - 1].is_ascii_digit()
+                && chars.get(*i + 1).is_some_and(|c| c.is_ascii_digit());
+            if !c.is_ascii_digit() && !separator {
+                break;
+            }
+            *i += 1;
+        }
+    };
+    digits(&mut i);
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        digits(&mut i);
+    }
+    if matches!(chars.get(i), Some('e' | 'E')) {
+        let mut j = i + 1;
+        if matches!(chars.get(j), Some('+' | '-')) {
+            j += 1;
+        }
+        if chars.get(j).is_some_and(|c| c.is_ascii_digit()) {
+            i = j;
+            digits(&mut i);
+        }
+    }
+    i
+}
+
+fn snippet(chars: &[char], stopped_at: usize) -> String {
+    let start = stopped_at.saturating_sub(SNIPPET_CONTEXT);
+    let end = (stopped_at + SNIPPET_CONTEXT).min(chars.len());
+    let mut text: String = chars[start..end].iter().collect();
+    let mut caret = stopped_at - start;
+    if start > 0 {
+        text.insert_str(0, "...");
+        caret += 3;
+    }
+    if end < chars.len() {
+        text.push_str("...");
+    }
+    format!("{}\n{}^", text, " ".repeat(caret))
+}
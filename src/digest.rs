@@ -0,0 +1,133 @@
+// A merging t-digest (Dunning & Ertl): a sorted list of centroids, small
+// near the tails and large in the middle, that estimates any quantile of
+// the values merged into it in bounded memory. quantile()'s approximate
+// mode folds values in a buffer at a time.
+
+// Centroids kept, roughly: more is more accurate and slower
+const COMPRESSION: f64 = 200.0;
+
+#[derive(Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+#[derive(Default)]
+pub struct TDigest {
+    // Sorted by mean
+    centroids: Vec<Centroid>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    // Merge `values`, in any order, into the digest
+    pub fn merge_unsorted(&mut self, mut values: Vec<f64>) {
+        if values.is_empty() {
+            return;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        if self.count == 0.0 {
+            self.min = values[0];
+            self.max = values[values.len() - 1];
+        } else {
+            self.min = self.min.min(values[0]);
+            self.max = self.max.max(values[values.len() - 1]);
+        }
+
+        // All points in mean order, then greedily combined while each
+        // centroid stays within the size its quantile allows (the k1 scale)
+        let mut points: Vec<Centroid> = Vec::with_capacity(self.centroids.len() + values.len());
+        let mut old = self.centroids.iter().peekable();
+        for value in values {
+            while let Some(c) = old.next_if(|c| c.mean <= value) {
+                points.push(*c);
+            }
+            points.push(Centroid {
+                mean: value,
+                weight: 1.0,
+            });
+        }
+        points.extend(old);
+
+        let total: f64 = points.iter().map(|c| c.weight).sum();
+        let mut merged: Vec<Centroid> = Vec::new();
+        let mut before = 0.0;
+        let mut limit = total * k_inverse(k(0.0) + 1.0);
+        for point in points {
+            match merged.last_mut() {
+                Some(last) if before + last.weight + point.weight <= limit => {
+                    let weight = last.weight + point.weight;
+                    last.mean += (point.mean - last.mean) * point.weight / weight;
+                    last.weight = weight;
+                }
+                Some(last) => {
+                    before += last.weight;
+                    limit = total * k_inverse(k(before / total) + 1.0);
+                    merged.push(point);
+                }
+                None => merged.push(point),
+            }
+        }
+        self.centroids = merged;
+        self.count = total;
+    }
+
+    // Estimate of the q-th quantile (0 <= q <= 1); 0.0 for an empty digest
+    pub fn estimate_quantile(&self, q: f64) -> f64 {
+        let centroids = &self.centroids;
+        match centroids.len() {
+            0 => return 0.0,
+            1 => return centroids[0].mean,
+            _ => {}
+        }
+        let rank = q.clamp(0.0, 1.0) * self.count;
+        if rank <= 0.0 {
+            return self.min;
+        }
+        if rank >= self.count {
+            return self.max;
+        }
+
+        // Each centroid's mean sits at the middle of its weight; interpolate
+        // between neighbouring middles, and to min/max at the ends
+        let mut cumulative = 0.0;
+        for i in 0..centroids.len() {
+            let c = centroids[i];
+            let middle = cumulative + c.weight / 2.0;
+            if rank < middle {
+                let (left_mean, left_rank) = match i {
+                    0 => (self.min, 0.0),
+                    _ => (
+                        centroids[i - 1].mean,
+                        cumulative - centroids[i - 1].weight / 2.0,
+                    ),
+                };
+                return interpolate(left_mean, left_rank, c.mean, middle, rank);
+            }
+            cumulative += c.weight;
+        }
+        let last = centroids[centroids.len() - 1];
+        let middle = self.count - last.weight / 2.0;
+        interpolate(last.mean, middle, self.max, self.count, rank)
+    }
+}
+
+fn interpolate(a: f64, a_rank: f64, b: f64, b_rank: f64, rank: f64) -> f64 {
+    if b_rank <= a_rank {
+        return a;
+    }
+    a + (b - a) * (rank - a_rank) / (b_rank - a_rank)
+}
+
+// The k1 scale function and its inverse, mapping a quantile to the index
+// space in which every centroid may span at most 1
+fn k(q: f64) -> f64 {
+    COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin()
+}
+
+fn k_inverse(k: f64) -> f64 {
+    let q = ((k * 2.0 * std::f64::consts::PI / COMPRESSION).sin() + 1.0) / 2.0;
+    q.clamp(0.0, 1.0)
+}
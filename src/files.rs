@@ -0,0 +1,937 @@
+// Opening files by path. Everything that opens a file goes through here so
+// the Windows specifics live in one place:
+// - paths of MAX_PATH (260) characters or more get the \\?\ prefix;
+// - input files are opened with full read/write/delete sharing, so a file
+//   another program (e.g. Excel) has open can still be read as long as that
+//   program allows it;
+// - the common Windows error codes get a hint about what to do.
+// On other platforms these are no-ops.
+//
+// FileWatch notices a file being truncated or replaced while a streaming
+// read has it open, e.g. by log rotation. Input caps how fast a file is
+// read, for max_bytes_per_sec, stops at a line longer than max_line_bytes,
+// hashes what it reads for content_hash, drops fields past max_columns,
+// notes the "" fields distinguish_empty_quoted asks about, times its reads
+// for timings, and is also how a CSV inside a ZIP archive or a compressed
+// file is read. SnapshotCopy and lock_shared are the two ways
+// snapshot= keeps other writers from tearing a read.
+
+use pyo3::{PyErr, Python};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::scanner::{FieldCutter, QuoteTracker};
+
+// Most a throttled Input reads at once, so the rate holds over short
+// spans too, not just on average
+const THROTTLE_CHUNK: usize = 64 * 1024;
+
+// How far past a record's end an Input keeps what QuoteTracker found in
+// it. A csv reader is never further behind its input than its own buffer
+// and the BufReader's, a small fraction of this, so only records already
+// read, or skipped by a scan that doesn't ask, are let go.
+const QUOTED_EMPTY_WINDOW: u64 = 1 << 20;
+
+// Copies SnapshotCopy makes before giving up on a file that keeps changing
+const SNAPSHOT_ATTEMPTS: usize = 3;
+
+// Endings of the names of snapshot copies and their owners' lock files in
+// the temp directory, which only this crate's files have, so a sweep
+// never takes anything else there for one
+const SNAPSHOT_SUFFIX: &str = ".csv_reader-snapshot";
+const SNAPSHOT_LOCK_SUFFIX: &str = ".csv_reader-lock";
+
+// Numbers this process's snapshot copies, so two parsers never share one
+static SNAPSHOTS: AtomicU64 = AtomicU64::new(0);
+
+// This process's live snapshot copies, and the lock file it holds while
+// it has any (see claim_snapshots)
+static SNAPSHOT_OWNER: Mutex<(usize, Option<File>)> = Mutex::new((0, None));
+
+thread_local! {
+    // Set while this thread runs a without_gil() closure
+    static WITHOUT_GIL: Cell<bool> = const { Cell::new(false) };
+}
+
+// py.allow_threads(f), for the scans that read files without the GIL. A
+// throttled Input read inside `f` then sleeps as it is, instead of taking
+// the GIL only to release it again.
+pub fn without_gil<T: Send, F: Send + FnOnce() -> T>(py: Python, f: F) -> T {
+    // Restores the flag even if `f` panics
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            WITHOUT_GIL.with(|flag| flag.set(self.0));
+        }
+    }
+    py.allow_threads(|| {
+        let _reset = Reset(WITHOUT_GIL.with(|flag| flag.replace(true)));
+        f()
+    })
+}
+
+pub fn open(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+        options.share_mode(0x1 | 0x2 | 0x4);
+    }
+    options.open(long_path(path))
+}
+
+pub fn create(path: &Path) -> io::Result<File> {
+    File::create(long_path(path))
+}
+
+pub fn metadata(path: &Path) -> io::Result<Metadata> {
+    std::fs::metadata(long_path(path))
+}
+
+// Name of the compression format `file` starts with, going by its magic
+// bytes, or None for anything else. Reads from the current position.
+pub fn compression(file: &mut File) -> Option<&'static str> {
+    let mut head = Vec::with_capacity(10);
+    file.take(10).read_to_end(&mut head).ok()?;
+    match head.as_slice() {
+        [0x1f, 0x8b, ..] => Some("gzip"),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some("zstd"),
+        // "BZh" alone is plausible CSV text, so also require the block size
+        // digit and the first block's magic number
+        [b'B', b'Z', b'h', b'1'..=b'9', 0x31, 0x41, 0x59, 0x26, 0x53, 0x59] => Some("bzip2"),
+        _ => None,
+    }
+}
+
+// A file being read, at full speed or at no more than a set number of
+// bytes per second
+pub struct Input {
+    source: Source,
+    throttle: Option<Throttle>,
+    line_limit: Option<LineLimit>,
+    digest: Option<Digest>,
+    // max_columns, applied after the digest so content_hash still sees
+    // the file's bytes
+    cutter: Option<FieldCutter>,
+    // distinguish_empty_quoted, applied after max_columns, to the bytes
+    // the csv reader sees
+    quotes: Option<Quotes>,
+    lines: Option<LineCount>,
+    // Nanoseconds spent in read(), added to a total shared with the parser
+    read_time: Option<Arc<AtomicU64>>,
+}
+
+enum Source {
+    File(File),
+    #[cfg(feature = "zip")]
+    Zip(crate::archive::EntryReader),
+    #[cfg(any(feature = "bgzf", feature = "zstd"))]
+    Compressed(crate::compressed::Reader),
+}
+
+struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    bytes: u64,
+}
+
+// Where the current line started, to fail a read once it runs past `max`
+// bytes without a line break (\r or \n) instead of buffering it whole
+struct LineLimit {
+    max: u64,
+    // Offset of the next byte to be read
+    pos: u64,
+    line_start: u64,
+}
+
+// xxHash64 (seed 0) of the bytes read so far. When a read reaches the end
+// of the input having read every byte from the start, in order, the hash
+// is stored in `result`. A seek anywhere but the next byte to hash rules
+// that out for this Input.
+struct Digest {
+    hasher: Xxh64,
+    // Offset of the next byte to be read
+    pos: u64,
+    whole: bool,
+    result: Arc<Mutex<Option<u64>>>,
+}
+
+// Line breaks read, for chunk_unit="lines": \n, \r\n and a lone \r each
+// end a line, as they end records for the csv reader. The reader reads
+// ahead of the records it hands out, so breaks past the last position
+// asked about are kept by offset until it gets there.
+struct LineCount {
+    // Offset of the next byte to be read
+    pos: u64,
+    // The last byte read was \r, so a \n next is part of its break
+    after_cr: bool,
+    // Breaks before the last position asked about
+    counted: u64,
+    // Offsets just past the breaks after it, in order. That is past the
+    // \r of a \r\n, where the csv reader puts the next record's start.
+    ahead: VecDeque<u64>,
+}
+
+// The "" fields of the records read and not yet let go, by record end
+// offset, in file order
+pub type QuotedEmpty = Arc<Mutex<VecDeque<(u64, Vec<usize>)>>>;
+
+struct Quotes {
+    tracker: QuoteTracker,
+    found: QuotedEmpty,
+}
+
+// Where a csv reader finds what its input's QuoteTracker found: an Input,
+// read directly or through a BufReader, or a file read into memory
+pub trait QuoteSource {
+    fn quoted_empty(&self) -> Option<&QuotedEmpty>;
+}
+
+impl QuoteSource for Input {
+    fn quoted_empty(&self) -> Option<&QuotedEmpty> {
+        self.quotes.as_ref().map(|q| &q.found)
+    }
+}
+
+impl<R: QuoteSource> QuoteSource for BufReader<R> {
+    fn quoted_empty(&self) -> Option<&QuotedEmpty> {
+        self.get_ref().quoted_empty()
+    }
+}
+
+// Input's bytes read into memory, with the "" fields found in them
+pub struct InMemory<'a> {
+    bytes: &'a [u8],
+    quoted_empty: Option<QuotedEmpty>,
+}
+
+impl<'a> InMemory<'a> {
+    pub fn new(bytes: &'a [u8], tracker: Option<QuoteTracker>) -> Self {
+        let quoted_empty = tracker.map(|mut tracker| {
+            let mut found = VecDeque::new();
+            tracker.feed(bytes, |end, fields| found.push_back((end, fields)));
+            tracker.finish(|end, fields| found.push_back((end, fields)));
+            Arc::new(Mutex::new(found))
+        });
+        InMemory {
+            bytes,
+            quoted_empty,
+        }
+    }
+}
+
+impl Read for InMemory<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.bytes.read(buf)
+    }
+}
+
+impl QuoteSource for InMemory<'_> {
+    fn quoted_empty(&self) -> Option<&QuotedEmpty> {
+        self.quoted_empty.as_ref()
+    }
+}
+
+// The error an Input's read returns for a line over max_line_bytes.
+// io_error turns it into a ValueError: it's the data that is wrong.
+#[derive(Debug)]
+pub struct LineTooLong {
+    limit: u64,
+    offset: u64,
+}
+
+impl Input {
+    pub fn new(file: File, max_bytes_per_sec: Option<u64>) -> Self {
+        Self::with_source(Source::File(file), max_bytes_per_sec)
+    }
+
+    #[cfg(feature = "zip")]
+    pub fn zip(entry: crate::archive::EntryReader, max_bytes_per_sec: Option<u64>) -> Self {
+        Self::with_source(Source::Zip(entry), max_bytes_per_sec)
+    }
+
+    #[cfg(any(feature = "bgzf", feature = "zstd"))]
+    pub fn compressed(reader: crate::compressed::Reader, max_bytes_per_sec: Option<u64>) -> Self {
+        Self::with_source(Source::Compressed(reader), max_bytes_per_sec)
+    }
+
+    fn with_source(source: Source, max_bytes_per_sec: Option<u64>) -> Self {
+        Input {
+            source,
+            throttle: max_bytes_per_sec.map(|bytes_per_sec| Throttle {
+                bytes_per_sec,
+                started: Instant::now(),
+                bytes: 0,
+            }),
+            line_limit: None,
+            digest: None,
+            cutter: None,
+            quotes: None,
+            lines: None,
+            read_time: None,
+        }
+    }
+
+    // Hash everything read, storing the hash of a complete read in
+    // `result` (see Digest)
+    pub fn digest(mut self, result: Option<Arc<Mutex<Option<u64>>>>) -> Self {
+        self.digest = result.map(|result| Digest {
+            hasher: Xxh64::new(0),
+            pos: 0,
+            whole: true,
+            result,
+        });
+        self
+    }
+
+    // Add the time spent reading, throttling included, to `total`
+    pub fn timer(mut self, total: Option<Arc<AtomicU64>>) -> Self {
+        self.read_time = total;
+        self
+    }
+
+    // Fail reads once a line runs past `max` bytes (see LineLimit)
+    pub fn max_line_bytes(mut self, max: Option<u64>) -> Self {
+        self.line_limit = max.map(|max| LineLimit {
+            max,
+            pos: 0,
+            line_start: 0,
+        });
+        self
+    }
+
+    // Drop every record's fields past the first few (see FieldCutter)
+    pub fn max_columns(mut self, cutter: Option<FieldCutter>) -> Self {
+        self.cutter = cutter;
+        self
+    }
+
+    // Note which fields of each record are "" (see QuoteTracker)
+    pub fn quoted_empty(mut self, tracker: Option<QuoteTracker>) -> Self {
+        self.quotes = tracker.map(|tracker| Quotes {
+            tracker,
+            found: Arc::new(Mutex::new(VecDeque::new())),
+        });
+        self
+    }
+
+    // Count line breaks, for lines_before()
+    pub fn count_lines(mut self) -> Self {
+        self.lines = Some(LineCount {
+            pos: 0,
+            after_cr: false,
+            counted: 0,
+            ahead: VecDeque::new(),
+        });
+        self
+    }
+
+    // Lines ended before offset `pos`, counted from the start of the file,
+    // or from where the last seek landed. Positions asked about must not
+    // go backwards. 0 without count_lines().
+    pub fn lines_before(&mut self, pos: u64) -> u64 {
+        let lines = match &mut self.lines {
+            Some(lines) => lines,
+            None => return 0,
+        };
+        while lines.ahead.front().is_some_and(|&end| end <= pos) {
+            lines.ahead.pop_front();
+            lines.counted += 1;
+        }
+        lines.counted
+    }
+
+    // A watch on the file as it is now. An archive entry or compressed file
+    // isn't watched: offsets into its data say nothing about its size.
+    pub fn watch(&self) -> FileWatch {
+        match &self.source {
+            Source::File(file) => FileWatch::new(file),
+            #[cfg(any(feature = "zip", feature = "bgzf", feature = "zstd"))]
+            _ => FileWatch {
+                size: 0,
+                identity: None,
+                modified: None,
+                active: false,
+            },
+        }
+    }
+}
+
+impl Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            #[cfg(feature = "zip")]
+            Source::Zip(entry) => entry.read(buf),
+            #[cfg(any(feature = "bgzf", feature = "zstd"))]
+            Source::Compressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = self.read_time.as_ref().map(|_| Instant::now());
+        let n = self.read_limited(buf)?;
+        if let (Some(total), Some(started)) = (&self.read_time, started) {
+            total.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+        if let Some(digest) = &mut self.digest {
+            if n > 0 {
+                digest.hasher.update(&buf[..n]);
+                digest.pos += n as u64;
+            } else if !buf.is_empty() && digest.whole {
+                *digest.result.lock().unwrap() = Some(digest.hasher.digest());
+            }
+        }
+        if let Some(cutter) = &mut self.cutter {
+            cutter.cut(&mut buf[..n]);
+        }
+        if let Some(quotes) = &mut self.quotes {
+            let mut found = quotes.found.lock().unwrap();
+            let mut add = |end: u64, fields: Vec<usize>| found.push_back((end, fields));
+            if n > 0 {
+                quotes.tracker.feed(&buf[..n], &mut add);
+            } else if !buf.is_empty() {
+                quotes.tracker.finish(&mut add);
+            }
+            while found
+                .front()
+                .is_some_and(|(end, _)| end + QUOTED_EMPTY_WINDOW < found.back().unwrap().0)
+            {
+                found.pop_front();
+            }
+        }
+        if let Some(lines) = &mut self.lines {
+            lines.feed(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl LineCount {
+    fn feed(&mut self, buf: &[u8]) {
+        for i in memchr::memchr2_iter(b'\r', b'\n', buf) {
+            let after_cr = match i {
+                0 => self.after_cr,
+                _ => buf[i - 1] == b'\r',
+            };
+            if buf[i] == b'\r' || !after_cr {
+                self.ahead.push_back(self.pos + i as u64 + 1);
+            }
+        }
+        if let Some(&last) = buf.last() {
+            self.after_cr = last == b'\r';
+        }
+        self.pos += buf.len() as u64;
+    }
+}
+
+impl Input {
+    fn read_limited(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = match &mut self.line_limit {
+            Some(limit) => limit,
+            None => return self.read_throttled(buf),
+        };
+        // Small reads, so a read_to_end into a buffer sized for the whole
+        // file stops after about `max` bytes of an endless line
+        let len = buf
+            .len()
+            .min(limit.max.saturating_add(1).max(THROTTLE_CHUNK as u64) as usize);
+        let n = self.read_throttled(&mut buf[..len])?;
+        if let Some(limit) = &mut self.line_limit {
+            limit.check(&buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn read_throttled(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let throttle = match &mut self.throttle {
+            Some(t) => t,
+            None => return self.source.read(buf),
+        };
+        let len = buf.len().min(THROTTLE_CHUNK);
+        let n = self.source.read(&mut buf[..len])?;
+
+        // Sleep until the bytes read so far fit the rate, without the GIL
+        // so other Python threads keep running. Inside without_gil() it is
+        // already released; otherwise this thread holds it, and with_gil
+        // only borrows it to release it.
+        throttle.bytes += n as u64;
+        let due = Duration::from_secs_f64(throttle.bytes as f64 / throttle.bytes_per_sec as f64);
+        if let Some(wait) = due.checked_sub(throttle.started.elapsed()) {
+            if WITHOUT_GIL.with(Cell::get) {
+                std::thread::sleep(wait);
+            } else {
+                Python::with_gil(|py| py.allow_threads(|| std::thread::sleep(wait)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(pos),
+            #[cfg(feature = "zip")]
+            Source::Zip(entry) => entry.seek(pos),
+            #[cfg(any(feature = "bgzf", feature = "zstd"))]
+            Source::Compressed(reader) => reader.seek(pos),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // A record after a cut one has its position right after the
+        // delimiter the cut started at; a record start follows a line
+        // ending. The byte before the target is read on the way there, so
+        // a source that is slow to seek back (a ZIP entry, plain gzip)
+        // doesn't have to.
+        let pos = match (&mut self.cutter, pos) {
+            (Some(cutter), SeekFrom::Start(target)) if target > 0 => {
+                let mut before = [0u8];
+                self.source.seek(SeekFrom::Start(target - 1))?;
+                let n = self.source.read(&mut before)?;
+                cutter.restart(n == 1 && before[0] == cutter.delimiter());
+                if n == 1 {
+                    target
+                } else {
+                    self.source.seek(SeekFrom::Start(target))?
+                }
+            }
+            (Some(cutter), SeekFrom::Start(0)) => {
+                cutter.restart(false);
+                self.source.seek(SeekFrom::Start(0))?
+            }
+            (Some(_), pos) => {
+                let target = self.source.seek(pos)?;
+                return self.seek(SeekFrom::Start(target));
+            }
+            (None, pos) => self.source.seek(pos)?,
+        };
+        // Seeks land on record starts, so a new line starts there
+        if let Some(limit) = &mut self.line_limit {
+            limit.pos = pos;
+            limit.line_start = pos;
+        }
+        if let Some(digest) = &mut self.digest {
+            digest.whole &= pos == digest.pos;
+        }
+        if let Some(quotes) = &mut self.quotes {
+            quotes.tracker.restart(pos);
+            quotes.found.lock().unwrap().clear();
+        }
+        if let Some(lines) = &mut self.lines {
+            *lines = LineCount {
+                pos,
+                after_cr: false,
+                counted: 0,
+                ahead: VecDeque::new(),
+            };
+        }
+        Ok(pos)
+    }
+}
+
+impl LineLimit {
+    // Account for `bytes`, just read, and fail if a line in them is too long
+    fn check(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let start = self.pos;
+        self.pos += bytes.len() as u64;
+        let is_break = |b: &u8| *b == b'\n' || *b == b'\r';
+
+        // A line that both starts and ends in `bytes` can only be too long
+        // if `bytes` is; otherwise only the lines running into and out of
+        // it are measured
+        if bytes.len() as u64 > self.max {
+            for (i, b) in bytes.iter().enumerate() {
+                if is_break(b) {
+                    self.line_ends(start + i as u64)?;
+                    self.line_start = start + i as u64 + 1;
+                }
+            }
+        } else if let Some(first) = bytes.iter().position(is_break) {
+            self.line_ends(start + first as u64)?;
+            let last = bytes.iter().rposition(is_break).unwrap_or(first);
+            self.line_start = start + last as u64 + 1;
+        }
+        self.line_ends(self.pos)
+    }
+
+    // Fail if the current line is too long by `end`
+    fn line_ends(&self, end: u64) -> io::Result<()> {
+        if end - self.line_start <= self.max {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            LineTooLong {
+                limit: self.max,
+                offset: self.line_start,
+            },
+        ))
+    }
+}
+
+impl fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The line at offset {} runs past max_line_bytes ({}) without a line break; \
+             the file may not be CSV, e.g. a JSON document on one line",
+            self.offset, self.limit
+        )
+    }
+}
+
+impl std::error::Error for LineTooLong {}
+
+// Size and identity of a file when a streaming read opened it
+pub struct FileWatch {
+    size: u64,
+    identity: Option<(u64, u64)>,
+    modified: Option<SystemTime>,
+    // False for a watch that never reports a change
+    active: bool,
+}
+
+pub enum FileChange {
+    // Now this many bytes, fewer than the read has reached or expected
+    Shrunk(u64),
+    // The path now names a different file, or none
+    Replaced,
+}
+
+impl FileWatch {
+    pub fn new(file: &File) -> Self {
+        let metadata = file.metadata().ok();
+        FileWatch {
+            size: metadata.as_ref().map_or(0, |m| m.len()),
+            identity: metadata.as_ref().and_then(identity),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            active: true,
+        }
+    }
+
+    // Whether the file at `path` is still exactly as when opened: same
+    // file, size and modification time. Stricter than check(), which lets
+    // a file grow; this is for reusing what was read from it.
+    pub fn unchanged(&self, path: &Path) -> bool {
+        if !self.active {
+            return true;
+        }
+        match metadata(path) {
+            Ok(m) => {
+                m.len() == self.size
+                    && identity(&m) == self.identity
+                    && m.modified().ok() == self.modified
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Compare the file at `path` with how it was when opened, for a read
+    // that has got `offset` bytes in. `at_eof` is set when the read just
+    // hit the end of the file, which is early if the file shrank.
+    pub fn check(&self, path: &Path, offset: u64, at_eof: bool) -> Option<FileChange> {
+        if !self.active {
+            return None;
+        }
+        let metadata = match metadata(path) {
+            Ok(m) => m,
+            Err(_) => return Some(FileChange::Replaced),
+        };
+        if self.identity.is_some() && identity(&metadata) != self.identity {
+            return Some(FileChange::Replaced);
+        }
+        let size = metadata.len();
+        if size < offset || (at_eof && offset < self.size && size < self.size) {
+            return Some(FileChange::Shrunk(size));
+        }
+        None
+    }
+}
+
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Windows' file index is not available from stable std; there a replaced
+// file is only noticed if it is also shorter
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// snapshot="copy": a private copy of a file in the temp directory, removed
+// when dropped. fs::copy clones the file where the filesystem can (APFS,
+// and Btrfs or XFS through copy_file_range) and streams it otherwise. The
+// copy is only kept if the source's size and modification time were the
+// same before and after it, so a save that lands mid-copy means another
+// try, not a torn snapshot.
+pub struct SnapshotCopy {
+    path: PathBuf,
+}
+
+impl SnapshotCopy {
+    pub fn create(source: &Path) -> io::Result<Self> {
+        sweep_snapshots();
+        claim_snapshots()?;
+        let name = source.file_name().unwrap_or_default().to_string_lossy();
+        let path = std::env::temp_dir().join(format!(
+            "csv_reader-{}-{}-{}{}",
+            std::process::id(),
+            SNAPSHOTS.fetch_add(1, Ordering::Relaxed),
+            name,
+            SNAPSHOT_SUFFIX
+        ));
+        // From here on, dropping `copy` removes whatever was written and
+        // lets go of the claim
+        let copy = SnapshotCopy { path };
+
+        for _ in 0..SNAPSHOT_ATTEMPTS {
+            let before = metadata(source)?;
+            let copied = std::fs::copy(long_path(source), long_path(&copy.path))?;
+            let after = metadata(source)?;
+            if copied == before.len()
+                && after.len() == before.len()
+                && after.modified().ok() == before.modified().ok()
+            {
+                return Ok(copy);
+            }
+        }
+        Err(io::Error::other(format!(
+            "the file kept changing while it was copied ({} attempts)",
+            SNAPSHOT_ATTEMPTS
+        )))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SnapshotCopy {
+    fn drop(&mut self) {
+        // A copy of a read-only file is read-only too, which Windows won't
+        // delete
+        #[cfg(windows)]
+        if let Ok(metadata) = metadata(&self.path) {
+            let mut permissions = metadata.permissions();
+            permissions.set_readonly(false);
+            let _ = std::fs::set_permissions(long_path(&self.path), permissions);
+        }
+        let _ = std::fs::remove_file(long_path(&self.path));
+        release_snapshots();
+    }
+}
+
+// A process that dies without dropping its SnapshotCopy values, e.g. by
+// crashing or being killed, leaves their files in the temp directory. So
+// that they can be told from a live process's, a process with copies
+// holds an exclusive lock on "csv_reader-<pid>.csv_reader-lock" there, and
+// every new copy first removes the copies of pids whose lock file isn't
+// locked.
+
+fn owner_lock_path(pid: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("csv_reader-{}{}", pid, SNAPSHOT_LOCK_SUFFIX))
+}
+
+// Count one more copy, locking this process's lock file for the first
+fn claim_snapshots() -> io::Result<()> {
+    let mut owner = SNAPSHOT_OWNER.lock().unwrap();
+    if owner.1.is_none() {
+        let path = owner_lock_path(std::process::id());
+        let file = loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(long_path(&path))?;
+            file.lock()?;
+            // A sweep may have removed the file, left by a dead process
+            // with this pid, between the open and the lock
+            let current = metadata(&path).ok();
+            let held = file.metadata().ok();
+            if current.is_some()
+                && current.as_ref().and_then(identity) == held.as_ref().and_then(identity)
+            {
+                break file;
+            }
+        };
+        owner.1 = Some(file);
+    }
+    owner.0 += 1;
+    Ok(())
+}
+
+// Count one copy less, unlocking and removing the lock file after the last
+fn release_snapshots() {
+    let mut owner = SNAPSHOT_OWNER.lock().unwrap();
+    owner.0 = owner.0.saturating_sub(1);
+    if owner.0 == 0 {
+        if let Some(file) = owner.1.take() {
+            let _ = std::fs::remove_file(long_path(&owner_lock_path(std::process::id())));
+            drop(file);
+        }
+    }
+}
+
+// Remove the copies, and lock files, of processes that ended without
+// removing them. Best effort: nothing here fails a copy.
+fn sweep_snapshots() {
+    let entries = match std::fs::read_dir(long_path(&std::env::temp_dir())) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    // Copies by the pid in their name, and pids with only a lock file
+    let mut copies: HashMap<u32, Vec<PathBuf>> = HashMap::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let rest = match name.to_str().and_then(|n| n.strip_prefix("csv_reader-")) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        // "<pid>-<n>-<name><SNAPSHOT_SUFFIX>" or "<pid><SNAPSHOT_LOCK_SUFFIX>"
+        let (pid, copy) = if let Some(rest) = rest.strip_suffix(SNAPSHOT_SUFFIX) {
+            match rest.split_once('-') {
+                Some((pid, _)) => (pid, true),
+                None => continue,
+            }
+        } else if let Some(pid) = rest.strip_suffix(SNAPSHOT_LOCK_SUFFIX) {
+            (pid, false)
+        } else {
+            continue;
+        };
+        match pid.parse::<u32>() {
+            Ok(pid) if pid != std::process::id() => {
+                let paths = copies.entry(pid).or_default();
+                if copy {
+                    paths.push(entry.path());
+                }
+            }
+            _ => {}
+        }
+    }
+    for (pid, paths) in copies {
+        let lock_path = owner_lock_path(pid);
+        // Held until the copies and the lock file are gone, so a new
+        // process with the same pid waits for the sweep to finish
+        let lock = match OpenOptions::new().write(true).open(long_path(&lock_path)) {
+            Ok(file) => match file.try_lock() {
+                Ok(()) => Some(file),
+                Err(_) => continue,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(_) => continue,
+        };
+        for path in paths {
+            let _ = std::fs::remove_file(long_path(&path));
+        }
+        if lock.is_some() {
+            let _ = std::fs::remove_file(long_path(&lock_path));
+        }
+    }
+}
+
+// snapshot="lock": take a shared lock on `file`, held until it is closed,
+// or fail at once if a writer holds one. On Windows this keeps every other
+// program from writing into the file; elsewhere the lock is advisory
+// (flock), so it only holds off writers that lock the file too. Nowhere
+// does it stop a save that renames a new file over this one, as Excel's
+// does.
+pub fn lock_shared(file: &File) -> io::Result<()> {
+    match file.try_lock_shared() {
+        Ok(()) => Ok(()),
+        Err(std::fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "another program holds a lock on the file",
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+// PyIOError for a failed file operation, e.g. "Failed to open file: ...",
+// FileNotFoundError (a subclass) when the file doesn't exist, or ValueError
+// for a read that hit max_line_bytes
+pub fn io_error(action: &str, e: io::Error) -> PyErr {
+    if let Some(err) = line_too_long(&e) {
+        return err;
+    }
+    let message = format!("Failed to {}: {}{}", action, e, hint(&e));
+    if e.kind() == io::ErrorKind::NotFound {
+        return PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(message);
+    }
+    PyErr::new::<pyo3::exceptions::PyIOError, _>(message)
+}
+
+// ValueError with the offset of the line as `offset`, if `e` is a
+// LineTooLong from an Input
+pub fn line_too_long(e: &io::Error) -> Option<PyErr> {
+    let long = e.get_ref()?.downcast_ref::<LineTooLong>()?;
+    let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(long.to_string());
+    Python::with_gil(|py| {
+        let _ = err.value(py).setattr("offset", long.offset);
+    });
+    Some(err)
+}
+
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    const MAX_PATH: usize = 260;
+
+    let raw = path.as_os_str();
+    if raw.len() < MAX_PATH || raw.to_string_lossy().starts_with(r"\\?\") {
+        return Cow::Borrowed(path);
+    }
+    // The prefix turns off path normalization, so the path must be absolute
+    let absolute = match std::path::absolute(path) {
+        Ok(p) => p,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    let absolute = absolute.to_string_lossy();
+    let verbatim = match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", absolute),
+    };
+    Cow::Owned(verbatim.into())
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+#[cfg(windows)]
+fn hint(e: &io::Error) -> &'static str {
+    match e.raw_os_error() {
+        // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+        Some(32) | Some(33) => " (the file is open in another program; close it and try again)",
+        // ERROR_ACCESS_DENIED
+        Some(5) => " (access denied; check the file's permissions, or whether it is open in another program)",
+        // ERROR_FILENAME_EXCED_RANGE
+        Some(206) => " (the path is too long)",
+        _ => "",
+    }
+}
+
+#[cfg(not(windows))]
+fn hint(_e: &io::Error) -> &'static str {
+    ""
+}
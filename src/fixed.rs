@@ -0,0 +1,143 @@
+// CSVParser.to_fixed_width(): write a CSV file out as fixed-width text for
+// systems that only take that. Works on field text only, so the whole
+// conversion runs without the GIL, one record at a time.
+
+use crate::cancel::Cancel;
+use csv::StringRecord;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+// What a cell longer than its column's width does
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overflow {
+    // Keep the cell's first `width` characters
+    Truncate,
+    // Stop with ExportError::TooLong
+    Error,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truncate" => Some(Overflow::Truncate),
+            "error" => Some(Overflow::Error),
+            _ => None,
+        }
+    }
+}
+
+pub enum ExportError {
+    Csv(csv::Error),
+    Io(std::io::Error),
+    // A cell longer than its column with overflow="error": data row (0
+    // based, None for the header), column index and the cell text
+    TooLong {
+        row: Option<usize>,
+        column: usize,
+        value: String,
+        position: Option<csv::Position>,
+    },
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        ExportError::Csv(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+pub struct Layout<'a> {
+    // Characters per column, one per header field
+    pub widths: &'a [usize],
+    pub overflow: Overflow,
+    // Right-align cells whose trimmed text parses as a number
+    pub right_align_numbers: bool,
+}
+
+// Write `header` (if given) and every remaining record of `reader`, up to
+// `max_rows`, to `path`, one "\n"-terminated line each. Line breaks in a
+// cell become spaces, so a record never spans lines. Each cell is padded
+// with spaces to its column's width, counted in characters; a row shorter
+// than the header gets blank columns and fields past the header are
+// dropped. Returns the number of data rows written. Stops early once
+// `cancel` is set.
+pub fn write_file<R: Read>(
+    reader: &mut csv::Reader<R>,
+    header: Option<&StringRecord>,
+    layout: &Layout,
+    path: &str,
+    max_rows: usize,
+    cancel: &Cancel,
+) -> Result<usize, ExportError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut line = String::new();
+
+    if let Some(header) = header {
+        write_line(&mut out, &mut line, header, layout, None)?;
+    }
+
+    let mut record = StringRecord::new();
+    let mut rows = 0;
+    while rows < max_rows && !cancel.check(rows) && reader.read_record(&mut record)? {
+        write_line(&mut out, &mut line, &record, layout, Some(rows))?;
+        rows += 1;
+    }
+
+    out.flush()?;
+    Ok(rows)
+}
+
+fn write_line(
+    out: &mut impl Write,
+    line: &mut String,
+    record: &StringRecord,
+    layout: &Layout,
+    row: Option<usize>,
+) -> Result<(), ExportError> {
+    line.clear();
+    for (i, &width) in layout.widths.iter().enumerate() {
+        let field = one_line(record.get(i).unwrap_or(""));
+        let field = field.as_ref();
+        let len = field.chars().count();
+        if len > width {
+            if layout.overflow == Overflow::Error {
+                return Err(ExportError::TooLong {
+                    row,
+                    column: i,
+                    value: field.to_string(),
+                    position: record.position().cloned(),
+                });
+            }
+            line.extend(field.chars().take(width));
+            continue;
+        }
+
+        let padding = width - len;
+        let numeric =
+            layout.right_align_numbers && row.is_some() && field.trim().parse::<f64>().is_ok();
+        if numeric {
+            line.extend(std::iter::repeat_n(' ', padding));
+            line.push_str(field);
+        } else {
+            line.push_str(field);
+            line.extend(std::iter::repeat_n(' ', padding));
+        }
+    }
+    line.push('\n');
+    out.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// `field` with each line break ("\r\n", "\r" or "\n") replaced by a space
+fn one_line(field: &str) -> Cow<'_, str> {
+    if !field.contains(['\r', '\n']) {
+        return Cow::Borrowed(field);
+    }
+    Cow::Owned(field.replace("\r\n", " ").replace(['\r', '\n'], " "))
+}
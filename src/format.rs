@@ -0,0 +1,81 @@
+// How typed Python values become cell text. Everything that writes Python
+// values out goes through ValueFormat, so a value is always written the
+// same way for the same options. The defaults round-trip: strings as-is,
+// None as an empty cell, bool as "true"/"false", int as its digits, float
+// as its shortest round-tripping repr and dates and datetimes with
+// isoformat(). Anything else, including Decimal, uses str(), which for
+// Decimal is exact.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyFloat, PyLong, PyString};
+
+pub struct ValueFormat {
+    // format() spec for floats, e.g. ".2f"; None uses repr
+    float_format: Option<String>,
+    // strftime() pattern for dates, datetimes and times; None uses
+    // isoformat()
+    datetime_format: Option<String>,
+    // Text for True and for False
+    bool_format: (String, String),
+    // Text for None
+    null_format: String,
+}
+
+impl Default for ValueFormat {
+    fn default() -> Self {
+        ValueFormat {
+            float_format: None,
+            datetime_format: None,
+            bool_format: ("true".to_string(), "false".to_string()),
+            null_format: String::new(),
+        }
+    }
+}
+
+impl ValueFormat {
+    pub fn new(
+        float_format: Option<String>,
+        datetime_format: Option<String>,
+        bool_format: Option<(String, String)>,
+        null_format: Option<String>,
+    ) -> Self {
+        let default = ValueFormat::default();
+        ValueFormat {
+            float_format,
+            datetime_format,
+            bool_format: bool_format.unwrap_or(default.bool_format),
+            null_format: null_format.unwrap_or(default.null_format),
+        }
+    }
+
+    // Text for one cell
+    pub fn format(&self, value: &PyAny) -> PyResult<String> {
+        if value.is_none() {
+            return Ok(self.null_format.clone());
+        }
+        if let Ok(s) = value.downcast::<PyString>() {
+            return Ok(s.to_str()?.to_string());
+        }
+        // Checked before int, since bool is an int subclass
+        if let Ok(b) = value.downcast::<PyBool>() {
+            let (yes, no) = &self.bool_format;
+            return Ok(if b.is_true() { yes } else { no }.clone());
+        }
+        if value.is_instance_of::<PyLong>() {
+            return Ok(value.str()?.to_str()?.to_string());
+        }
+        if value.is_instance_of::<PyFloat>() {
+            return match &self.float_format {
+                Some(spec) => value.call_method1("__format__", (spec,))?.extract(),
+                None => Ok(value.repr()?.to_str()?.to_string()),
+            };
+        }
+        if value.hasattr("isoformat")? {
+            return match &self.datetime_format {
+                Some(pattern) => value.call_method1("strftime", (pattern,))?.extract(),
+                None => value.call_method0("isoformat")?.extract(),
+            };
+        }
+        Ok(value.str()?.to_str()?.to_string())
+    }
+}
@@ -0,0 +1,63 @@
+// Module-level introspection: csv_reader.__version__, features() and
+// build_info(), for applications that support several versions or slim
+// builds and need to know what this one can do at runtime.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+// Each optional cargo feature and whether this build has it. The names
+// are the feature names in Cargo.toml, so a wheel built with
+// `--features sqlite` reports {"sqlite": True, ...}.
+#[pyfunction]
+pub fn features(py: Python) -> PyResult<PyObject> {
+    let features = PyDict::new(py);
+    // CSVParser.to_sqlite()
+    features.set_item("sqlite", cfg!(feature = "sqlite"))?;
+    // CSVParser.from_zip() and zip_entries()
+    features.set_item("zip", cfg!(feature = "zip"))?;
+    // gzip input to CSVParser, BGZF seekable
+    features.set_item("bgzf", cfg!(feature = "bgzf"))?;
+    // zstd input to CSVParser, the seekable format seekable
+    features.set_item("zstd", cfg!(feature = "zstd"))?;
+    Ok(features.to_object(py))
+}
+
+// {"version", "rustc", "target", "profile", "simd"}: the crate version,
+// the compiler and target triple it was built with (see build.rs),
+// "release" or "debug", and the widest SIMD instruction set the byte
+// searches (memchr) use on this machine, detected at runtime where the
+// target allows it.
+#[pyfunction]
+pub fn build_info(py: Python) -> PyResult<PyObject> {
+    let info = PyDict::new(py);
+    info.set_item("version", env!("CARGO_PKG_VERSION"))?;
+    info.set_item("rustc", env!("CSV_READER_RUSTC"))?;
+    info.set_item("target", env!("CSV_READER_TARGET"))?;
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    info.set_item("profile", profile)?;
+    info.set_item("simd", simd_level())?;
+    Ok(info.to_object(py))
+}
+
+fn simd_level() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            "avx2"
+        } else {
+            "sse2"
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        "neon"
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        "none"
+    }
+}
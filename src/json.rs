@@ -0,0 +1,59 @@
+// json_columns and a Query's "json" type: cell text parsed with serde_json
+// and built into the Python objects json.loads would give. Parsing needs
+// no GIL-bound calls, so an invalid cell costs a Rust error rather than a
+// raised and caught Python exception.
+//
+// Numbers keep their text (serde_json's arbitrary_precision), so integers
+// of any size come out exact, as Python ints, and objects keep their key
+// order (preserve_order). Unlike json.loads, NaN and Infinity aren't
+// accepted, and nesting deeper than 128 levels is invalid.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyLong};
+use serde_json::{Number, Value};
+
+// `text` as a Python object, or None if it isn't valid JSON
+pub fn parse(py: Python, text: &str) -> PyResult<Option<PyObject>> {
+    match serde_json::from_str::<Value>(text) {
+        Ok(value) => to_python(py, &value).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn to_python(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.to_object(py),
+        Value::Number(n) => number(py, n)?,
+        Value::String(s) => s.to_object(py),
+        Value::Array(items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(to_python(py, item)?)?;
+            }
+            list.to_object(py)
+        }
+        Value::Object(entries) => {
+            let dict = PyDict::new(py);
+            for (key, item) in entries {
+                dict.set_item(key, to_python(py, item)?)?;
+            }
+            dict.to_object(py)
+        }
+    })
+}
+
+// An int for a number without a fraction or exponent, as json.loads
+// gives, else a float
+fn number(py: Python, n: &Number) -> PyResult<PyObject> {
+    if let Some(i) = n.as_i64() {
+        return Ok(i.to_object(py));
+    }
+    let text = n.to_string();
+    if text.contains(['.', 'e', 'E']) {
+        // Out of range is inf, as in json.loads
+        let f: f64 = text.parse().unwrap_or(f64::NAN);
+        return Ok(f.to_object(py));
+    }
+    Ok(py.get_type::<PyLong>().call1((text,))?.to_object(py))
+}
@@ -1,705 +1,6529 @@
-use csv::ReaderBuilder;
+// pyo3 0.19's #[pymethods] expansion trips this rustc lint; the impls it
+// generates are fine, the macro just predates the lint.
+#![allow(non_local_definitions)]
+
+#[cfg(feature = "zip")]
+mod archive;
+mod cancel;
+#[cfg(any(feature = "bgzf", feature = "zstd"))]
+mod compressed;
+mod convert;
+mod digest;
+mod files;
+mod fixed;
+mod format;
+mod info;
+mod json;
+mod names;
+mod query;
+mod scanner;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod table;
+#[cfg(test)]
+mod tests;
+mod timings;
+mod writer;
+
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList};
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyList, PyLong, PyString, PyType};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use xxhash_rust::xxh64::Xxh64;
+
+use cancel::Cancel;
+use convert::{ConversionError, Converter};
+use files::{FileChange, FileWatch, InMemory, Input, QuoteSource, SnapshotCopy};
+use format::ValueFormat;
+use query::{Predicate, Query, QueryOutput};
+use scanner::{FieldCutter, QuoteTracker, RecordScanner};
+use stats::{ColumnAccumulator, CorrelationAccumulator, QuantileAccumulator, Stat};
+use timings::Timings;
+use writer::CSVWriter;
 
 // Custom buffer size for faster I/O
 const BUF_SIZE: usize = 64 * 1024; // 64KB buffer
 
+// How much of the file line_ending_report() looks at
+const LINE_ENDING_SAMPLE: usize = 1024 * 1024; // 1MB
+
+// get_file_info() warns when the file's first this many bytes have no line
+// break
+const LINE_CHECK_BYTES: u64 = 1024 * 1024; // 1MB
+
+// Python logging levels used for parser events
+const LOG_DEBUG: u8 = 10;
+const LOG_INFO: u8 = 20;
+
+// Default for raw_error_length
+const RAW_ERROR_LENGTH: usize = 200;
+
+// A `columns` projection takes the sparse path when it selects at most
+// 1/SPARSE_FRACTION of the header's columns
+const SPARSE_FRACTION: usize = 10;
+
+// Size of one object pointer, e.g. a list slot
+const POINTER_SIZE: usize = 8;
+// sys.getsizeof([]) on 64-bit CPython: object header plus GC header
+const LIST_OVERHEAD: usize = 56;
+// estimate_memory() recommends lazy_batches() above this
+const MEMORY_THRESHOLD: u64 = 1024 * 1024 * 1024; // 1GB
+
+// Rows extend_headers looks at when deciding how wide the data is
+const EXTEND_HEADERS_SAMPLE: usize = 1000;
+
+// Version written to, and accepted from, save_profile() files
+const PROFILE_VERSION: u64 = 1;
+
+// read_optimized() builds files up to this size as one flat list (see
+// collect_flat)
+const SMALL_FILE_SIZE: usize = 64 * 1024; // 64KB
+
+// Default string interning limits: cells up to this many bytes, and this
+// many distinct values per column (see StringCache)
+const INTERN_MAX_LEN: usize = 8;
+const INTERN_MAX_VALUES: usize = 256;
+
+// Readers read_chunk() keeps positioned for later calls (see ReaderPool)
+const CHUNK_POOL_SIZE: usize = 4;
+
+// seek_strategy="auto": how far before start_row, as a fraction of it, a
+// seek may land and still be used, the rows in between being skipped
+const SEEK_TOLERANCE: f64 = 0.25;
+
+// Most seek anchors kept (see SeekAnchors); past it every other one goes,
+// which keeps them spread over the file
+const SEEK_ANCHORS: usize = 1024;
+
+// Characters strip_zero_width removes from the start of cells: the byte
+// order mark (zero-width no-break space), zero-width space, non-joiner
+// and joiner, and word joiner
+const ZERO_WIDTH: &[char] = &['\u{feff}', '\u{200b}', '\u{200c}', '\u{200d}', '\u{2060}'];
+
+// Key include_row_hash adds to each row
+const ROW_HASH_KEY: &str = "_hash";
+
+// Delimiters check_delimiter looks for in a one-column first record, with
+// their names for its message
+const SUSPECT_DELIMITERS: &[(char, &str)] = &[('\t', "tabs"), (';', "semicolons"), ('|', "pipes")];
+
+// Row numbering contract shared by every read method:
+// rows are the data records that remain after the header (when has_headers
+// is set) and after the csv reader's own skipping of empty lines, numbered
+// from 0 in file order. A record spanning several physical lines (quoted
+// newlines) is still a single row. `read()` batches, `iter_rows()`,
+// `lazy_batches()`, `read_chunk(start, n)`, `get_row(i)`, `count_rows()` and
+// the add_row_number key all count rows this way, so `read_chunk(i * n, n)`
+// is always the same slice as the flattened `read()`.
+
+// FileChangedDuringRead is raised when the file is truncated or replaced
+// while a streaming read is in progress; carries `rows` (rows read so far)
+// and `offset` (bytes). EmptyFileError is raised at construction for a file
+// with no data records when allow_empty is off. CSVReaderWarning, a
+// UserWarning, flags input that parses but probably not as intended.
+// The allow is for a cfg pyo3 0.19's macro checks that rustc doesn't know.
+#[allow(unexpected_cfgs)]
+mod exceptions {
+    pyo3::create_exception!(
+        csv_reader,
+        FileChangedDuringRead,
+        pyo3::exceptions::PyIOError
+    );
+    pyo3::create_exception!(csv_reader, EmptyFileError, pyo3::exceptions::PyValueError);
+    pyo3::create_exception!(
+        csv_reader,
+        CSVReaderWarning,
+        pyo3::exceptions::PyUserWarning
+    );
+}
+use exceptions::{CSVReaderWarning, EmptyFileError, FileChangedDuringRead};
+
 #[pyclass]
 struct CSVParser {
     filename: String,
+    // Read this entry of the ZIP archive at `filename` instead of the file
+    // itself (see from_zip)
+    zip_entry: Option<String>,
+    #[cfg(feature = "zip")]
+    archive: Option<archive::Entry>,
+    // The format of a compressed file, whose uncompressed data is what is
+    // read, with its block index once a seek has built it
+    #[cfg(any(feature = "bgzf", feature = "zstd"))]
+    compressed: Option<Arc<compressed::Compressed>>,
+    // How reads are kept from seeing another program's half-written save
+    snapshot: SnapshotMode,
+    // snapshot="copy": the copy every read opens instead of `filename`,
+    // deleted along with the parser
+    snapshot_copy: Option<SnapshotCopy>,
     batch_size: usize,
     #[pyo3(get)]
     has_headers: bool,
     file_size: u64,
+    // Column name -> value used in place of an empty cell
+    defaults: HashMap<String, PyObject>,
+    // Prefix for the positional keys used when has_headers is false
+    index_key_prefix: String,
+    // csv.DictReader emulation (compat="dictreader"): extra fields go in a
+    // list under `restkey` and short rows are padded with `restval`
+    dictreader_compat: bool,
+    restkey: PyObject,
+    restval: PyObject,
+    // Columns converted to decimal.Decimal from their exact text
+    decimal_columns: Vec<String>,
+    // Columns whose cells are parsed as JSON (see json.rs), and what a
+    // cell that isn't valid JSON becomes
+    json_columns: Vec<String>,
+    json_invalid: JsonInvalid,
+    // Every cell a str, whatever decimal_columns, json_columns, a Query's
+    // schema, defaults, restval or distinguish_empty_quoted say (see
+    // as_string)
+    all_strings: bool,
+    // Clean typed (decimal) cells before conversion: trim Unicode
+    // whitespace and drop any of `clean_chars`. String columns are never
+    // cleaned.
+    clean: bool,
+    clean_chars: String,
+    // Drop byte order marks and zero-width characters (see ZERO_WIDTH)
+    // from the start of every cell, for files glued together from
+    // BOM-prefixed fragments
+    strip_zero_width: bool,
+    // Inject a "_hash" fingerprint (see row_hash) into every row, computed
+    // over `hash_columns` or all columns
+    include_row_hash: bool,
+    hash_columns: Option<Vec<String>>,
+    // Inject each row's number (see the row numbering contract), counted
+    // from row_number_start, under this key
+    add_row_number: Option<String>,
+    row_number_start: usize,
+    // Cap on the number of rows any method will process. Past it, methods
+    // stop as if the file ended there, or raise when row_limit_strict is set
+    row_limit: Option<usize>,
+    row_limit_strict: bool,
+    // Cap on the number of columns: the header and every record are cut
+    // to their first max_columns fields as the file is read (see
+    // scanner::FieldCutter)
+    max_columns: Option<usize>,
+    extend_headers: bool,
+    // Width the header is extended to, given up front instead of sampled
+    extend_headers_to: Option<usize>,
+    // Column count decided once by extend_headers or extend_headers_to;
+    // headers narrower than this get unnamed_<i> names for the trailing
+    // columns
+    header_width: usize,
+    // Columns to keep in each row dict, in this order; None keeps all
+    columns: Option<Vec<String>>,
+    // Header name -> key used for it in row dicts. Options that name
+    // columns (columns, defaults, ...) still use the header names.
+    rename: HashMap<String, String>,
+    // Rewrite the header row's names, then make repeated ones unique (see
+    // clean_headers). Unlike rename, this changes the header names
+    // themselves, so every option names columns the new way.
+    normalize_headers: Option<HeaderStyle>,
+    dedup_headers: bool,
+    drop_empty_columns: bool,
+    // Cells that count as empty for empty_columns(), besides "", e.g. "NA"
+    na_values: Vec<String>,
+    // Indices of the columns left out of rows by drop_empty_columns, found
+    // once by empty_column_indices() when the parser is created. By index
+    // so a repeated header name only drops the copy that is empty.
+    dropped_columns: Vec<usize>,
+    // sparse=True: leave empty cells out of the row dicts entirely
+    omit_empty: bool,
+    // Read an unquoted empty cell as None, keeping "" for a quoted one
+    // (see mark_empty_cells)
+    distinguish_empty_quoted: bool,
+    // Read each line whole, as the only field, under this column name
+    single_column: Option<String>,
+    // Field delimiter: ',' or, if the first record has a single column
+    // with it, the first of fallback_delimiters that splits it
+    delimiter: u8,
+    fallback_delimiters: Option<Vec<char>>,
+    // Raise instead of warning when the first record looks split on the
+    // wrong delimiter (see check_delimiter)
+    strict_dialect: bool,
+    // String cells up to intern_max_len bytes share one Python object per
+    // distinct value and column, for up to intern_max_values values
+    intern_max_len: usize,
+    intern_max_values: usize,
+    // Treat the first blank line after a record as the end of the data;
+    // data_end says where that is, found once at construction
+    stop_at_blank_line: bool,
+    data_end: Option<DataEnd>,
+    // Refuse files with malformed quoting (see check_quoting) instead of
+    // reading them the lenient way
+    strict_quoting: bool,
+    // Accept a file with no data records (empty, only blank lines, or only
+    // the header row); when off, the constructor raises EmptyFileError for
+    // one
+    allow_empty: bool,
+    // What to do when a row's field count differs from the header's
+    schema_drift: SchemaDrift,
+    // Stop a streaming read cleanly when the file is truncated or replaced
+    // under it, instead of raising FileChangedDuringRead
+    tolerate_truncation: bool,
+    // Cap on how fast the file is read; None reads at full speed
+    max_bytes_per_sec: Option<u64>,
+    // Longest line (bytes without a \r or \n) a read accepts before
+    // failing; None for no limit
+    max_line_bytes: Option<u64>,
+    // Which path read() takes; Auto picks by file size
+    mode: ReadMode,
+    // Whether read_chunk_optimized seeks by estimate, and whether it checks
+    // the estimate first
+    seek_strategy: SeekStrategy,
+    // What read_chunk's start_row and num_rows count
+    chunk_unit: ChunkUnit,
+    // Characters of a failing record's original text kept on the error as
+    // `raw`; 0 disables the capture
+    raw_error_length: usize,
+    // Rows between Python signal checks (KeyboardInterrupt) in read loops;
+    // 0 disables the checks
+    signal_check_interval: usize,
+    // Rows between releases of the GIL in read loops, so other Python
+    // threads get to run while a large batch is built; 0 disables them
+    gil_yield_every: usize,
+    // Log events to the `csv_reader` logger at INFO instead of DEBUG
+    verbose: bool,
+    // hash_content: where every Input stores the hash of the bytes it read
+    // when it read all of them (see files::Input::digest); None when off,
+    // and until the constructor's own scans are done
+    content_digest: Option<Arc<Mutex<Option<u64>>>>,
+    // timings=True: each read(), iter_rows() and lazy_batches() times its
+    // phases in its own Timings, carried by its RowLayout, so reads in
+    // progress at once don't mix their counts
+    timings: bool,
+    // The Timings of the last of those reads to start, for last_read_stats()
+    last_timings: Mutex<Option<Arc<Timings>>>,
+    // Set by line_ending_report() when it sees more than one line ending
+    // style; read_chunk_optimized then skips its byte-level seek.
+    mixed_line_endings: AtomicBool,
+    // The path the last read() took, as a ReadMode; 0 before any read
+    last_read_mode: AtomicU8,
+    // Readers left where earlier read_chunk() calls stopped, and how often
+    // a call could start from one
+    chunk_readers: Mutex<ReaderPool>,
+    pool_hits: AtomicU64,
+    pool_misses: AtomicU64,
+    // read_chunk_optimized calls that found the seek estimate off and
+    // skipped rows exactly instead
+    seek_fallbacks: AtomicU64,
+    // The path the last `columns` projection took: 0 for none, 1 for the
+    // plain projection, 2 for the sparse path
+    last_projection: AtomicU8,
+    // Whether the last read to finish stopped early under
+    // tolerate_truncation
+    last_read_truncated: AtomicBool,
+    // seek_strategy="auto": record starts counted by earlier calls
+    seek_anchors: Mutex<Option<SeekAnchors>>,
 }
 
 #[pymethods]
 impl CSVParser {
+    // Every option is a Python keyword argument, hence the long signature
     #[new]
-    fn new(filename: String, batch_size: usize, has_headers: Option<bool>) -> PyResult<Self> {
-        // Get file size during initialization to avoid reopening for size check
-        let file_size = match File::open(&filename) {
-            Ok(file) => match file.metadata() {
-                Ok(metadata) => metadata.len(),
-                Err(_) => 0,
-            },
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        filename,
+        batch_size,
+        has_headers=None,
+        defaults=None,
+        index_key_prefix=None,
+        decimal_columns=None,
+        json_columns=None,
+        json_invalid="raw",
+        compat=None,
+        restkey=None,
+        restval=None,
+        include_row_hash=false,
+        hash_columns=None,
+        add_row_number=None,
+        row_number_start=0,
+        row_limit=None,
+        row_limit_strict=false,
+        max_columns=None,
+        extend_headers=false,
+        extend_headers_to=None,
+        columns=None,
+        rename=None,
+        normalize_headers=None,
+        dedup_headers=false,
+        drop_empty_columns=false,
+        na_values=None,
+        sparse=false,
+        distinguish_empty_quoted=false,
+        single_column=None,
+        fallback_delimiters=None,
+        strict_dialect=false,
+        intern_max_len=INTERN_MAX_LEN,
+        intern_max_values=INTERN_MAX_VALUES,
+        stop_at_blank_line=false,
+        strict_quoting=false,
+        allow_empty=true,
+        schema_drift="ignore",
+        clean=true,
+        clean_chars=None,
+        strip_zero_width=false,
+        tolerate_truncation=false,
+        max_bytes_per_sec=None,
+        max_line_bytes=None,
+        mode="auto",
+        seek_strategy="auto",
+        zip_entry=None,
+        raw_error_length=RAW_ERROR_LENGTH,
+        signal_check_interval=10_000,
+        gil_yield_every=50_000,
+        verbose=false,
+        hash_content=false,
+        timings=false,
+        chunk_unit="records",
+        all_strings=false,
+        snapshot="none"
+    ))]
+    fn new(
+        py: Python,
+        filename: String,
+        batch_size: usize,
+        has_headers: Option<bool>,
+        defaults: Option<HashMap<String, PyObject>>,
+        index_key_prefix: Option<String>,
+        decimal_columns: Option<Vec<String>>,
+        json_columns: Option<Vec<String>>,
+        json_invalid: &str,
+        compat: Option<&str>,
+        restkey: Option<PyObject>,
+        restval: Option<PyObject>,
+        include_row_hash: bool,
+        hash_columns: Option<Vec<String>>,
+        add_row_number: Option<String>,
+        row_number_start: usize,
+        row_limit: Option<usize>,
+        row_limit_strict: bool,
+        max_columns: Option<usize>,
+        extend_headers: bool,
+        extend_headers_to: Option<usize>,
+        columns: Option<Vec<String>>,
+        rename: Option<HashMap<String, String>>,
+        normalize_headers: Option<&str>,
+        dedup_headers: bool,
+        drop_empty_columns: bool,
+        na_values: Option<Vec<String>>,
+        sparse: bool,
+        distinguish_empty_quoted: bool,
+        single_column: Option<String>,
+        fallback_delimiters: Option<Vec<char>>,
+        strict_dialect: bool,
+        intern_max_len: usize,
+        intern_max_values: usize,
+        stop_at_blank_line: bool,
+        strict_quoting: bool,
+        allow_empty: bool,
+        schema_drift: &str,
+        clean: bool,
+        clean_chars: Option<String>,
+        strip_zero_width: bool,
+        tolerate_truncation: bool,
+        max_bytes_per_sec: Option<u64>,
+        max_line_bytes: Option<u64>,
+        mode: &str,
+        seek_strategy: &str,
+        zip_entry: Option<String>,
+        raw_error_length: usize,
+        signal_check_interval: usize,
+        gil_yield_every: usize,
+        verbose: bool,
+        hash_content: bool,
+        timings: bool,
+        chunk_unit: &str,
+        all_strings: bool,
+        snapshot: &str,
+    ) -> PyResult<Self> {
+        // batch_size=0 would never fill a batch. Any larger value is fine:
+        // one bigger than the file just yields a single batch.
+        if batch_size == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "batch_size must be at least 1".to_string(),
+            ));
+        }
+        if max_columns == Some(0) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "max_columns must be at least 1".to_string(),
+            ));
+        }
+        // StringCache packs a cell into a 16-byte key
+        if intern_max_len > 15 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "intern_max_len must be at most 15".to_string(),
+            ));
+        }
+        if max_bytes_per_sec == Some(0) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "max_bytes_per_sec must be at least 1".to_string(),
+            ));
+        }
+        // The csv reader takes a single byte, and a quote or line break
+        // can't separate fields
+        for &c in fallback_delimiters.iter().flatten() {
+            if !c.is_ascii() || matches!(c, '"' | '\r' | '\n') {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "fallback_delimiters must be ASCII characters other than '\"' and line breaks, not {:?}",
+                    c
                 )));
             }
-        };
-
-        Ok(CSVParser {
-            filename,
-            batch_size,
-            has_headers: has_headers.unwrap_or(true),
-            file_size,
-        })
-    }
-
-    // Read the CSV file and return batches of rows as Python objects
-    fn read(&self, py: Python) -> PyResult<Vec<PyObject>> {
-        // Fast path: read entire file into memory for large files
-        if self.file_size > 0 && self.file_size < 100 * 1024 * 1024 {
-            // check if under 100 MB 1024 as kb
-            return self.read_optimized(py); // Will read whole file to memory first
+        }
+        if max_line_bytes == Some(0) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "max_line_bytes must be at least 1".to_string(),
+            ));
         }
 
-        // Write with chunking for larger files
-        let path = Path::new(&self.filename);
-        let file = match File::open(path) {
-            Ok(f) => BufReader::with_capacity(BUF_SIZE, f),
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
+        let dictreader_compat = match compat {
+            None => false,
+            Some("dictreader") => true,
+            Some(other) => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Unsupported compat mode: {}",
+                    other
                 )));
             }
         };
 
-        let mut reader = ReaderBuilder::new()
-            .flexible(true)
-            .has_headers(self.has_headers)
-            .from_reader(file);
-
-        let headers = match reader.headers() {
-            Ok(h) => h.clone(),
-            Err(e) => {
+        let schema_drift = match schema_drift {
+            "ignore" => SchemaDrift::Ignore,
+            "warn" => SchemaDrift::Warn,
+            "error" => SchemaDrift::Error,
+            other => {
                 return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                    "Failed to read CSV headers: {}",
-                    e
+                    "schema_drift must be \"error\", \"warn\" or \"ignore\", not {:?}",
+                    other
                 )));
             }
         };
 
-        // Pre-allocate the vector to reduce reallocations
-        let mut batches: Vec<PyObject> =
-            Vec::with_capacity((self.file_size / (self.batch_size as u64 * 100) + 1) as usize);
-
-        let mut current_batch = PyList::empty(py);
-        let mut current_rows = Vec::with_capacity(self.batch_size);
-        let mut count: usize = 0;
+        let mode = ReadMode::from_name(mode)?;
+        let seek_strategy = SeekStrategy::from_name(seek_strategy)?;
+        let chunk_unit = ChunkUnit::from_name(chunk_unit)?;
+        // A record max_columns cut ends, as the csv reader sees it, inside
+        // its line, before the line breaks of the fields it dropped, so
+        // the line a record starts on can't be told from its position
+        if chunk_unit == ChunkUnit::Lines && max_columns.is_some() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "chunk_unit=\"lines\" can't be combined with max_columns".to_string(),
+            ));
+        }
+        let json_invalid = JsonInvalid::from_name(json_invalid)?;
+        let normalize_headers = normalize_headers.map(HeaderStyle::from_name).transpose()?;
+        let decimal_columns = decimal_columns.unwrap_or_default();
+        let json_columns = json_columns.unwrap_or_default();
+        if let Some(both) = json_columns.iter().find(|c| decimal_columns.contains(c)) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Column {} is in both decimal_columns and json_columns",
+                both
+            )));
+        }
 
-        // Process records in batches for better memory usage
-        let iter = reader.records();
-        for result in iter {
-            let record = match result {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                        "Failed to read CSV record: {}",
-                        e
-                    )));
-                }
-            };
+        // With snapshot="copy" the parser only ever opens the copy, this
+        // included
+        let snapshot = SnapshotMode::from_name(snapshot)?;
+        let snapshot_copy = match snapshot {
+            SnapshotMode::Copy => match SnapshotCopy::create(Path::new(&filename)) {
+                Ok(copy) => Some(copy),
+                Err(e) => return Err(files::io_error("copy file for snapshot", e)),
+            },
+            _ => None,
+        };
+        let data_path = snapshot_copy
+            .as_ref()
+            .map_or(Path::new(&filename), |copy| copy.path());
 
-            // Create Python dict for this record
-            let row = PyDict::new(py);
+        // Get file size during initialization to avoid reopening for size check
+        let mut file = match files::open(data_path) {
+            Ok(file) => file,
+            Err(e) => return Err(files::io_error("open file", e)),
+        };
+        let file_size = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
 
-            // Efficient field extraction
-            for (i, field) in record.iter().enumerate() {
-                if i < headers.len() {
-                    let header = headers.get(i).unwrap_or("None");
-                    // Direct set without unnecessary conversions
-                    row.set_item(header, field)?;
-                }
-            }
+        #[cfg(feature = "zip")]
+        let archive = match &zip_entry {
+            Some(name) => Some(archive::locate(&data_path.to_string_lossy(), Some(name))?),
+            None => None,
+        };
+        #[cfg(feature = "zip")]
+        let file_size = archive.as_ref().map_or(file_size, |entry| entry.size);
+        #[cfg(not(feature = "zip"))]
+        if zip_entry.is_some() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "zip_entry needs a build with the zip cargo feature".to_string(),
+            ));
+        }
+        if zip_entry.is_some() && snapshot == SnapshotMode::Lock {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "snapshot=\"lock\" can't lock an archive entry; use snapshot=\"copy\"".to_string(),
+            ));
+        }
 
-            // Store row
-            current_rows.push(row.to_object(py));
-            count += 1;
+        // Compressed input would otherwise be parsed as garbage text, or fail
+        // much later with a confusing UTF-8 error. Go by content, not the
+        // extension, since mislabeled files are common. The exceptions are
+        // the formats the build's cargo features read.
+        let format = files::compression(&mut file);
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        let compressed = match compressed::Compressed::detect(&mut file) {
+            Ok(compressed) => compressed.map(Arc::new),
+            Err(e) => return Err(files::io_error("read file", e)),
+        };
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        let format = format.filter(|_| compressed.is_none());
+        if let Some(format) = format {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "{} is {}-compressed; decompress it first, compressed input is not supported",
+                filename, format
+            )));
+        }
 
-            // When batch is full, add to batches and create new batch
-            if count >= self.batch_size {
-                // Build list from collected rows
-                for row in &current_rows {
-                    let _ = current_batch.append(row.clone_ref(py))?;
-                }
+        let mut parser = CSVParser {
+            filename,
+            zip_entry,
+            #[cfg(feature = "zip")]
+            archive,
+            #[cfg(any(feature = "bgzf", feature = "zstd"))]
+            compressed,
+            snapshot,
+            snapshot_copy,
+            batch_size,
+            has_headers: has_headers.unwrap_or(true),
+            file_size,
+            defaults: defaults.unwrap_or_default(),
+            index_key_prefix: index_key_prefix.unwrap_or_default(),
+            decimal_columns,
+            json_columns,
+            json_invalid,
+            all_strings,
+            clean,
+            clean_chars: clean_chars.unwrap_or_default(),
+            strip_zero_width,
+            dictreader_compat,
+            restkey: restkey.unwrap_or_else(|| py.None()),
+            restval: restval.unwrap_or_else(|| py.None()),
+            include_row_hash,
+            hash_columns,
+            add_row_number,
+            row_number_start,
+            row_limit,
+            row_limit_strict,
+            max_columns,
+            extend_headers,
+            extend_headers_to,
+            header_width: 0,
+            columns,
+            rename: rename.unwrap_or_default(),
+            normalize_headers,
+            dedup_headers,
+            drop_empty_columns,
+            na_values: na_values.unwrap_or_default(),
+            dropped_columns: Vec::new(),
+            omit_empty: sparse,
+            distinguish_empty_quoted,
+            single_column,
+            delimiter: b',',
+            fallback_delimiters,
+            strict_dialect,
+            intern_max_len,
+            intern_max_values,
+            stop_at_blank_line,
+            data_end: None,
+            strict_quoting,
+            allow_empty,
+            schema_drift,
+            tolerate_truncation,
+            max_bytes_per_sec,
+            max_line_bytes,
+            mode,
+            seek_strategy,
+            chunk_unit,
+            raw_error_length,
+            signal_check_interval,
+            gil_yield_every,
+            verbose,
+            content_digest: None,
+            timings,
+            last_timings: Mutex::new(None),
+            mixed_line_endings: AtomicBool::new(false),
+            last_read_mode: AtomicU8::new(0),
+            chunk_readers: Mutex::new(ReaderPool::default()),
+            seek_anchors: Mutex::new(None),
+            pool_hits: AtomicU64::new(0),
+            pool_misses: AtomicU64::new(0),
+            seek_fallbacks: AtomicU64::new(0),
+            last_projection: AtomicU8::new(0),
+            last_read_truncated: AtomicBool::new(false),
+        };
 
-                batches.push(current_batch.to_object(py));
-                current_batch = PyList::empty(py);
-                current_rows.clear();
-                count = 0;
+        if let Some(fallbacks) = &parser.fallback_delimiters {
+            parser.delimiter = parser.resolve_delimiter(py, fallbacks)?;
+            if parser.delimiter != b',' {
+                let delimiter = (parser.delimiter as char).to_string();
+                parser.log_event(
+                    py,
+                    "delimiter",
+                    format!(
+                        "The first record has one column with ','; using {:?}",
+                        delimiter
+                    ),
+                    &[("delimiter", delimiter.to_object(py))],
+                )?;
             }
         }
+        if !allow_empty && !parser.has_records()? {
+            return Err(EmptyFileError::new_err(format!(
+                "{} has no data: it is empty, holds only blank lines or only a header",
+                parser.filename
+            )));
+        }
+        parser.check_delimiter(py)?;
+        if stop_at_blank_line {
+            parser.data_end = parser.find_data_end()?;
+        }
+        if strict_quoting {
+            parser.check_quoting(py)?;
+        }
 
-        // Don't forget remaining rows
-        if count > 0 {
-            for row in &current_rows {
-                let _ = current_batch.append(row.clone_ref(py))?;
+        // Decide the width once, so every method sees the same columns
+        if extend_headers {
+            parser.header_width = parser.sample_width(py)?;
+        }
+        if let Some(width) = extend_headers_to {
+            if parser.has_headers {
+                let file = parser.open_buffered()?;
+                let mut reader = parser.reader_builder().from_reader(file);
+                let names = parser.read_headers(&mut reader)?.len();
+                if width < names {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "extend_headers_to={} is less than the {} columns the header has",
+                        width, names
+                    )));
+                }
             }
-            batches.push(current_batch.to_object(py));
+            parser.header_width = parser.header_width.max(width);
+        }
+        if drop_empty_columns {
+            parser.dropped_columns = parser.empty_column_indices(py)?;
+        }
+        // Only now, so the scans above, which can read the whole file,
+        // don't count as a read for content_hash()
+        if hash_content {
+            parser.content_digest = Some(Arc::new(Mutex::new(None)));
         }
 
-        Ok(batches)
+        parser.log_event(
+            py,
+            "open",
+            format!("Opened {} ({} bytes)", parser.filename, file_size),
+            &[("size_bytes", file_size.to_object(py))],
+        )?;
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        if let Some(format) = parser.compressed.clone().filter(|c| !c.seekable()) {
+            parser.warn_sequential(py, format.name())?;
+        }
+
+        Ok(parser)
+    }
+
+    // Read the CSV file and return batches of rows as Python objects.
+    // `mode` overrides the parser's mode for this call: "memory" reads the
+    // whole file into memory first, "stream" parses it as it is read, and
+    // "auto" picks memory for files under 100 MB. Both paths give the same
+    // rows; last_read_stats() reports which one ran.
+    #[pyo3(signature = (mode=None))]
+    fn read(&self, py: Python, mode: Option<&str>) -> PyResult<Vec<PyObject>> {
+        let timings = self.start_timings();
+        let started = timings.as_ref().map(|_| Instant::now());
+        let batches = self.read_batches(py, mode, timings.as_ref());
+        if let (Some(timings), Some(started)) = (&timings, started) {
+            Timings::add(&timings.total, started);
+        }
+        batches
     }
 
     // Optimized method for reading entire file at once (for smaller files)
     fn read_optimized(&self, py: Python) -> PyResult<Vec<PyObject>> {
-        let path = Path::new(&self.filename);
+        self.read_memory(py, None)
+    }
 
-        // Read the entire file into memory at once
-        let mut content = Vec::with_capacity(self.file_size as usize);
-        {
-            let mut file = match File::open(path) {
-                Ok(f) => f,
-                Err(e) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                        "Failed to open file: {}",
-                        e
-                    )));
+    // All rows as one list, last row first, for newest-first views of
+    // append-only files. The header still comes from the top of the file.
+    // `mode` picks the path as for read(): "memory" reads the file forward
+    // and reverses the list; "stream" scans record offsets first (8 bytes
+    // per row, without the GIL), then parses `batch_size` records at a time
+    // from the end backward, seeking to each window, so the file is read
+    // twice but its bytes are never held in memory.
+    #[pyo3(signature = (mode=None))]
+    fn read_reversed(&self, py: Python, mode: Option<&str>) -> PyResult<PyObject> {
+        let rows = PyList::empty(py);
+        if self.read_path(mode)? == ReadMode::Memory {
+            for batch in self.read_optimized(py)? {
+                for row in batch.as_ref(py).downcast::<PyList>()? {
+                    rows.append(row)?;
                 }
-            };
-
-            if let Err(e) = file.read_to_end(&mut content) {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to read file: {}",
-                    e
-                )));
             }
+            rows.reverse()?;
+            return Ok(rows.to_object(py));
         }
 
-        // Process the content with a memory reader (faster than file I/O)
-        let mut reader = ReaderBuilder::new()
-            .flexible(true)
-            .has_headers(self.has_headers)
-            .from_reader(content.as_slice());
+        // Row limits are enforced by the scan
+        let offsets = self.scan_record_offsets(py, 1)?;
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
+        let skip = usize::from(self.has_headers);
 
-        let headers = match reader.headers() {
-            Ok(h) => h.clone(),
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                    "Failed to read CSV headers: {}",
+        let mut buf = RecordBuf::default();
+        let mut window: Vec<&PyDict> = Vec::with_capacity(self.batch_size);
+        let mut end = offsets.len();
+        while end > 0 {
+            let start = end.saturating_sub(self.batch_size);
+            // Record numbers count the header, as in a forward read
+            let mut position = csv::Position::new();
+            position
+                .set_byte(offsets[start])
+                .set_record((start + skip) as u64);
+            if let Err(e) = reader.seek_raw(SeekFrom::Start(offsets[start]), position) {
+                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                    "Failed to seek in file: {}",
                     e
                 )));
             }
-        };
-
-        // Pre-allocate results
-        let estimated_rows = content.len() / 50; // Rough estimate of rows based on byte size
-                                                 // heuristic value as count as
-                                                 // A few numeric fields (4-8 bytes each)
-                                                 // A few short text fields (10-20 bytes each)
-                                                 // Commas between fields (1 byte each)
-                                                 // A newline character (1-2 bytes)
-        let estimated_batches = (estimated_rows / self.batch_size) + 1; // + 1 is for the remainder batch if any
-        let mut batches: Vec<PyObject> = Vec::with_capacity(estimated_batches);
-
-        // Process in batches
-        let mut current_batch = PyList::empty(py);
-        let mut current_rows = Vec::with_capacity(self.batch_size);
-        let mut count: usize = 0;
-
-        // Process all records at once
-        for result in reader.records() {
-            let record = match result {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                        "Failed to read CSV record: {}",
-                        e
-                    )));
-                }
-            };
-
-            // Create dict with capacity for all fields
-            let row = PyDict::new(py);
-
-            // Process all fields
-            for (i, field) in record.iter().enumerate() {
-                if i < headers.len() {
-                    let header = headers.get(i).unwrap_or("None");
-                    row.set_item(header, field)?;
-                }
-            }
-
-            // Add to batch
-            current_rows.push(row.to_object(py));
-            count += 1;
 
-            // When batch is full, push to batches
-            if count >= self.batch_size {
-                // Build list from collected rows
-                for row in &current_rows {
-                    let _ = current_batch.append(row.clone_ref(py))?;
+            window.clear();
+            buf.next_row = start;
+            for _ in start..end {
+                match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                    Some(row) => window.push(row),
+                    None => break,
                 }
-
-                batches.push(current_batch.to_object(py));
-                current_batch = PyList::empty(py);
-                current_rows.clear();
-                count = 0;
             }
-        }
-
-        // Add any remaining rows
-        if count > 0 {
-            for row in &current_rows {
-                let _ = current_batch.append(row.clone_ref(py))?;
+            for row in window.iter().rev() {
+                rows.append(*row)?;
             }
-            batches.push(current_batch.to_object(py));
+            self.check_signals(py, rows.len())?;
+            end = start;
         }
 
-        Ok(batches)
+        Ok(rows.to_object(py))
     }
 
     // Get the total number of rows in the CSV file (optimized)
-    fn count_rows(&self) -> PyResult<usize> {
-        let path = Path::new(&self.filename);
-        let file = match File::open(path) {
-            Ok(f) => BufReader::with_capacity(BUF_SIZE, f),
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
-                )));
-            }
-        };
-
-        let mut reader = ReaderBuilder::new()
-            .has_headers(self.has_headers)
-            .from_reader(file);
+    fn count_rows(&self, py: Python) -> PyResult<usize> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
 
         // If headers exist, we need to account for them
         if self.has_headers {
-            if reader.headers().is_err() {
-                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                    "Failed to read headers".to_string(),
-                ));
+            if let Err(e) = reader.headers() {
+                return Err(line_error(&e).unwrap_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Failed to read headers: {}",
+                        e
+                    ))
+                }));
             }
         }
 
-        // Count rows efficiently
+        // Count rows efficiently, without UTF-8 validation
+        let mut record = ByteRecord::new();
         let mut count = 0;
-        for result in reader.records() {
-            if result.is_ok() {
-                count += 1;
+        while count < self.max_rows() {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => {
+                    count += 1;
+                    self.check_signals(py, count)?;
+                }
+                Ok(false) => break,
+                Err(e) => {
+                    return Err(self.record_error(py, e));
+                }
             }
         }
+        self.enforce_row_limit(&mut reader, count)?;
 
         Ok(count)
     }
 
-    // Optimized method to read a specific chunk of the CSV file
+    // Read `num_rows` rows starting at row `start_row` (see the row numbering
+    // contract at the top of this file). The result is always exactly the
+    // corresponding slice of `read()`.
+    // With chunk_unit="lines" both count physical lines instead, and the
+    // chunk is the rows whose records start on those lines (see
+    // ChunkUnit::Lines).
+    // Successive calls reuse readers left where earlier calls stopped (see
+    // ReaderPool), so paging forward through a file doesn't re-skip it.
     fn read_chunk(&self, py: Python, start_row: usize, num_rows: usize) -> PyResult<PyObject> {
-        if start_row == 0 && self.has_headers {
-            // Just use the regular read method with a limit
-            let path = Path::new(&self.filename);
-            let file = match File::open(path) {
-                Ok(f) => BufReader::with_capacity(BUF_SIZE, f),
-                Err(e) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                        "Failed to open file: {}",
-                        e
-                    )));
-                }
-            };
+        self.read_chunk_exact(py, self.chunk_unit, start_row, num_rows)
+    }
 
-            let mut reader = ReaderBuilder::new()
-                .has_headers(self.has_headers)
-                .from_reader(file);
+    // Row `row`, numbered as in read() whatever chunk_unit is, as a dict;
+    // IndexError past the last row. Reads like read_chunk(row, 1), so
+    // looking up rows in increasing order continues from pooled readers.
+    fn get_row(&self, py: Python, row: usize) -> PyResult<PyObject> {
+        let rows = self.read_chunk_exact(py, ChunkUnit::Records, row, 1)?;
+        let rows: &PyList = rows.downcast(py)?;
+        match rows.get_item(0) {
+            Ok(found) => Ok(found.to_object(py)),
+            Err(_) => Err(PyErr::new::<pyo3::exceptions::PyIndexError, _>(format!(
+                "Row {} is past the end of {}",
+                row, self.filename
+            ))),
+        }
+    }
 
-            let headers = match reader.headers() {
-                Ok(h) => h.clone(),
-                Err(e) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                        "Failed to read CSV headers: {}",
-                        e
-                    )));
-                }
-            };
+    // Return the rows whose entry in `mask` is true, for the second pass of
+    // a filter computed in Python. The mask is aligned to row numbers; rows
+    // it skips are never turned into dicts. A mask shorter than the file
+    // stops the read early; one longer than the file is an error.
+    fn read_mask(&self, py: Python, mask: Vec<bool>) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
 
-            let chunk = PyList::empty(py);
+        let rows = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        let wanted = mask.len().min(self.max_rows());
 
-            // Process only up to num_rows
-            for (_, result) in reader.records().take(num_rows).enumerate() {
-                let record = match result {
-                    Ok(r) => r,
+        for (i, &keep) in mask[..wanted].iter().enumerate() {
+            if keep {
+                buf.next_row = i;
+                match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                    Some(row) => rows.append(row)?,
+                    None => return Err(mask_too_long(mask.len(), i)),
+                }
+            } else {
+                match reader.read_byte_record(&mut buf.bytes) {
+                    Ok(true) => {}
+                    Ok(false) => return Err(mask_too_long(mask.len(), i)),
                     Err(e) => {
-                        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                            "Failed to read CSV record: {}",
-                            e
-                        )));
+                        return Err(self.record_error(py, e));
                     }
-                };
+                }
+            }
+            self.check_signals(py, i + 1)?;
+        }
 
-                let row = PyDict::new(py);
+        // Mask entries past row_limit point at rows that don't exist
+        if wanted < mask.len() {
+            self.enforce_row_limit(&mut reader, wanted)?;
+            return Err(mask_too_long(mask.len(), wanted));
+        }
 
-                for (i, field) in record.iter().enumerate() {
-                    if i < headers.len() {
-                        let header = headers.get(i).unwrap_or("None");
-                        row.set_item(header, field)?;
+        Ok(rows.to_object(py))
+    }
+
+    // The rows whose records start at `offsets`, e.g. the `offset` of
+    // errors collected on an earlier pass, without parsing the rest of the
+    // file. An offset may also point at the line ending or blank lines just
+    // before a record. Each entry is a dict with "row_number", "offset"
+    // (where the record starts), "raw" (its text, decoded lossily), "row"
+    // (the row dict, or None if it can't be built) and "error" (why not, or
+    // None), in file order and once per record. Row numbers come from a
+    // byte scan up to the last offset, run without the GIL; the records
+    // are then read in order, skipping ahead when they are close together
+    // and seeking otherwise.
+    fn read_rows_at(&self, py: Python, offsets: Vec<u64>) -> PyResult<PyObject> {
+        let mut offsets = offsets;
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
+
+        let cancel = self.cancel();
+        let located = files::without_gil(py, || self.locate_records(&offsets, &cancel));
+        cancel.raise()?;
+        let located = match located {
+            Ok(located) => located,
+            Err(e) => return Err(files::io_error("read file", e)),
+        };
+        let mut raw_file = self.open_input()?;
+        let skip = usize::from(self.has_headers);
+
+        let entries = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        let mut last_start = None;
+        for (&offset, found) in offsets.iter().zip(located) {
+            let (index, start) = match found {
+                Some((index, start)) if index >= skip && index - skip < self.max_rows() => {
+                    (index, start)
+                }
+                _ => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "No row starts at offset {}",
+                        offset
+                    )));
+                }
+            };
+            if !self.only_line_endings(&mut raw_file, offset, start)? {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Offset {} is inside a record, not at the start of one",
+                    offset
+                )));
+            }
+            // Several offsets can lead to the same record
+            if last_start == Some(start) {
+                continue;
+            }
+            last_start = Some(start);
+
+            // Close records are reached by reading on, far ones by seeking
+            let position = reader.position().clone();
+            let ahead = index >= position.record() as usize
+                && start - position.byte().min(start) <= BUF_SIZE as u64;
+            if ahead {
+                for _ in position.record() as usize..index {
+                    if let Err(e) = reader.read_byte_record(&mut buf.bytes) {
+                        return Err(self.record_error(py, e));
                     }
                 }
+            } else {
+                let mut target = csv::Position::new();
+                target.set_byte(start).set_record(index as u64);
+                if let Err(e) = reader.seek_raw(SeekFrom::Start(start), target) {
+                    return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                        "Failed to seek in file: {}",
+                        e
+                    )));
+                }
+            }
 
-                let _ = chunk.append(row.to_object(py))?;
+            buf.next_row = index - skip;
+            let parsed = self
+                .next_record(py, &mut reader, &layout, &mut buf)
+                .and_then(|_| self.buffered_row(py, &layout, &buf));
+            let end = reader.position().byte();
+            let (row, error) = match parsed {
+                Ok(row) => (row.to_object(py), py.None()),
+                Err(e) => (py.None(), e.value(py).str()?.to_object(py)),
+            };
+
+            let mut bytes = Vec::new();
+            let read = raw_file
+                .seek(SeekFrom::Start(start))
+                .and_then(|_| (&mut raw_file).take(end - start).read_to_end(&mut bytes));
+            if let Err(e) = read {
+                return Err(files::io_error("read file", e));
             }
+            let raw = String::from_utf8_lossy(&bytes);
 
-            return Ok(chunk.to_object(py));
+            let entry = PyDict::new(py);
+            entry.set_item("row_number", index - skip)?;
+            entry.set_item("offset", start)?;
+            entry.set_item("raw", raw.trim_end_matches(['\r', '\n']))?;
+            entry.set_item("row", row)?;
+            entry.set_item("error", error)?;
+            entries.append(entry)?;
         }
 
-        // For seeking to a specific row, we need a more efficient approach
-        // This is a more complex implementation for larger start_row values
-        let chunk = self.read_chunk_optimized(py, start_row, num_rows)?;
-        Ok(chunk)
+        Ok(entries.to_object(py))
     }
 
-    // Advanced chunk reading with seeking optimization
-    fn read_chunk_optimized(
+    // The first row where `column <op> value` holds, with up to `before`
+    // rows preceding it and `after` rows following it, like grep -C. Each
+    // entry is a dict with the row's number ("row_number"), the row dict
+    // ("row") and whether it is the match ("match"), in file order. Empty
+    // if no row matches. The preceding records wait in a ring buffer of
+    // `before` slots and only become dicts if a match follows, so memory
+    // stays bounded however far into the file the match is.
+    #[pyo3(signature = (column, op, value, before=5, after=5))]
+    fn read_context(
         &self,
         py: Python,
-        start_row: usize,
-        num_rows: usize,
+        column: &str,
+        op: &str,
+        value: &PyAny,
+        before: usize,
+        after: usize,
     ) -> PyResult<PyObject> {
-        let path = Path::new(&self.filename);
+        let condition = Condition::new(op, value)?;
 
-        // If we're starting far into the file, try to estimate the position
-        // and seek to it before reading to avoid processing unnecessary rows
-        if start_row > 1000 {
-            // Use the file size to estimate bytes per row
-            if self.file_size > 0 {
-                // First estimate bytes per row by sampling
-                let estimated_bytes_per_row = self.estimate_bytes_per_row()?;
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, column)?;
+        let layout = self.row_layout(py, &headers)?;
 
-                if estimated_bytes_per_row > 0.0 {
-                    // Create a seekable reader
-                    let file = match File::open(path) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                                "Failed to open file: {}",
-                                e
-                            )));
-                        }
-                    };
+        // (row number, record) for the `before` most recent rows
+        let mut ring: VecDeque<(usize, RecordBuf)> = VecDeque::with_capacity(before);
+        let mut buf = RecordBuf::default();
+        let mut rows: usize = 0;
 
-                    let mut reader = BufReader::with_capacity(BUF_SIZE, file);
-                    let mut buffer = [0; 1];
-                    while reader.read_exact(&mut buffer).is_ok() {
-                        if buffer[0] == b'\n' {
-                            break;
-                        }
-                    }
+        let found = loop {
+            if rows >= self.max_rows() || !self.next_record(py, &mut reader, &layout, &mut buf)? {
+                break false;
+            }
+            rows += 1;
+            self.check_signals(py, rows)?;
 
-                    // Estimate position for start_row
-                    let header_offset = if self.has_headers {
-                        estimated_bytes_per_row
-                    } else {
-                        0.0
-                    };
-                    let estimated_pos =
-                        (estimated_bytes_per_row * start_row as f64) + header_offset;
+            if buffered_field(&layout, &buf, index).is_some_and(|f| condition.matches(f)) {
+                break true;
+            }
+            if before > 0 {
+                let mut slot = if ring.len() == before {
+                    ring.pop_front().unwrap().1
+                } else {
+                    RecordBuf::default()
+                };
+                // The drift warning is once per read, not per buffer, and
+                // the row count carries on
+                slot.drift_warned = buf.drift_warned;
+                slot.next_row = buf.next_row;
+                std::mem::swap(&mut slot, &mut buf);
+                ring.push_back((rows - 1, slot));
+            }
+        };
 
-                    // Seek to estimated position
-                    if estimated_pos < self.file_size as f64 {
-                        // Seek to slightly before estimated position to ensure we don't miss a row
-                        let safe_pos =
-                            (estimated_pos - estimated_bytes_per_row * 2.0).max(0.0) as u64;
-                        if let Err(e) = reader.seek(SeekFrom::Start(safe_pos)) {
-                            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                                "Failed to seek in file: {}",
-                                e
-                            )));
-                        }
+        let context = PyList::empty(py);
+        if !found {
+            self.enforce_row_limit(&mut reader, rows)?;
+            return Ok(context.to_object(py));
+        }
 
-                        // Skip to next line boundary
-                        let mut buffer = [0; 1];
-                        while reader.read_exact(&mut buffer).is_ok() {
-                            if buffer[0] == b'\n' {
-                                break;
-                            }
-                        }
+        let entry = |row_number: usize, row: &PyDict, is_match: bool| -> PyResult<&PyDict> {
+            let entry = PyDict::new(py);
+            entry.set_item("row_number", row_number)?;
+            entry.set_item("row", row)?;
+            entry.set_item("match", is_match)?;
+            Ok(entry)
+        };
+        for (row_number, record) in &ring {
+            context.append(entry(
+                *row_number,
+                self.buffered_row(py, &layout, record)?,
+                false,
+            )?)?;
+        }
+        let matched = rows - 1;
+        context.append(entry(matched, self.buffered_row(py, &layout, &buf)?, true)?)?;
+        for i in 1..=after {
+            if matched + i >= self.max_rows() {
+                self.enforce_row_limit(&mut reader, matched + i)?;
+                break;
+            }
+            match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                Some(row) => context.append(entry(matched + i, row, false)?)?,
+                None => break,
+            }
+        }
 
-                        // Now recreate the reader at this position
-                        let pos = reader.stream_position().unwrap_or(0);
-                        drop(reader);
+        Ok(context.to_object(py))
+    }
 
-                        let file = match File::open(path) {
-                            Ok(f) => f,
-                            Err(e) => {
-                                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                                    "Failed to open file: {}",
-                                    e
-                                )));
-                            }
-                        };
+    // Rows whose `column` value is one of `values`, in one streaming pass.
+    // `values` is turned into a Rust set once (see KeySet for how strings
+    // and numbers match) and each record is tested on that one field; only
+    // matching rows become dicts. output="rows" returns a flat list,
+    // "batches" a list of batch_size lists like read(). The scanned and
+    // matched counts are logged as a "where_in" event.
+    #[pyo3(signature = (column, values, output="rows"))]
+    fn read_where_in(
+        &self,
+        py: Python,
+        column: &str,
+        values: &PyAny,
+        output: &str,
+    ) -> PyResult<PyObject> {
+        let batched = match output {
+            "rows" => false,
+            "batches" => true,
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "output must be \"rows\" or \"batches\", not {:?}",
+                    other
+                )));
+            }
+        };
+        let keys = KeySet::from_values(values)?;
 
-                        let mut reader = BufReader::with_capacity(BUF_SIZE, file);
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, column)?;
+        let layout = self.row_layout(py, &headers)?;
+        let started = Instant::now();
 
-                        // Seek to our calculated position
-                        if let Err(e) = reader.seek(SeekFrom::Start(pos)) {
-                            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                                "Failed to seek in file: {}",
-                                e
-                            )));
-                        }
+        let mut batches: Vec<PyObject> = Vec::new();
+        let mut current = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        let mut scanned: usize = 0;
+        let mut matched: usize = 0;
 
-                        // Create new reader from this position
-                        let mut csv_reader = ReaderBuilder::new()
-                            .has_headers(false) // Important: no headers since we're mid-file
-                            .from_reader(reader);
-
-                        // Read headers first to know field names
-                        // We need to get the headers from the beginning of the file
-                        let headers = {
-                            let header_file = match File::open(path) {
-                                Ok(f) => f,
-                                Err(e) => {
-                                    return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(
-                                        format!("Failed to open file for headers: {}", e),
-                                    ));
-                                }
-                            };
+        while scanned < self.max_rows() && self.next_record(py, &mut reader, &layout, &mut buf)? {
+            scanned += 1;
+            self.check_signals(py, scanned)?;
 
-                            let mut header_reader = ReaderBuilder::new()
-                                .has_headers(true)
-                                .from_reader(header_file);
+            if !buffered_field(&layout, &buf, index).is_some_and(|f| keys.contains(f)) {
+                continue;
+            }
 
-                            match header_reader.headers() {
-                                Ok(h) => h.clone(),
-                                Err(e) => {
-                                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                                        format!("Failed to read CSV headers: {}", e),
-                                    ));
-                                }
-                            }
-                        };
+            current.append(self.buffered_row(py, &layout, &buf)?)?;
+            matched += 1;
+            if batched && current.len() >= self.batch_size {
+                batches.push(current.to_object(py));
+                current = PyList::empty(py);
+            }
+        }
+        self.enforce_row_limit(&mut reader, scanned)?;
 
-                        // Now read records from our seeked position
-                        let chunk = PyList::empty(py);
-                        let mut current_row = 0;
-
-                        for result in csv_reader.records().take(num_rows) {
-                            let record = match result {
-                                Ok(r) => r,
-                                Err(e) => {
-                                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                                        format!("Failed to read CSV record: {}", e),
-                                    ));
-                                }
-                            };
+        self.log_event(
+            py,
+            "where_in",
+            format!(
+                "Matched {} of {} rows ({:.1} ms)",
+                matched,
+                scanned,
+                started.elapsed().as_secs_f64() * 1000.0
+            ),
+            &[
+                ("scanned", scanned.to_object(py)),
+                ("matched", matched.to_object(py)),
+            ],
+        )?;
 
-                            let row = PyDict::new(py);
+        if !batched {
+            return Ok(current.to_object(py));
+        }
+        if !current.is_empty() {
+            batches.push(current.to_object(py));
+        }
+        Ok(batches.to_object(py))
+    }
 
-                            for (i, field) in record.iter().enumerate() {
-                                if i < headers.len() {
-                                    let header = headers.get(i).unwrap_or("None");
-                                    row.set_item(header, field)?;
-                                }
-                            }
+    // Run a prepared Query (see query.rs) against this file in one streaming
+    // pass: each record is tested on the filter columns first and only rows
+    // that pass become dicts, projected and typed as the query says, on
+    // top of the parser's other options. Raises ValueError naming the file
+    // and column if the header lacks a column the query needs. The scanned
+    // and matched counts are logged as a "query" event.
+    fn execute(&self, py: Python, query: PyRef<'_, Query>) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        for column in query.required_columns() {
+            if !headers.iter().any(|h| h == column) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{} has no column {}, which the query needs",
+                    self.filename, column
+                )));
+            }
+        }
+        let filters: Vec<(usize, &Predicate)> = query
+            .filters
+            .iter()
+            .map(|f| {
+                let index = headers.iter().position(|h| h == f.column).unwrap();
+                (index, &f.predicate)
+            })
+            .collect();
+        let layout = self.query_layout(py, &headers, Some(&*query))?;
+        let started = Instant::now();
 
-                            let _ = chunk.append(row.to_object(py))?;
-                            current_row += 1;
+        let mut batches: Vec<PyObject> = Vec::new();
+        let mut current = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        let mut scanned: usize = 0;
+        let mut matched: usize = 0;
 
-                            if current_row >= num_rows {
-                                break;
-                            }
-                        }
+        while scanned < self.max_rows() && self.next_record(py, &mut reader, &layout, &mut buf)? {
+            scanned += 1;
+            self.check_signals(py, scanned)?;
 
-                        return Ok(chunk.to_object(py));
-                    }
+            let keep = filters.iter().all(|(index, predicate)| {
+                buffered_field(&layout, &buf, *index).is_some_and(|f| predicate.matches(f))
+            });
+            if !keep {
+                continue;
+            }
+
+            current.append(self.buffered_row(py, &layout, &buf)?)?;
+            matched += 1;
+            if query.output == QueryOutput::Batches && current.len() >= self.batch_size {
+                batches.push(current.to_object(py));
+                current = PyList::empty(py);
+            }
+        }
+        self.enforce_row_limit(&mut reader, scanned)?;
+
+        self.log_event(
+            py,
+            "query",
+            format!(
+                "Matched {} of {} rows ({:.1} ms)",
+                matched,
+                scanned,
+                started.elapsed().as_secs_f64() * 1000.0
+            ),
+            &[
+                ("scanned", scanned.to_object(py)),
+                ("matched", matched.to_object(py)),
+            ],
+        )?;
+
+        match query.output {
+            QueryOutput::Rows => Ok(current.to_object(py)),
+            QueryOutput::Batches => {
+                if !current.is_empty() {
+                    batches.push(current.to_object(py));
                 }
+                Ok(batches.to_object(py))
+            }
+            QueryOutput::Columns(format) => {
+                let convert = query.convert.as_ref().map(|c| c.clone_ref(py));
+                let columnar = ColumnarBatches::new(py, format, convert, &layout)?;
+                columnar.convert(py, self, current, None)
             }
         }
+    }
 
-        // Fallback: read row-by-row until we reach start_row
-        let file = match File::open(path) {
-            Ok(f) => BufReader::with_capacity(BUF_SIZE, f),
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
+    // A lookup table: one dict mapping each row's `column` cell text to its
+    // row dict, built in one pass. Keys are the cell text as the row holds
+    // it before conversion (see cleaned_field); a row too short to have
+    // the column is keyed by "". `subset` keeps only those columns in each
+    // value.
+    // `duplicates` decides what a repeated key does (see Duplicates).
+    // output="offsets" maps keys to the byte offset of each row's record
+    // instead, for read_rows_at(), without building row dicts, so memory
+    // stays at a key and an int per row.
+    #[pyo3(signature = (column, duplicates="error", subset=None, output="rows"))]
+    fn read_keyed(
+        &self,
+        py: Python,
+        column: &str,
+        duplicates: &str,
+        subset: Option<Vec<String>>,
+        output: &str,
+    ) -> PyResult<PyObject> {
+        let duplicates = Duplicates::from_name(duplicates)?;
+        let offsets = match output {
+            "rows" => false,
+            "offsets" => true,
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "output must be \"rows\" or \"offsets\", not {:?}",
+                    other
                 )));
             }
         };
 
-        let mut reader = ReaderBuilder::new()
-            .has_headers(self.has_headers)
-            .from_reader(file);
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, column)?;
+        // The subset's header positions, so renamed and duplicate-named
+        // columns resolve as they do in the row
+        let picks = match &subset {
+            Some(names) => {
+                check_columns(&headers, names, "subset")?;
+                Some(hash_indices(&headers, Some(names))?)
+            }
+            None => None,
+        };
+        let layout = self.row_layout(py, &headers)?;
 
-        let headers = match reader.headers() {
-            Ok(h) => h.clone(),
-            Err(e) => {
+        let table = PyDict::new(py);
+        let mut buf = RecordBuf::default();
+        let mut rows: usize = 0;
+        while rows < self.max_rows() && self.next_record(py, &mut reader, &layout, &mut buf)? {
+            let row_number = rows;
+            rows += 1;
+            self.check_signals(py, rows)?;
+
+            let key = buffered_field(&layout, &buf, index).unwrap_or("");
+            let key = self.cleaned_field(&layout, index, key);
+            let key = key.as_ref();
+            let position = if layout.sparse {
+                buf.bytes.position()
+            } else {
+                buf.record.position()
+            };
+            let existing = table.get_item(key);
+            match (duplicates, existing) {
+                (Duplicates::First, Some(_)) => continue,
+                (Duplicates::Error, Some(_)) => {
+                    let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Duplicate key {:?} in column {} at row {}",
+                        key, column, row_number
+                    ));
+                    err.value(py).setattr("key", key)?;
+                    err.value(py).setattr("row", row_number)?;
+                    return Err(self.with_raw(py, err, position));
+                }
+                _ => {}
+            }
+
+            let value = if offsets {
+                position.map_or(0, |p| p.byte()).to_object(py)
+            } else {
+                let row = self.buffered_row(py, &layout, &buf)?;
+                match &picks {
+                    None => row.to_object(py),
+                    Some(indices) => {
+                        let picked = PyDict::new(py);
+                        for &i in indices {
+                            if let Some(value) = row.get_item(&layout.keys[i]) {
+                                picked.set_item(&layout.keys[i], value)?;
+                            }
+                        }
+                        picked.to_object(py)
+                    }
+                }
+            };
+            match (duplicates, existing) {
+                (Duplicates::List, Some(list)) => list.downcast::<PyList>()?.append(value)?,
+                (Duplicates::List, None) => table.set_item(key, PyList::new(py, [value]))?,
+                _ => table.set_item(key, value)?,
+            }
+        }
+        self.enforce_row_limit(&mut reader, rows)?;
+
+        Ok(table.to_object(py))
+    }
+
+    // Rows of this file joined with the file at `other_path` on column
+    // `on`: each row dict gets the matching row's `other_columns` (all of
+    // its columns but `on` by default) as cell text. The other file must
+    // have a header row; it is loaded into a map by key first, without the
+    // GIL, so it should be the smaller one, and this file is then streamed.
+    // A key matching several rows gives one joined row per match, in their
+    // file order. how="inner" drops rows without a match; how="left" keeps
+    // them, with None for the other file's columns.
+    #[pyo3(signature = (other_path, on, how="inner", other_columns=None))]
+    fn join(
+        &self,
+        py: Python,
+        other_path: &str,
+        on: &str,
+        how: &str,
+        other_columns: Option<Vec<String>>,
+    ) -> PyResult<PyObject> {
+        let keep_unmatched = match how {
+            "inner" => false,
+            "left" => true,
+            other => {
                 return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                    "Failed to read CSV headers: {}",
-                    e
+                    "how must be \"inner\" or \"left\", not {:?}",
+                    other
                 )));
             }
         };
 
-        let chunk = PyList::empty(py);
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, on)?;
+        let layout = self.row_layout(py, &headers)?;
+        let cancel = self.cancel();
+        let joined = files::without_gil(py, || {
+            self.join_table(other_path, on, other_columns, &cancel)
+        });
+        cancel.raise()?;
+        let (names, matches) = joined?;
 
-        // Skip rows until start_row
-        let mut records = reader.records();
-        for _ in 0..start_row {
-            if records.next().is_none() {
-                // Reached end of file before start_row
-                return Ok(chunk.to_object(py));
-            }
+        let keys = column_names(py, &layout)
+            .iter()
+            .map(|k| k.extract::<String>(py))
+            .collect::<PyResult<HashSet<_>>>()?;
+        if let Some(name) = names.iter().find(|n| keys.contains(*n)) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Column {} is in both files; leave it out with other_columns",
+                name
+            )));
         }
+        let names: Vec<PyObject> = names.iter().map(|n| n.to_object(py)).collect();
 
-        // Read num_rows rows
-        for _ in 0..num_rows {
-            match records.next() {
-                Some(Ok(record)) => {
-                    let row = PyDict::new(py);
-
-                    for (i, field) in record.iter().enumerate() {
-                        if i < headers.len() {
-                            let header = headers.get(i).unwrap_or("None");
-                            row.set_item(header, field)?;
+        let joined = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        let mut rows: usize = 0;
+        while rows < self.max_rows() && self.next_record(py, &mut reader, &layout, &mut buf)? {
+            rows += 1;
+            self.check_signals(py, rows)?;
+
+            let key = buffered_field(&layout, &buf, index).unwrap_or("");
+            match matches.get(key) {
+                Some(found) => {
+                    for cells in found {
+                        let row = self.buffered_row(py, &layout, &buf)?;
+                        for (name, cell) in names.iter().zip(cells) {
+                            row.set_item(name, cell)?;
                         }
+                        joined.append(row)?;
                     }
-
-                    let _ = chunk.append(row.to_object(py))?;
                 }
-                Some(Err(e)) => {
-                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                        "Failed to read CSV record: {}",
-                        e
-                    )));
+                None if keep_unmatched => {
+                    let row = self.buffered_row(py, &layout, &buf)?;
+                    for name in &names {
+                        row.set_item(name, py.None())?;
+                    }
+                    joined.append(row)?;
                 }
-                None => break, // End of file
+                None => {}
             }
         }
+        self.enforce_row_limit(&mut reader, rows)?;
 
-        Ok(chunk.to_object(py))
+        Ok(joined.to_object(py))
     }
 
-    // Helper method to estimate bytes per row
-    fn estimate_bytes_per_row(&self) -> PyResult<f64> {
-        let path = Path::new(&self.filename);
-        let file = match File::open(path) {
-            Ok(f) => f,
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
-                )));
-            }
-        };
+    // Parse the file and extend `existing`, a {column: list} dict, with its
+    // rows, for accumulating several files into one columnar structure.
+    // The columns are the keys read() rows would have, in that order (plus
+    // "_hash" with include_row_hash, and the add_row_number key); an
+    // empty dict gets one list per column, any other must have exactly
+    // those keys in that order. Cells a row lacks (short rows, or empty
+    // cells with sparse=True) are None. The file is parsed in full before
+    // anything is appended, so an error leaves `existing` unchanged.
+    // Returns the number of rows appended.
+    fn append_to_columns(&self, py: Python, existing: &PyDict) -> PyResult<usize> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
+        let names = column_names(py, &layout);
 
-        let mut reader = BufReader::with_capacity(BUF_SIZE, file);
-        let start_pos = match reader.stream_position() {
-            Ok(pos) => pos,
-            Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to get stream position: {}",
-                    e
+        let targets: Vec<&PyList> = if existing.is_empty() {
+            Vec::new()
+        } else {
+            let found = existing.keys();
+            let expected = PyList::new(py, &names);
+            if !found.eq(expected)? {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Columns don't match: existing has {}, file has {}",
+                    found.repr()?,
+                    expected.repr()?
                 )));
             }
+            existing
+                .values()
+                .iter()
+                .zip(&names)
+                .map(|(list, name)| {
+                    list.downcast::<PyList>().map_err(|_| {
+                        PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                            "Column {} is a {}, not a list",
+                            name,
+                            list.get_type().name().unwrap_or("?")
+                        ))
+                    })
+                })
+                .collect::<PyResult<_>>()?
         };
 
-        // Create a CSV reader that will read from our buffered reader
-        let mut csv_reader = ReaderBuilder::new()
-            .has_headers(self.has_headers)
-            .from_reader(reader.by_ref());
+        let columns: Vec<&PyList> = names.iter().map(|_| PyList::empty(py)).collect();
+        let mut buf = RecordBuf::default();
+        let mut rows: usize = 0;
+        while rows < self.max_rows() {
+            let row = match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                Some(row) => row,
+                None => break,
+            };
+            append_row(py, &columns, &names, row)?;
+            rows += 1;
+            self.check_signals(py, rows)?;
+        }
+        self.enforce_row_limit(&mut reader, rows)?;
 
-        // Skip header if needed
-        if self.has_headers {
-            if csv_reader.headers().is_err() {
-                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                    "Failed to read headers".to_string(),
-                ));
+        if targets.is_empty() {
+            for (name, column) in names.iter().zip(columns) {
+                existing.set_item(name, column)?;
+            }
+        } else {
+            for (target, column) in targets.iter().zip(columns) {
+                target.call_method1("extend", (column,))?;
             }
         }
+        Ok(rows)
+    }
 
-        // Count bytes for sample rows
-        let sample_size = 100;
-        let mut row_count = 0;
+    // Iterate over rows one dict at a time
+    fn iter_rows(slf: PyRef<'_, Self>) -> PyResult<RowIterator> {
+        let timings = slf.start_timings();
+        let file = slf.open_timed(timings.as_ref())?;
+        let watch = file.get_ref().watch();
+        let mut reader = slf.reader_builder().from_reader(file);
+        let headers = slf.read_headers(&mut reader)?;
+        let mut layout = slf.row_layout(slf.py(), &headers)?;
+        layout.timings = timings;
 
-        for _ in 0..sample_size {
-            match csv_reader.records().next() {
-                Some(Ok(_)) => row_count += 1,
-                Some(Err(e)) => {
+        Ok(RowIterator {
+            parser: slf.into(),
+            reader: Some(reader),
+            layout,
+            buf: RecordBuf::default(),
+            rows: 0,
+            watch,
+            truncated: false,
+        })
+    }
+
+    // `for row in parser:` behaves like iter_rows(), which together with
+    // compat="dictreader" makes the parser a drop-in csv.DictReader
+    fn __iter__(slf: PyRef<'_, Self>) -> PyResult<RowIterator> {
+        Self::iter_rows(slf)
+    }
+
+    // Lazily yield one batch list at a time. Only the current batch is ever
+    // materialized, so this works for files too big to read() at once.
+    // `format` picks what each batch is: "rows" a list of row dicts,
+    // "columns" a {column: list} dict as append_to_columns() builds,
+    // "pandas" a DataFrame and "arrow" a pyarrow RecordBatch made from
+    // those columns. pandas and pyarrow are imported only when asked for.
+    #[pyo3(signature = (format="rows"))]
+    fn lazy_batches(slf: PyRef<'_, Self>, format: &str) -> PyResult<BatchIterator> {
+        let py = slf.py();
+        let format = match format {
+            "rows" => None,
+            other => match BatchFormat::from_name(other) {
+                Some(format) => Some((format, batch_converter(py, format)?)),
+                None => {
                     return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                        "Error reading sample row: {}",
-                        e
+                        "format must be \"rows\", \"columns\", \"pandas\" or \"arrow\", not {:?}",
+                        other
                     )));
                 }
-                None => break, // End of file
+            },
+        };
+
+        let timings = slf.start_timings();
+        let file = slf.open_timed(timings.as_ref())?;
+        let watch = file.get_ref().watch();
+        let mut reader = slf.reader_builder().from_reader(file);
+        // Header is resolved once, up front
+        let headers = slf.read_headers(&mut reader)?;
+        let mut layout = slf.row_layout(py, &headers)?;
+        layout.timings = timings;
+        let columnar = match format {
+            Some((format, convert)) => Some(ColumnarBatches::new(py, format, convert, &layout)?),
+            None => None,
+        };
+
+        Ok(BatchIterator {
+            parser: slf.into(),
+            reader: Some(reader),
+            layout,
+            columnar,
+            buf: RecordBuf::default(),
+            done: false,
+            batches: 0,
+            rows: 0,
+            started: Instant::now(),
+            watch,
+            truncated: false,
+            batch_timings: None,
+        })
+    }
+
+    // Advanced chunk reading with seeking optimization.
+    // For start_row > 1000 this seeks to a position estimated from the average
+    // row width. seek_strategy="auto" counts which row the seek landed on
+    // (see landing_skip) and skips on from there to start_row, or reads
+    // exactly when it landed too far off; "estimate" trusts the seek, so
+    // the rows returned are only approximately the requested ones; "exact"
+    // never seeks.
+    fn read_chunk_optimized(
+        &self,
+        py: Python,
+        start_row: usize,
+        num_rows: usize,
+    ) -> PyResult<PyObject> {
+        // If we're starting far into the file, try to estimate the position
+        // and seek to it before reading to avoid processing unnecessary rows.
+        // The seek lands on a record boundary found by the quote-aware
+        // RecordScanner. Files with mixed line endings still always take
+        // the exact path, as line_ending_report() promises.
+        // An estimated landing row can't honor row_limit or
+        // stop_at_blank_line, or number rows, so those cases are exact too,
+        // as is chunk_unit="lines", which the estimate doesn't count in.
+        // A ZIP entry, or a compressed file without a block index, can only
+        // seek by reading up to the target, so there is nothing to gain.
+        if start_row > 1000
+            && self.seek_strategy != SeekStrategy::Exact
+            && !self.slow_seeks()
+            && !self.mixed_line_endings.load(Ordering::Relaxed)
+            && self.row_limit.is_none()
+            && self.data_end.is_none()
+            && self.add_row_number.is_none()
+            && self.chunk_unit == ChunkUnit::Records
+        {
+            // Use the file size to estimate bytes per row
+            let data_size = self.data_size()?;
+            if data_size > 0 {
+                // First estimate bytes per row by sampling
+                let estimated_bytes_per_row = self.estimate_bytes_per_row()?;
+
+                if estimated_bytes_per_row > 0.0 {
+                    // Estimate position for start_row
+                    let header_offset = if self.has_headers {
+                        estimated_bytes_per_row
+                    } else {
+                        0.0
+                    };
+                    let estimated_pos =
+                        (estimated_bytes_per_row * start_row as f64) + header_offset;
+
+                    if estimated_pos < data_size as f64 {
+                        // Seek to slightly before estimated position to ensure we don't miss a row
+                        let safe_pos =
+                            (estimated_pos - estimated_bytes_per_row * 2.0).max(0.0) as u64;
+
+                        // Move forward to the next record boundary; if it
+                        // can't be found, fall back to the exact path below.
+                        // auto then counts the rows between the landing and
+                        // start_row, or falls back if it landed too far off.
+                        let landing = match self.record_start_after(safe_pos)? {
+                            Some(pos) if self.seek_strategy == SeekStrategy::Estimate => {
+                                Some((pos, 0))
+                            }
+                            Some(pos) => self
+                                .landing_skip(py, pos, start_row)?
+                                .map(|skip| (pos, skip)),
+                            None => None,
+                        };
+                        if let Some((pos, skip)) = landing {
+                            let reader = self.open_buffered()?;
+
+                            // Create new reader from this position
+                            let mut csv_reader = self
+                                .reader_builder()
+                                .has_headers(false) // Important: no headers since we're mid-file
+                                .from_reader(reader);
+
+                            // Seek to our calculated position, keeping record
+                            // positions relative to the start of the file
+                            let mut start = csv::Position::new();
+                            start.set_byte(pos);
+                            if let Err(e) = csv_reader.seek_raw(SeekFrom::Start(pos), start) {
+                                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                                    "Failed to seek in file: {}",
+                                    e
+                                )));
+                            }
+                            let mut record = ByteRecord::new();
+                            for skipped in 0..skip {
+                                match csv_reader.read_byte_record(&mut record) {
+                                    Ok(true) => self.check_signals(py, skipped + 1)?,
+                                    Ok(false) => break,
+                                    Err(e) => return Err(self.record_error(py, e)),
+                                }
+                            }
+
+                            // Read headers first to know field names
+                            // We need to get the headers from the beginning of the file
+                            let headers = {
+                                let header_file = self.open_input()?;
+
+                                let mut header_reader =
+                                    self.reader_builder().from_reader(header_file);
+                                self.read_headers(&mut header_reader)?
+                            };
+                            let layout = self.row_layout(py, &headers)?;
+
+                            // Now read records from our seeked position
+                            let chunk = PyList::empty(py);
+
+                            // Rows count from start_row, for schema_drift's
+                            // errors; with seek_strategy="estimate" that is
+                            // the row the landing is taken to be
+                            let mut buf = RecordBuf {
+                                next_row: start_row,
+                                ..Default::default()
+                            };
+                            while chunk.len() < num_rows {
+                                match self.next_row(py, &mut csv_reader, &layout, &mut buf)? {
+                                    Some(row) => chunk.append(row)?,
+                                    None => break,
+                                }
+                            }
+
+                            // A short chunk means the file ended first,
+                            // either because the request runs past the
+                            // last row or because the estimate overshot.
+                            // Only the exact path can tell which, so let
+                            // it redo the read rather than return too few.
+                            if chunk.len() == num_rows {
+                                return Ok(chunk.to_object(py));
+                            }
+                        }
+                    }
+                }
             }
         }
 
-        // Get the current position after reading sample rows
-        let end_pos = match reader.stream_position() {
-            Ok(pos) => pos,
+        // Fallback: read row-by-row until we reach start_row
+        self.read_chunk_exact(py, self.chunk_unit, start_row, num_rows)
+    }
+
+    // Sample the start of the file and count each line ending style.
+    // Returns {"lf", "crlf", "cr", "mixed", "sampled_bytes"}. When more than
+    // one style is present a warning is emitted and read_chunk_optimized
+    // stops seeking by byte estimate for this parser.
+    fn line_ending_report(&self, py: Python) -> PyResult<PyObject> {
+        let file = BufReader::with_capacity(BUF_SIZE, self.open_uncut()?);
+        let mut sample = Vec::with_capacity(LINE_ENDING_SAMPLE.min(self.file_size as usize));
+        if let Err(e) = file
+            .take(LINE_ENDING_SAMPLE as u64)
+            .read_to_end(&mut sample)
+        {
+            return Err(files::io_error("read file", e));
+        }
+
+        let (lf, crlf, cr) = self.record_scanner().count_line_endings(&sample);
+        let styles = [lf, crlf, cr].iter().filter(|&&n| n > 0).count();
+        let mixed = styles > 1;
+
+        if mixed {
+            self.mixed_line_endings.store(true, Ordering::Relaxed);
+            let message = format!(
+                "Mixed line endings in {} (lf={}, crlf={}, cr={}); \
+                 read_chunk_optimized will use exact row skipping",
+                self.filename, lf, crlf, cr
+            );
+            self.log_event(py, "warning", message.clone(), &[])?;
+            PyErr::warn(
+                py,
+                py.get_type::<pyo3::exceptions::PyUserWarning>(),
+                &message,
+                1,
+            )?;
+        }
+
+        let report = PyDict::new(py);
+        report.set_item("lf", lf)?;
+        report.set_item("crlf", crlf)?;
+        report.set_item("cr", cr)?;
+        report.set_item("mixed", mixed)?;
+        report.set_item("sampled_bytes", sample.len())?;
+
+        Ok(report.to_object(py))
+    }
+
+    // Compute several statistics over several columns in a single streaming
+    // pass. `requests` maps a column name to a list of stat names ("count",
+    // "nulls", "sum", "mean", "min", "max", "value_counts",
+    // "approx_distinct"); the key "*" accepts only "count" and counts rows.
+    // Returns {column: {stat: value}}. Empty cells are nulls; numeric stats
+    // skip cells that don't parse as numbers.
+    fn analyze(&self, py: Python, requests: HashMap<String, Vec<String>>) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+
+        let mut columns: Vec<(String, usize, ColumnAccumulator)> = Vec::new();
+        let mut count_rows = false;
+        for (column, names) in &requests {
+            let mut wanted = Vec::with_capacity(names.len());
+            for name in names {
+                match Stat::parse(name) {
+                    Some(Stat::Count) if column == "*" => count_rows = true,
+                    Some(stat) if column != "*" => wanted.push(stat),
+                    _ => {
+                        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                            "Unsupported statistic for {}: {}",
+                            column, name
+                        )));
+                    }
+                }
+            }
+            if column == "*" {
+                continue;
+            }
+
+            let index = self.column_index(&headers, column)?;
+            columns.push((column.clone(), index, ColumnAccumulator::new(wanted)));
+        }
+
+        // Single pass, fed straight from the tokenizer without the GIL
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let scanned = files::without_gil(py, || -> csv::Result<usize> {
+            let mut record = StringRecord::new();
+            let mut rows: usize = 0;
+            while rows < max_rows && !cancel.check(rows) && reader.read_record(&mut record)? {
+                rows += 1;
+                for (_, index, acc) in columns.iter_mut() {
+                    acc.push(record.get(*index));
+                }
+            }
+            Ok(rows)
+        });
+        cancel.raise()?;
+        let rows = match scanned {
+            Ok(rows) => rows,
             Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to get stream position: {}",
-                    e
-                )));
+                return Err(self.record_error(py, e));
             }
         };
+        self.enforce_row_limit(&mut reader, rows)?;
 
-        if row_count > 0 {
-            Ok((end_pos - start_pos) as f64 / row_count as f64)
-        } else {
-            // If we couldn't read any rows, return a default value
-            Ok(100.0) // Default guess: 100 bytes per row
+        let results = PyDict::new(py);
+        if count_rows {
+            let all = PyDict::new(py);
+            all.set_item("count", rows)?;
+            results.set_item("*", all)?;
         }
+        for (column, _, acc) in &columns {
+            let out = PyDict::new(py);
+            for stat in &acc.stats {
+                let value = match stat {
+                    Stat::Count => acc.count.to_object(py),
+                    Stat::Nulls => acc.nulls.to_object(py),
+                    Stat::Sum => acc.numeric.sum.to_object(py),
+                    Stat::Mean => acc.numeric.mean().to_object(py),
+                    Stat::Min => acc.numeric.min.to_object(py),
+                    Stat::Max => acc.numeric.max.to_object(py),
+                    Stat::ValueCounts => acc.value_counts.to_object(py),
+                    Stat::ApproxDistinct => {
+                        acc.distinct.as_ref().map(|h| h.estimate()).to_object(py)
+                    }
+                };
+                out.set_item(stat.name(), value)?;
+            }
+            results.set_item(column, out)?;
+        }
+
+        Ok(results.to_object(py))
     }
 
-    // New method: get file information
-    fn get_file_info(&self, py: Python) -> PyResult<PyObject> {
-        let path = Path::new(&self.filename);
-        let metadata = match std::fs::metadata(path) {
-            Ok(m) => m,
+    // Estimate the q-th quantile (0 <= q <= 1) of a numeric column in one
+    // pass. With approximate=True a t-digest keeps memory bounded; with
+    // approximate=False all values are collected and sorted, which is exact
+    // but needs memory for the whole column. Empty cells are ignored and
+    // non-numeric cells are counted. Returns {"value", "count", "skipped"}.
+    #[pyo3(signature = (column, q, approximate=true))]
+    fn quantile(&self, py: Python, column: &str, q: f64, approximate: bool) -> PyResult<PyObject> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "q must be between 0 and 1, got {}",
+                q
+            )));
+        }
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, column)?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let scanned = files::without_gil(py, || -> csv::Result<(QuantileAccumulator, usize)> {
+            let mut acc = QuantileAccumulator::new(approximate);
+            let mut record = StringRecord::new();
+            let mut rows: usize = 0;
+            while rows < max_rows && !cancel.check(rows) && reader.read_record(&mut record)? {
+                rows += 1;
+                acc.push(record.get(index));
+            }
+            Ok((acc, rows))
+        });
+        cancel.raise()?;
+        let acc = match scanned {
+            Ok((acc, rows)) => {
+                self.enforce_row_limit(&mut reader, rows)?;
+                acc
+            }
             Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to get file metadata: {}",
-                    e
-                )));
+                return Err(self.record_error(py, e));
             }
         };
 
-        let info = PyDict::new(py);
-        info.set_item("filename", &self.filename)?;
-        info.set_item("size_bytes", metadata.len())?;
-        info.set_item("size_mb", (metadata.len() as f64) / (1024.0 * 1024.0))?;
-        info.set_item("batch_size", self.batch_size)?;
-        info.set_item("has_headers", self.has_headers)?;
+        let report = PyDict::new(py);
+        report.set_item("count", acc.count)?;
+        report.set_item("skipped", acc.skipped)?;
+        report.set_item("value", acc.quantile(q))?;
+        Ok(report.to_object(py))
+    }
 
-        // Try to get sample headers
-        let file = match File::open(path) {
-            Ok(f) => f,
+    // Pearson correlation between numeric columns `column_a` and
+    // `column_b` in one pass, without the GIL. Rows where either cell is
+    // empty are ignored, and rows where either isn't a number are counted.
+    // Returns {"value", "count", "skipped"}; value is None with fewer than
+    // two numeric pairs or when either column is constant.
+    fn correlation(&self, py: Python, column_a: &str, column_b: &str) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index_a = self.column_index(&headers, column_a)?;
+        let index_b = self.column_index(&headers, column_b)?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let scanned = files::without_gil(py, || -> csv::Result<(CorrelationAccumulator, usize)> {
+            let mut acc = CorrelationAccumulator::default();
+            let mut record = StringRecord::new();
+            let mut rows: usize = 0;
+            while rows < max_rows && !cancel.check(rows) && reader.read_record(&mut record)? {
+                rows += 1;
+                acc.push(record.get(index_a), record.get(index_b));
+            }
+            Ok((acc, rows))
+        });
+        cancel.raise()?;
+        let acc = match scanned {
+            Ok((acc, rows)) => {
+                self.enforce_row_limit(&mut reader, rows)?;
+                acc
+            }
             Err(e) => {
-                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                    "Failed to open file: {}",
-                    e
-                )));
+                return Err(self.record_error(py, e));
             }
         };
 
-        let mut reader = ReaderBuilder::new()
-            .has_headers(self.has_headers)
-            .from_reader(file);
+        let report = PyDict::new(py);
+        report.set_item("count", acc.count)?;
+        report.set_item("skipped", acc.skipped)?;
+        report.set_item("value", acc.correlation())?;
+        Ok(report.to_object(py))
+    }
 
-        if self.has_headers {
-            match reader.headers() {
+    // A numeric column as an array.array: typecode "q" parses each cell as
+    // an i64, "d" as an f64. Eight bytes per value instead of a Python
+    // object each, and no numpy needed. A cell that doesn't parse (empty
+    // cells included) raises ValueError with `row` and `raw` attributes,
+    // or with invalid="skip" is left out of the array.
+    #[pyo3(signature = (column, typecode, invalid="error"))]
+    fn read_column_array(
+        &self,
+        py: Python,
+        column: &str,
+        typecode: &str,
+        invalid: &str,
+    ) -> PyResult<PyObject> {
+        let integers = match typecode {
+            "q" => true,
+            "d" => false,
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "typecode must be \"q\" or \"d\", not {:?}",
+                    other
+                )));
+            }
+        };
+        let skip_invalid = match invalid {
+            "error" => false,
+            "skip" => true,
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "invalid must be \"error\" or \"skip\", not {:?}",
+                    other
+                )));
+            }
+        };
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let index = self.column_index(&headers, column)?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        // Values in native byte order, ready for array.frombytes()
+        let scanned =
+            files::without_gil(py, || -> csv::Result<(Vec<u8>, usize, Option<BadCell>)> {
+                let mut values = Vec::new();
+                let mut record = StringRecord::new();
+                let mut rows: usize = 0;
+                while rows < max_rows && !cancel.check(rows) && reader.read_record(&mut record)? {
+                    let field = record.get(index).unwrap_or("").trim();
+                    let parsed = if integers {
+                        field.parse::<i64>().ok().map(i64::to_ne_bytes)
+                    } else {
+                        field.parse::<f64>().ok().map(f64::to_ne_bytes)
+                    };
+                    match parsed {
+                        Some(bytes) => values.extend_from_slice(&bytes),
+                        None if skip_invalid => {}
+                        None => {
+                            let bad = BadCell {
+                                row: rows,
+                                value: field.to_string(),
+                                position: record.position().cloned(),
+                            };
+                            return Ok((values, rows, Some(bad)));
+                        }
+                    }
+                    rows += 1;
+                }
+                Ok((values, rows, None))
+            });
+        cancel.raise()?;
+        let values = match scanned {
+            Ok((_, _, Some(bad))) => {
+                let converter = if integers {
+                    Converter::Int
+                } else {
+                    Converter::Float
+                };
+                let context = ConversionError::new(converter, &bad.value);
+                let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Invalid {} {:?} in column {} at row {} (stopped at character {})",
+                    converter.name(),
+                    bad.value,
+                    column,
+                    bad.row,
+                    context.stopped_at
+                ));
+                err.value(py).setattr("row", bad.row)?;
+                context.attach(py, &err)?;
+                return Err(self.with_raw(py, err, bad.position.as_ref()));
+            }
+            Ok((values, rows, None)) => {
+                self.enforce_row_limit(&mut reader, rows)?;
+                values
+            }
+            Err(e) => {
+                return Err(self.record_error(py, e));
+            }
+        };
+
+        let array = py.import("array")?.getattr("array")?.call1((typecode,))?;
+        array.call_method1("frombytes", (PyBytes::new(py, &values),))?;
+        Ok(array.to_object(py))
+    }
+
+    // Fingerprint of every row in file order, as 16-digit hex strings. Uses
+    // the same canonical form as include_row_hash (see row_hash), over
+    // `columns` in the order given, or over all columns.
+    #[pyo3(signature = (columns=None))]
+    fn hash_rows(&self, py: Python, columns: Option<Vec<String>>) -> PyResult<Vec<String>> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let indices = hash_indices(&headers, columns.as_ref())?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let scanned = files::without_gil(py, || -> csv::Result<Vec<String>> {
+            let mut hashes = Vec::new();
+            let mut record = StringRecord::new();
+            while hashes.len() < max_rows
+                && !cancel.check(hashes.len())
+                && reader.read_record(&mut record)?
+            {
+                hashes.push(format!("{:016x}", row_hash(&record, &indices)));
+            }
+            Ok(hashes)
+        });
+        cancel.raise()?;
+
+        match scanned {
+            Ok(hashes) => {
+                self.enforce_row_limit(&mut reader, hashes.len())?;
+                Ok(hashes)
+            }
+            Err(e) => Err(self.record_error(py, e)),
+        }
+    }
+
+    // Each distinct row once, with how many times it appears, as
+    // (row, count) tuples in order of first appearance. With `subset`, rows
+    // are compared on those columns only and the returned dicts hold just
+    // them. Rows are grouped by their row_hash fingerprint, and rows whose
+    // fingerprints match are told apart by their compared fields, which
+    // each group keeps; memory grows with the number of distinct rows, not
+    // the file size.
+    #[pyo3(signature = (subset=None))]
+    fn unique_rows_with_counts(
+        &self,
+        py: Python,
+        subset: Option<Vec<String>>,
+    ) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        if let Some(names) = &subset {
+            check_columns(&headers, names, "subset")?;
+        }
+        let indices = hash_indices(&headers, subset.as_ref())?;
+        let layout = self.row_layout(py, &headers)?;
+
+        // Fingerprint -> positions in `groups` of the rows with it, more
+        // than one only if fingerprints collide
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        // (row, count, compared fields), in first-seen order
+        let mut groups: Vec<(PyObject, u64, Vec<String>)> = Vec::new();
+        let mut record = StringRecord::new();
+        let mut rows: usize = 0;
+
+        while rows < self.max_rows() {
+            match reader.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Err(self.record_error(py, e)),
+            }
+            rows += 1;
+            self.check_signals(py, rows)?;
+
+            let fields = || indices.iter().map(|&i| record.get(i).unwrap_or(""));
+            let same_hash = seen.entry(row_hash(&record, &indices)).or_default();
+            let found = same_hash
+                .iter()
+                .find(|&&group| groups[group].2.iter().map(String::as_str).eq(fields()));
+            if let Some(&group) = found {
+                groups[group].1 += 1;
+                continue;
+            }
+
+            let row = self.build_row(py, &layout, &record)?;
+            if let Some(key) = &layout.row_number {
+                row.set_item(key, rows - 1 + self.row_number_start)?;
+            }
+            // The subset's columns, picked by position so renamed and
+            // duplicate-named columns resolve as they do in the row
+            let representative = match &subset {
+                None => row,
+                Some(_) => {
+                    let key = PyDict::new(py);
+                    for &i in &indices {
+                        if let Some(value) = row.get_item(&layout.keys[i]) {
+                            key.set_item(&layout.keys[i], value)?;
+                        }
+                    }
+                    key
+                }
+            };
+            same_hash.push(groups.len());
+            groups.push((
+                representative.to_object(py),
+                1,
+                fields().map(String::from).collect(),
+            ));
+        }
+        self.enforce_row_limit(&mut reader, rows)?;
+
+        let counted: Vec<(PyObject, u64)> =
+            groups.into_iter().map(|(row, n, _)| (row, n)).collect();
+        Ok(counted.to_object(py))
+    }
+
+    // Load the file into SQLite table `table_name` in `db_path`, creating
+    // the table if it doesn't exist. Column types (INTEGER, REAL or TEXT)
+    // are inferred from the first `sample_rows` rows; empty cells become
+    // NULL. Only the columns rows keep are loaded, in their order (see
+    // projection), under their keys after `rename`, made usable by
+    // names::sql_columns (see column_identifiers()). Rows are inserted
+    // with one prepared statement, committing every `batch_commit` rows,
+    // without the GIL. Returns the number of rows inserted. Needs the
+    // "sqlite" cargo feature.
+    #[cfg(feature = "sqlite")]
+    #[pyo3(signature = (db_path, table_name, batch_commit=10_000, sample_rows=1000))]
+    fn to_sqlite(
+        &self,
+        py: Python,
+        db_path: &str,
+        table_name: &str,
+        batch_commit: usize,
+        sample_rows: usize,
+    ) -> PyResult<usize> {
+        if batch_commit == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "batch_commit must be at least 1".to_string(),
+            ));
+        }
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        check_columns(&headers, self.rename.keys(), "rename")?;
+        let selected = self
+            .projection(&headers, None)?
+            .unwrap_or_else(|| (0..headers.len()).collect());
+        let columns = names::sql_columns(selected.iter().map(|&i| {
+            let header = &headers[i];
+            self.rename.get(header).map_or(header, |n| n.as_str())
+        }));
+        let types = match sqlite::infer_types(&mut reader, &selected, sample_rows) {
+            Ok(types) => types,
+            Err(e) => return Err(self.record_error(py, e)),
+        };
+
+        // Start over for the load itself
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        self.read_headers(&mut reader)?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let loaded = files::without_gil(py, || {
+            sqlite::write_table(
+                &mut reader,
+                &selected,
+                &columns,
+                &types,
+                db_path,
+                table_name,
+                batch_commit,
+                max_rows,
+                &cancel,
+            )
+        });
+        cancel.raise()?;
+
+        match loaded {
+            Ok(rows) => {
+                self.enforce_row_limit(&mut reader, rows)?;
+                Ok(rows)
+            }
+            Err(sqlite::ExportError::Csv(e)) => Err(self.record_error(py, e)),
+            Err(sqlite::ExportError::Sqlite(e)) => Err(
+                PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("SQLite error: {}", e)),
+            ),
+        }
+    }
+
+    // Write the file to `output_path` as fixed-width text: each field
+    // space-padded to its column's entry in `widths` (in characters, one
+    // per header field), one line per row, streaming without the GIL.
+    // `overflow` says what a longer cell does: "truncate" keeps its first
+    // characters, "error" raises ValueError with `row`, `column` and `raw`
+    // attributes. Cells are left-aligned; right_align_numbers=True
+    // right-aligns cells that parse as numbers. The header line is written
+    // only with include_header=True. Returns the number of rows written.
+    #[pyo3(signature = (
+        output_path,
+        widths,
+        overflow="truncate",
+        right_align_numbers=false,
+        include_header=false
+    ))]
+    fn to_fixed_width(
+        &self,
+        py: Python,
+        output_path: &str,
+        widths: Vec<usize>,
+        overflow: &str,
+        right_align_numbers: bool,
+        include_header: bool,
+    ) -> PyResult<usize> {
+        let overflow = fixed::Overflow::from_name(overflow).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "overflow must be \"truncate\" or \"error\", not {:?}",
+                overflow
+            ))
+        })?;
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        if widths.len() != headers.len() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "widths has {} entries, but the file has {} columns",
+                widths.len(),
+                headers.len()
+            )));
+        }
+
+        let layout = fixed::Layout {
+            widths: &widths,
+            overflow,
+            right_align_numbers,
+        };
+        let header = include_header.then_some(&headers);
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let written = files::without_gil(py, || {
+            fixed::write_file(&mut reader, header, &layout, output_path, max_rows, &cancel)
+        });
+        cancel.raise()?;
+
+        match written {
+            Ok(rows) => {
+                self.enforce_row_limit(&mut reader, rows)?;
+                Ok(rows)
+            }
+            Err(fixed::ExportError::Csv(e)) => Err(self.record_error(py, e)),
+            Err(fixed::ExportError::Io(e)) => Err(files::io_error("write file", e)),
+            Err(fixed::ExportError::TooLong {
+                row,
+                column,
+                value,
+                position,
+            }) => {
+                let at = match row {
+                    Some(row) => format!("row {}", row),
+                    None => "the header".to_string(),
+                };
+                let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Cell in column {} of {} is {} characters, longer than its width {}: {:?}",
+                    &headers[column],
+                    at,
+                    value.chars().count(),
+                    widths[column],
+                    value
+                ));
+                let attrs = err.value(py);
+                attrs.setattr("row", row)?;
+                attrs.setattr("column", &headers[column])?;
+                Err(self.with_raw(py, err, position.as_ref()))
+            }
+        }
+    }
+
+    // {identifier: header name} for every header field, in order, for
+    // outputs that restrict column names. `style="python"` gives valid
+    // Python identifiers (names::python_identifiers), `style="sql"` the
+    // column names to_sqlite() makes of them (names::sql_columns). Names
+    // come from the whole header as in the file, before `columns` and
+    // `rename`.
+    #[pyo3(signature = (style="python"))]
+    fn column_identifiers(&self, py: Python, style: &str) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let identifiers = match style {
+            "python" => names::python_identifiers(&headers),
+            "sql" => names::sql_columns(&headers),
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "style must be \"python\" or \"sql\", not {:?}",
+                    other
+                )));
+            }
+        };
+
+        let mapping = PyDict::new(py);
+        for (identifier, header) in identifiers.iter().zip(&headers) {
+            mapping.set_item(identifier, header)?;
+        }
+        Ok(mapping.to_object(py))
+    }
+
+    // The first `max_rows` rows as an aligned text table for terminal
+    // previews (see table::render), with the keys read() rows have as the
+    // header. Values are written as CSVWriter writes them by default, and
+    // cells longer than `max_width` characters are cut with "…".
+    #[pyo3(signature = (max_rows=10, max_width=40))]
+    fn to_table(&self, py: Python, max_rows: usize, max_width: usize) -> PyResult<String> {
+        if max_width == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "max_width must be at least 1".to_string(),
+            ));
+        }
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
+        let keys = column_names(py, &layout);
+        let format = ValueFormat::default();
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut buf = RecordBuf::default();
+        while rows.len() < max_rows.min(self.max_rows()) {
+            let row = match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                Some(row) => row,
+                None => break,
+            };
+            // Keys a row lacks (omit_empty) are blank
+            let cells = keys
+                .iter()
+                .map(|key| match row.get_item(key) {
+                    Some(value) => format.format(value),
+                    None => Ok(String::new()),
+                })
+                .collect::<PyResult<_>>()?;
+            rows.push(cells);
+        }
+
+        let header = keys
+            .iter()
+            .map(|key| key.extract::<String>(py))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(table::render(&header, &rows, max_width))
+    }
+
+    // Byte offset at which each row starts (every `every`-th row, starting
+    // with row 0). Uses the quote-aware RecordScanner, so quoted fields with
+    // embedded newlines are handled exactly and blank lines are skipped the
+    // same way the csv reader skips them. Runs without the GIL.
+    #[pyo3(signature = (every=1))]
+    fn scan_record_offsets(&self, py: Python, every: usize) -> PyResult<Vec<u64>> {
+        if every == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "every must be at least 1".to_string(),
+            ));
+        }
+
+        let mut file = self.open_buffered()?;
+        // The header record is not a row
+        let skip = usize::from(self.has_headers);
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let result = files::without_gil(py, || -> std::io::Result<(Vec<u64>, bool)> {
+            let mut scanner = self.record_scanner();
+            let mut offsets = Vec::new();
+            let mut record: usize = 0;
+            let mut buf = vec![0u8; BUF_SIZE];
+            let mut base: u64 = 0;
+            let mut over_limit = false;
+
+            while !over_limit && !cancel.cancelled() {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                scanner.feed(&buf[..n], base, |offset| {
+                    if record >= skip {
+                        let row = record - skip;
+                        if row >= max_rows {
+                            over_limit = true;
+                        } else if row.is_multiple_of(every) {
+                            offsets.push(offset);
+                        }
+                    }
+                    record += 1;
+                    cancel.check(record);
+                });
+                base += n as u64;
+            }
+            Ok((offsets, over_limit))
+        });
+        cancel.raise()?;
+
+        match result {
+            Ok((offsets, over_limit)) => {
+                if over_limit && self.strict_limit() {
+                    return Err(self.row_limit_error());
+                }
+                Ok(offsets)
+            }
+            Err(e) => Err(files::io_error("read file", e)),
+        }
+    }
+
+    // Names of the columns in which every cell is empty, in header order,
+    // e.g. placeholder columns to drop (see empty_column_indices)
+    fn empty_columns(&self, py: Python) -> PyResult<Vec<String>> {
+        let indices = self.empty_column_indices(py)?;
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        Ok(indices
+            .into_iter()
+            .map(|i| headers[i].to_string())
+            .collect())
+    }
+
+    // Byte offset of the first invalid UTF-8 sequence in the file, or None
+    // if the whole file is valid UTF-8. Looks at bytes only, without
+    // parsing records, so it is a cheap check before a long read. Runs
+    // without the GIL.
+    fn check_utf8(&self, py: Python) -> PyResult<Option<u64>> {
+        let mut file = self.open_buffered()?;
+
+        let result = files::without_gil(py, || -> std::io::Result<Option<u64>> {
+            let mut buf = vec![0u8; BUF_SIZE];
+            // Offset of buf[0] in the file, and how much of buf holds data.
+            // A sequence cut off at the end of one read is moved to the
+            // front and completed by the next.
+            let mut base: u64 = 0;
+            let mut filled = 0;
+            loop {
+                let n = file.read(&mut buf[filled..])?;
+                let end = filled + n;
+                match std::str::from_utf8(&buf[..end]) {
+                    Ok(_) if n == 0 => return Ok(None),
+                    Ok(_) => {
+                        base += end as u64;
+                        filled = 0;
+                    }
+                    Err(e) if e.error_len().is_some() || n == 0 => {
+                        return Ok(Some(base + e.valid_up_to() as u64));
+                    }
+                    Err(e) => {
+                        let valid = e.valid_up_to();
+                        buf.copy_within(valid..end, 0);
+                        base += valid as u64;
+                        filled = end - valid;
+                    }
+                }
+            }
+        });
+
+        result.map_err(|e| files::io_error("read file", e))
+    }
+
+    // The records after the first blank line that follows a record (see
+    // stop_at_blank_line), such as a report's summary block, as lists of
+    // field strings. They have a shape of their own, so they don't become
+    // row dicts. Empty if there is no blank line. Works with or without
+    // stop_at_blank_line.
+    fn trailer(&self, py: Python) -> PyResult<Vec<Vec<String>>> {
+        let offset = match &self.data_end {
+            Some(end) => end.offset,
+            None if self.stop_at_blank_line => return Ok(Vec::new()),
+            None => match self.find_data_end()? {
+                Some(end) => end.offset,
+                None => return Ok(Vec::new()),
+            },
+        };
+
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().has_headers(false).from_reader(file);
+        // Record positions stay relative to the start of the file
+        let mut start = csv::Position::new();
+        start.set_byte(offset);
+        if let Err(e) = reader.seek_raw(SeekFrom::Start(offset), start) {
+            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to seek in file: {}",
+                e
+            )));
+        }
+
+        let mut records = Vec::new();
+        let mut record = StringRecord::new();
+        loop {
+            match reader.read_record(&mut record) {
+                Ok(true) => records.push(record.iter().map(String::from).collect()),
+                Ok(false) => return Ok(records),
+                Err(e) => return Err(self.record_error(py, e)),
+            }
+        }
+    }
+
+    // The header record exactly as it appears in the file (quoting, spacing
+    // and any BOM intact), without its line terminator. Quote-aware, so a
+    // quoted header containing a newline is returned whole. None when the
+    // parser has no header row.
+    fn raw_header(&self, py: Python) -> PyResult<Option<PyObject>> {
+        if !self.has_headers {
+            return Ok(None);
+        }
+
+        let mut file = self.open_buffered()?;
+        let mut scanner = self.record_scanner();
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut starts: Vec<u64> = Vec::new();
+        let mut buf = vec![0u8; BUF_SIZE];
+
+        // Read until the record after the header starts, or EOF
+        while starts.len() < 2 {
+            let n = match file.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    return Err(files::io_error("read file", e));
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            scanner.feed(&buf[..n], bytes.len() as u64, |offset| starts.push(offset));
+            bytes.extend_from_slice(&buf[..n]);
+        }
+
+        let start = starts.first().map_or(bytes.len(), |&s| s as usize);
+        let mut end = starts.get(1).map_or(bytes.len(), |&s| s as usize);
+        while end > start && scanner.is_terminator(bytes[end - 1]) {
+            end -= 1;
+        }
+
+        Ok(Some(PyBytes::new(py, &bytes[start..end]).to_object(py)))
+    }
+
+    // Estimate how much Python memory the whole file would take once
+    // materialized. Builds the first `sample_rows` rows exactly as read()
+    // would and measures them with sys.getsizeof (each distinct value object
+    // once, so shared defaults and CPython's cached "" and one-character
+    // strings count once), then scales by the estimated row count. Containers
+    // that aren't measured use LIST_OVERHEAD and POINTER_SIZE. Returns
+    // {"sample_rows", "estimated_rows", "exact", "bytes": {"dicts", "lists",
+    // "columnar"}, "available_bytes", "recommendation"}.
+    #[pyo3(signature = (sample_rows=10_000, threshold_bytes=MEMORY_THRESHOLD))]
+    fn estimate_memory(
+        &self,
+        py: Python,
+        sample_rows: usize,
+        threshold_bytes: u64,
+    ) -> PyResult<PyObject> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+        let layout = self.row_layout(py, &headers)?;
+        let data_start = reader.position().byte();
+        let getsizeof = py.import("sys")?.getattr("getsizeof")?;
+
+        let mut buf = RecordBuf::default();
+        let mut sample: Vec<&PyDict> = Vec::new();
+        let mut seen: HashSet<usize> = HashSet::new();
+        let (mut dict_bytes, mut value_bytes, mut fields) = (0, 0, 0);
+        let mut exhausted = false;
+
+        // Rows stay alive in `sample` so value ids can't be reused
+        while sample.len() < sample_rows.min(self.max_rows()) {
+            let row = match self.next_row(py, &mut reader, &layout, &mut buf)? {
+                Some(row) => row,
+                None => {
+                    exhausted = true;
+                    break;
+                }
+            };
+            dict_bytes += getsizeof.call1((row,))?.extract::<usize>()?;
+            for value in row.values() {
+                if seen.insert(pyo3::AsPyPointer::as_ptr(value) as usize) {
+                    value_bytes += getsizeof.call1((value,))?.extract::<usize>()?;
+                }
+            }
+            fields += row.len();
+            sample.push(row);
+        }
+        let sampled = sample.len();
+
+        // Scale by bytes read, unless the sample was the whole file
+        let estimated_rows = if exhausted || sampled == 0 || sampled >= self.max_rows() {
+            sampled
+        } else {
+            let sample_bytes = reader.position().byte().saturating_sub(data_start).max(1);
+            let data_bytes = self.data_size()?.saturating_sub(data_start);
+            ((sampled as f64 * data_bytes as f64 / sample_bytes as f64).round() as usize)
+                .max(sampled)
+                .min(self.max_rows())
+        };
+
+        let per_row = |bytes: usize| bytes as f64 / sampled.max(1) as f64;
+        let rows = estimated_rows as f64;
+        let values = rows * per_row(value_bytes);
+        let slots = rows * per_row(fields) * POINTER_SIZE as f64;
+        let batches = estimated_rows.div_ceil(self.batch_size) as f64;
+        // read(): a row dict per row, in batch lists, in the outer list
+        let dicts = rows * (per_row(dict_bytes) + POINTER_SIZE as f64)
+            + values
+            + batches * (LIST_OVERHEAD + POINTER_SIZE) as f64
+            + LIST_OVERHEAD as f64;
+        // One list of values per row
+        let lists = rows * (LIST_OVERHEAD + POINTER_SIZE) as f64 + slots + values;
+        // One list per column
+        let columnar = (layout.keys.len() * LIST_OVERHEAD) as f64 + slots + values;
+
+        let available = available_memory();
+        let too_big =
+            dicts as u64 > threshold_bytes || available.is_some_and(|a| dicts as u64 > a / 2);
+
+        let bytes = PyDict::new(py);
+        bytes.set_item("dicts", dicts as u64)?;
+        bytes.set_item("lists", lists as u64)?;
+        bytes.set_item("columnar", columnar as u64)?;
+
+        let report = PyDict::new(py);
+        report.set_item("sample_rows", sampled)?;
+        report.set_item("estimated_rows", estimated_rows)?;
+        report.set_item("exact", exhausted || sampled >= self.max_rows())?;
+        report.set_item("bytes", bytes)?;
+        report.set_item("available_bytes", available)?;
+        report.set_item(
+            "recommendation",
+            if too_big { "lazy_batches" } else { "read" },
+        )?;
+
+        Ok(report.to_object(py))
+    }
+
+    // Approximate row count from the first `sample_rows` rows' average
+    // size and the file size, without reading the rest of the file. Exact
+    // when the sample reaches the end of the data. Scaled the same way as
+    // estimate_memory's `estimated_rows`.
+    #[pyo3(signature = (sample_rows=1000))]
+    fn estimate_row_count(&self, py: Python, sample_rows: usize) -> PyResult<usize> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        self.read_headers(&mut reader)?;
+        let data_start = reader.position().byte();
+
+        let mut record = ByteRecord::new();
+        let mut sampled: usize = 0;
+        let mut exhausted = false;
+        while sampled < sample_rows.min(self.max_rows()) {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => sampled += 1,
+                Ok(false) => {
+                    exhausted = true;
+                    break;
+                }
+                Err(e) => return Err(self.record_error(py, e)),
+            }
+        }
+
+        if exhausted || sampled == 0 || sampled >= self.max_rows() {
+            return Ok(sampled);
+        }
+        let sample_bytes = reader.position().byte().saturating_sub(data_start).max(1);
+        let data_bytes = self.data_size()?.saturating_sub(data_start);
+        Ok(
+            ((sampled as f64 * data_bytes as f64 / sample_bytes as f64).round() as usize)
+                .max(sampled)
+                .min(self.max_rows()),
+        )
+    }
+
+    // Helper method to estimate bytes per row, from the records' positions
+    // rather than the stream's, which is ahead by whatever the csv reader
+    // has buffered
+    fn estimate_bytes_per_row(&self) -> PyResult<f64> {
+        let reader = self.open_buffered()?;
+
+        // Create a CSV reader that will read from our buffered reader
+        let mut csv_reader = self.reader_builder().from_reader(reader);
+
+        // Skip header if needed
+        if self.has_headers {
+            if let Err(e) = csv_reader.headers() {
+                return Err(line_error(&e).unwrap_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Failed to read headers: {}",
+                        e
+                    ))
+                }));
+            }
+        }
+        let start_pos = csv_reader.position().byte();
+
+        // Count bytes for sample rows
+        let sample_size = 100;
+        let mut row_count = 0;
+        let mut record = ByteRecord::new();
+
+        for _ in 0..sample_size {
+            match csv_reader.read_byte_record(&mut record) {
+                Ok(true) => row_count += 1,
+                Err(e) => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Error reading sample row: {}",
+                        e
+                    )));
+                }
+                Ok(false) => break, // End of file
+            }
+        }
+
+        // The position after the sample rows
+        let end_pos = csv_reader.position().byte();
+
+        if row_count > 0 {
+            Ok((end_pos - start_pos) as f64 / row_count as f64)
+        } else {
+            // If we couldn't read any rows, return a default value
+            Ok(100.0) // Default guess: 100 bytes per row
+        }
+    }
+
+    // Details of the reads on this parser: {"mode": the path the last
+    // read() took, "memory" or "stream" (None before any read()),
+    // "pool_hits" and "pool_misses": read_chunk() calls that did and didn't
+    // continue from a pooled reader, "seek_fallbacks": read_chunk_optimized()
+    // calls that skipped rows exactly because the seek estimate was off,
+    // "projection": the path the last `columns` projection took,
+    // "sparse" or "projection" (None without one), "truncated": whether
+    // the last read(), iter_rows() or lazy_batches() to finish stopped
+    // early under tolerate_truncation, and with timings=True, "timings":
+    // the time per phase of the last read(), iter_rows() or lazy_batches()
+    // (see timings::Snapshot)}, or None if none of these has happened
+    fn last_read_stats(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let mode = match self.last_read_mode.load(Ordering::Relaxed) {
+            1 => Some(ReadMode::Memory),
+            2 => Some(ReadMode::Stream),
+            _ => None,
+        };
+        let hits = self.pool_hits.load(Ordering::Relaxed);
+        let misses = self.pool_misses.load(Ordering::Relaxed);
+        let fallbacks = self.seek_fallbacks.load(Ordering::Relaxed);
+        let timings = self
+            .last_timings
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|t| t.snapshot());
+        let timed = timings.is_some_and(|t| !t.is_empty());
+        let truncated = self.last_read_truncated.load(Ordering::Relaxed);
+        let projection = projection_name(self.last_projection.load(Ordering::Relaxed));
+        if mode.is_none()
+            && hits + misses + fallbacks == 0
+            && !timed
+            && !truncated
+            && projection.is_none()
+        {
+            return Ok(None);
+        }
+        let stats = PyDict::new(py);
+        stats.set_item("mode", mode.map(|m| m.name()))?;
+        stats.set_item("pool_hits", hits)?;
+        stats.set_item("pool_misses", misses)?;
+        stats.set_item("seek_fallbacks", fallbacks)?;
+        stats.set_item("projection", projection)?;
+        stats.set_item("truncated", truncated)?;
+        if let Some(timings) = timings {
+            stats.set_item("timings", timings.to_dict(py)?)?;
+        }
+        Ok(Some(stats.to_object(py)))
+    }
+
+    // With hash_content, the xxHash64 of the input's bytes as 16 hex
+    // digits, from the last read that read all of them in order (see
+    // files::Input::digest), so the file needn't be read again to record
+    // which version was processed. None before such a read, or without
+    // hash_content.
+    fn content_hash(&self) -> Option<String> {
+        let digest = self.content_digest.as_ref()?;
+        let hash = (*digest.lock().unwrap())?;
+        Some(format!("{:016x}", hash))
+    }
+
+    // New method: get file information
+    fn get_file_info(&self, py: Python) -> PyResult<PyObject> {
+        let path = Path::new(&self.filename);
+        let metadata = match files::metadata(path) {
+            Ok(m) => m,
+            Err(e) => return Err(files::io_error("get file metadata", e)),
+        };
+
+        // An archive entry's or compressed file's size is its uncompressed
+        // size
+        let size = if self.zip_entry.is_some() || self.is_compressed() {
+            self.data_size()?
+        } else {
+            metadata.len()
+        };
+
+        let info = PyDict::new(py);
+        info.set_item("filename", &self.filename)?;
+        if let Some(entry) = &self.zip_entry {
+            info.set_item("zip_entry", entry)?;
+        }
+        info.set_item("size_bytes", size)?;
+        info.set_item("size_mb", (size as f64) / (1024.0 * 1024.0))?;
+        info.set_item("batch_size", self.batch_size)?;
+        info.set_item("has_headers", self.has_headers)?;
+        info.set_item("delimiter", (self.delimiter as char).to_string())?;
+
+        // A first line this long is rarely CSV, and reading the headers
+        // would buffer all of it
+        let mut head = Vec::new();
+        if let Err(e) = self
+            .open_input()?
+            .take(LINE_CHECK_BYTES)
+            .read_to_end(&mut head)
+        {
+            return Err(files::io_error("read file", e));
+        }
+        if head.len() as u64 == LINE_CHECK_BYTES && !head.iter().any(|&b| b == b'\n' || b == b'\r')
+        {
+            info.set_item(
+                "warning",
+                format!(
+                    "No line break in the first {} MB; this may not be a CSV file. \
+                     Set max_line_bytes to make reads fail fast on it.",
+                    LINE_CHECK_BYTES / (1024 * 1024)
+                ),
+            )?;
+            if self.has_headers {
+                info.set_item("headers", PyList::empty(py))?;
+            }
+            return Ok(info.to_object(py));
+        }
+
+        // Try to get sample headers
+        let file = self.open_input()?;
+
+        let mut reader = self.reader_builder().from_reader(file);
+
+        if self.has_headers {
+            match self.read_headers(&mut reader) {
                 Ok(headers) => {
                     // Convert headers to a vector of strings first
                     let header_vec: Vec<&str> = headers.iter().collect();
                     let header_list = PyList::new(py, &header_vec);
                     info.set_item("headers", header_list)?;
                 }
-                Err(_) => {
+                // Usually a header that isn't UTF-8 (Latin-1, UTF-16, ...):
+                // keep its bytes and the reason so the cause is visible
+                Err(e) => {
                     info.set_item("headers", PyList::empty(py))?;
+                    info.set_item("header_error", e.value(py).str()?)?;
+                    info.set_item("raw_headers_bytes", self.raw_header(py)?)?;
                 }
             }
         }
 
         Ok(info.to_object(py))
     }
+
+    // Save every constructor option except the filename as JSON, so the same
+    // configuration can be applied to other files with from_profile().
+    // Options holding values JSON can't represent (e.g. a Decimal in
+    // `defaults`) raise ValueError naming the option rather than being
+    // dropped or stringified.
+    fn save_profile(&self, py: Python, path: String) -> PyResult<()> {
+        let json = py.import("json")?;
+        let options = self.options(py)?;
+        for (name, value) in options.iter() {
+            if let Err(e) = json.call_method1("dumps", (value,)) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Option {} can't be saved in a profile: {}",
+                    name,
+                    e.value(py)
+                )));
+            }
+        }
+
+        let profile = PyDict::new(py);
+        profile.set_item("version", PROFILE_VERSION)?;
+        profile.set_item("options", options)?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("indent", 2)?;
+        let text: String = json
+            .call_method("dumps", (profile,), Some(kwargs))?
+            .extract()?;
+
+        let mut file = match files::create(Path::new(&path)) {
+            Ok(f) => f,
+            Err(e) => return Err(files::io_error("create file", e)),
+        };
+        if let Err(e) = file.write_all(text.as_bytes()) {
+            return Err(files::io_error("write file", e));
+        }
+        Ok(())
+    }
+
+    // Build a parser for a CSV inside the ZIP archive at `zip_path`:
+    // `entry_name`, or the archive's only .csv entry (or only entry) if
+    // None. Takes the constructor's keyword arguments. The entry is
+    // decompressed as it is read, without extracting it to disk.
+    #[cfg(feature = "zip")]
+    #[classmethod]
+    #[pyo3(signature = (zip_path, batch_size, entry_name=None, **kwargs))]
+    fn from_zip(
+        cls: &PyType,
+        zip_path: String,
+        batch_size: usize,
+        entry_name: Option<String>,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let py = cls.py();
+        let entry = archive::locate(&zip_path, entry_name.as_deref())?;
+        let kwargs = match kwargs {
+            Some(kwargs) => kwargs.copy()?,
+            None => PyDict::new(py),
+        };
+        kwargs.set_item("zip_entry", entry.name)?;
+        Ok(cls
+            .call((zip_path, batch_size), Some(kwargs))?
+            .to_object(py))
+    }
+
+    // Names of the files in the ZIP archive at `zip_path`, in archive order
+    #[cfg(feature = "zip")]
+    #[staticmethod]
+    fn zip_entries(zip_path: String) -> PyResult<Vec<String>> {
+        archive::entries(&zip_path)
+    }
+
+    // Build a parser for `data_path` from a save_profile() file. Keyword
+    // arguments override the profile's options.
+    #[classmethod]
+    #[pyo3(signature = (profile_path, data_path, **overrides))]
+    fn from_profile(
+        cls: &PyType,
+        profile_path: String,
+        data_path: String,
+        overrides: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let py = cls.py();
+        let mut text = String::new();
+        match files::open(Path::new(&profile_path)) {
+            Ok(mut f) => {
+                if let Err(e) = f.read_to_string(&mut text) {
+                    return Err(files::io_error("read file", e));
+                }
+            }
+            Err(e) => return Err(files::io_error("open file", e)),
+        }
+
+        let invalid = |reason: &str| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid profile {}: {}",
+                profile_path, reason
+            ))
+        };
+        let profile: &PyDict = match py.import("json")?.call_method1("loads", (text,)) {
+            Ok(p) => p.downcast().map_err(|_| invalid("not a JSON object"))?,
+            Err(e) => return Err(invalid(&e.value(py).to_string())),
+        };
+        match profile.get_item("version").map(|v| v.extract::<u64>()) {
+            Some(Ok(PROFILE_VERSION)) => {}
+            Some(Ok(v)) => return Err(invalid(&format!("unsupported version {}", v))),
+            _ => return Err(invalid("missing version")),
+        }
+        let options: &PyDict = match profile.get_item("options") {
+            Some(o) => o
+                .downcast()
+                .map_err(|_| invalid("options is not an object"))?,
+            None => return Err(invalid("missing options")),
+        };
+
+        let kwargs = options.copy()?;
+        if let Some(overrides) = overrides {
+            kwargs.update(overrides.as_mapping())?;
+        }
+        Ok(cls.call((data_path,), Some(kwargs))?.to_object(py))
+    }
+}
+
+// SMALL_FILE_SIZE, or in tests the size run() set to send every read down
+// one path or the other
+fn small_file_size() -> usize {
+    #[cfg(test)]
+    return tests::SMALL_FILE_SIZE.load(Ordering::Relaxed);
+    #[cfg(not(test))]
+    SMALL_FILE_SIZE
+}
+
+// Internal helpers shared by the Python-facing methods
+impl CSVParser {
+    // read_optimized(), and read()'s memory path with its `timings`
+    fn read_memory(&self, py: Python, timings: Option<&Arc<Timings>>) -> PyResult<Vec<PyObject>> {
+        // Read the entire file into memory at once
+        let mut content = Vec::with_capacity(self.file_size as usize);
+        {
+            // The "" fields are found in `content` below, all at once
+            let mut file = self
+                .open_input()?
+                .quoted_empty(None)
+                .timer(timings.map(|t| t.read.clone()));
+
+            if let Err(e) = file.read_to_end(&mut content) {
+                return Err(files::io_error("read file", e));
+            }
+        }
+
+        // Pre-allocate results
+        let estimated_rows = content.len() / 50; // Rough estimate of rows based on byte size
+                                                 // heuristic value as count as
+                                                 // A few numeric fields (4-8 bytes each)
+                                                 // A few short text fields (10-20 bytes each)
+                                                 // Commas between fields (1 byte each)
+                                                 // A newline character (1-2 bytes)
+        let estimated_batches = (estimated_rows / self.batch_size) + 1; // + 1 is for the remainder batch if any
+
+        // Process the content with a memory reader (faster than file I/O)
+        let content_len = content.len();
+        let content = InMemory::new(&content, self.quote_tracker());
+        let mut reader = self.reader_builder().from_reader(content);
+        if content_len <= small_file_size() {
+            return self.collect_flat(py, &mut reader, timings);
+        }
+        self.collect_batches(py, &mut reader, estimated_batches, None, timings)
+    }
+
+    // read() itself, which times it when timings is on
+    fn read_batches(
+        &self,
+        py: Python,
+        mode: Option<&str>,
+        timings: Option<&Arc<Timings>>,
+    ) -> PyResult<Vec<PyObject>> {
+        let mode = self.read_path(mode)?;
+        self.last_read_mode.store(mode as u8, Ordering::Relaxed);
+
+        if mode == ReadMode::Memory {
+            self.log_event(
+                py,
+                "path",
+                "Using in-memory read path".to_string(),
+                &[("mode", "memory".to_object(py))],
+            )?;
+            return self.read_memory(py, timings); // Will read whole file to memory first
+        }
+
+        self.log_event(
+            py,
+            "path",
+            "Using streaming read path".to_string(),
+            &[("mode", "stream".to_object(py))],
+        )?;
+
+        // Write with chunking for larger files
+        let file = self.open_timed(timings)?;
+        let watch = file.get_ref().watch();
+        let mut reader = self.reader_builder().from_reader(file);
+
+        // Pre-allocate the vector to reduce reallocations
+        let estimated_batches =
+            (self.file_size / (self.batch_size as u64).saturating_mul(100) + 1) as usize;
+
+        self.collect_batches(py, &mut reader, estimated_batches, Some(&watch), timings)
+    }
+
+    // The constructor keyword arguments that reproduce this parser's
+    // configuration, as save_profile() writes them
+    fn options<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let options = PyDict::new(py);
+        options.set_item("batch_size", self.batch_size)?;
+        options.set_item("has_headers", self.has_headers)?;
+        options.set_item("defaults", &self.defaults)?;
+        options.set_item("index_key_prefix", &self.index_key_prefix)?;
+        options.set_item("decimal_columns", &self.decimal_columns)?;
+        options.set_item("json_columns", &self.json_columns)?;
+        options.set_item("json_invalid", self.json_invalid.name())?;
+        options.set_item("compat", self.dictreader_compat.then_some("dictreader"))?;
+        options.set_item("restkey", &self.restkey)?;
+        options.set_item("restval", &self.restval)?;
+        options.set_item("include_row_hash", self.include_row_hash)?;
+        options.set_item("hash_columns", &self.hash_columns)?;
+        options.set_item("add_row_number", &self.add_row_number)?;
+        options.set_item("row_number_start", self.row_number_start)?;
+        options.set_item("row_limit", self.row_limit)?;
+        options.set_item("row_limit_strict", self.row_limit_strict)?;
+        options.set_item("max_columns", self.max_columns)?;
+        options.set_item("extend_headers", self.extend_headers)?;
+        options.set_item("extend_headers_to", self.extend_headers_to)?;
+        options.set_item("columns", &self.columns)?;
+        options.set_item("rename", &self.rename)?;
+        options.set_item(
+            "normalize_headers",
+            self.normalize_headers.map(|s| s.name()),
+        )?;
+        options.set_item("dedup_headers", self.dedup_headers)?;
+        options.set_item("drop_empty_columns", self.drop_empty_columns)?;
+        options.set_item("na_values", &self.na_values)?;
+        options.set_item("sparse", self.omit_empty)?;
+        options.set_item("distinguish_empty_quoted", self.distinguish_empty_quoted)?;
+        options.set_item("single_column", &self.single_column)?;
+        options.set_item("fallback_delimiters", &self.fallback_delimiters)?;
+        options.set_item("strict_dialect", self.strict_dialect)?;
+        options.set_item("intern_max_len", self.intern_max_len)?;
+        options.set_item("intern_max_values", self.intern_max_values)?;
+        options.set_item("stop_at_blank_line", self.stop_at_blank_line)?;
+        options.set_item("strict_quoting", self.strict_quoting)?;
+        options.set_item("allow_empty", self.allow_empty)?;
+        options.set_item("schema_drift", self.schema_drift.name())?;
+        options.set_item("clean", self.clean)?;
+        options.set_item("clean_chars", &self.clean_chars)?;
+        options.set_item("strip_zero_width", self.strip_zero_width)?;
+        options.set_item("tolerate_truncation", self.tolerate_truncation)?;
+        options.set_item("max_bytes_per_sec", self.max_bytes_per_sec)?;
+        options.set_item("max_line_bytes", self.max_line_bytes)?;
+        options.set_item("mode", self.mode.name())?;
+        options.set_item("seek_strategy", self.seek_strategy.name())?;
+        options.set_item("chunk_unit", self.chunk_unit.name())?;
+        options.set_item("all_strings", self.all_strings)?;
+        options.set_item("snapshot", self.snapshot.name())?;
+        options.set_item("zip_entry", &self.zip_entry)?;
+        options.set_item("raw_error_length", self.raw_error_length)?;
+        options.set_item("signal_check_interval", self.signal_check_interval)?;
+        options.set_item("gil_yield_every", self.gil_yield_every)?;
+        options.set_item("verbose", self.verbose)?;
+        options.set_item("hash_content", self.content_digest.is_some())?;
+        options.set_item("timings", self.timings)?;
+        Ok(options)
+    }
+
+    // ReadMode to use for a read: `mode` if given, else the parser's, with
+    // "auto" resolved by file size
+    fn read_path(&self, mode: Option<&str>) -> PyResult<ReadMode> {
+        let mode = match mode {
+            Some(name) => ReadMode::from_name(name)?,
+            None => self.mode,
+        };
+        Ok(match mode {
+            // A compressed file's size on disk says little about how much
+            // memory its data takes, so it streams
+            ReadMode::Auto if self.is_compressed() => ReadMode::Stream,
+            // Fast path: read entire file into memory for large files
+            // (under 100 MB)
+            ReadMode::Auto if self.file_size > 0 && self.file_size < 100 * 1024 * 1024 => {
+                ReadMode::Memory
+            }
+            ReadMode::Auto => ReadMode::Stream,
+            forced => forced,
+        })
+    }
+
+    // Every csv::Reader in this crate is built from here so that all methods
+    // agree on how records are split (and therefore on row numbering).
+    fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder.flexible(true).has_headers(self.has_headers);
+        // No quoting and a delimiter that text lines don't contain, so each
+        // line is one field. A NUL byte still splits the line; next_row
+        // joins the pieces back.
+        if self.single_column.is_some() {
+            builder.quoting(false).delimiter(b'\0');
+        } else {
+            builder.delimiter(self.delimiter);
+        }
+        builder
+    }
+
+    // Byte-level counterpart of reader_builder for code that finds record
+    // boundaries without the csv reader. Must use the same dialect: the
+    // delimiter, and the builder's defaults of '"', no escape character and
+    // any of \r, \n or \r\n as terminator.
+    fn record_scanner(&self) -> RecordScanner {
+        if self.single_column.is_some() {
+            return RecordScanner::new(b'\0', b'"').quoting(false);
+        }
+        RecordScanner::new(self.delimiter, b'"')
+            .escape(None)
+            .terminator(None)
+    }
+
+    // fallback_delimiters: keep ',' unless the first record has a single
+    // column with it, else take the first fallback that splits that record
+    // into several. Returns the delimiter chosen.
+    fn resolve_delimiter(&self, py: Python, fallbacks: &[char]) -> PyResult<u8> {
+        let width = |delimiter: u8| -> PyResult<usize> {
+            let mut reader = self
+                .reader_builder()
+                .has_headers(false)
+                .delimiter(delimiter)
+                .from_reader(BufReader::with_capacity(BUF_SIZE, self.open_uncut()?));
+            let mut record = ByteRecord::new();
+            match reader.read_byte_record(&mut record) {
+                Ok(_) => Ok(record.len()),
+                Err(e) => Err(self.record_error(py, e)),
+            }
+        };
+        if self.single_column.is_some() || width(b',')? != 1 {
+            return Ok(b',');
+        }
+        for &c in fallbacks {
+            if width(c as u8)? > 1 {
+                return Ok(c as u8);
+            }
+        }
+        Ok(b',')
+    }
+
+    // A first record that is one column but holds tabs, semicolons or pipes
+    // is almost always a file split on the wrong delimiter. Warn with
+    // CSVReaderWarning, or raise ValueError with strict_dialect, naming the
+    // likeliest delimiter and how often each candidate appears (as
+    // `delimiter` and `counts` on the error). A one-column record without
+    // them is a valid one-column file and passes silently.
+    fn check_delimiter(&self, py: Python) -> PyResult<()> {
+        if self.single_column.is_some() {
+            return Ok(());
+        }
+        let mut reader = self
+            .reader_builder()
+            .has_headers(false)
+            .from_reader(self.open_buffered()?);
+        let mut record = ByteRecord::new();
+        match reader.read_byte_record(&mut record) {
+            Ok(true) if record.len() == 1 => {}
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(self.record_error(py, e)),
+        }
+
+        let counts: Vec<(char, &str, usize)> = SUSPECT_DELIMITERS
+            .iter()
+            .map(|&(c, name)| (c, name, record[0].iter().filter(|&&b| b == c as u8).count()))
+            .collect();
+        // The first of the most frequent
+        let mut likely = counts[0];
+        for &candidate in &counts[1..] {
+            if candidate.2 > likely.2 {
+                likely = candidate;
+            }
+        }
+        if likely.2 == 0 {
+            return Ok(());
+        }
+
+        let found: Vec<String> = counts
+            .iter()
+            .map(|(_, name, n)| format!("{} {}", n, name))
+            .collect();
+        let message = format!(
+            "The first record of {} is one column but contains {}; the file is probably \
+             {:?}-separated, which fallback_delimiters=[{:?}] would handle",
+            self.filename,
+            found.join(", "),
+            likely.0,
+            likely.0
+        );
+        if self.strict_dialect {
+            let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(message);
+            let value = err.value(py);
+            value.setattr("delimiter", likely.0.to_string())?;
+            let counts_dict = PyDict::new(py);
+            for (c, _, n) in &counts {
+                counts_dict.set_item(c.to_string(), n)?;
+            }
+            value.setattr("counts", counts_dict)?;
+            return Err(err);
+        }
+        self.log_event(
+            py,
+            "warning",
+            message.clone(),
+            &[("delimiter", likely.0.to_string().to_object(py))],
+        )?;
+        PyErr::warn(py, py.get_type::<CSVReaderWarning>(), &message, 1)
+    }
+
+    // A compressed file without a block index reads fine front to back,
+    // but every seek backwards decompresses it again from the start
+    #[cfg(any(feature = "bgzf", feature = "zstd"))]
+    fn warn_sequential(&self, py: Python, format: &str) -> PyResult<()> {
+        let message = format!(
+            "{} is {}-compressed without a block index, so it is read sequentially and \
+             seeks decompress it from the start; recompress it with bgzip or as seekable \
+             zstd for fast random access",
+            self.filename, format
+        );
+        self.log_event(
+            py,
+            "warning",
+            message.clone(),
+            &[("format", format.to_object(py))],
+        )?;
+        PyErr::warn(py, py.get_type::<CSVReaderWarning>(), &message, 1)
+    }
+
+    fn open_buffered(&self) -> PyResult<BufReader<Input>> {
+        Ok(BufReader::with_capacity(BUF_SIZE, self.open_input()?))
+    }
+
+    // The file reads open: `filename`, or its snapshot copy
+    fn data_path(&self) -> &Path {
+        self.snapshot_copy
+            .as_ref()
+            .map_or(Path::new(&self.filename), |copy| copy.path())
+    }
+
+    // The data file, read at no more than max_bytes_per_sec, failing on
+    // lines over max_line_bytes, with fields past max_columns dropped, ""
+    // fields noted for distinguish_empty_quoted, and locked with
+    // snapshot="lock"
+    fn open_input(&self) -> PyResult<Input> {
+        Ok(self
+            .open_uncut()?
+            .max_columns(self.field_cutter())
+            .quoted_empty(self.quote_tracker()))
+    }
+
+    // open_input() without max_columns, for looking at the file's bytes
+    // as they are
+    fn open_uncut(&self) -> PyResult<Input> {
+        let input = self.open_source()?;
+        Ok(input
+            .max_line_bytes(self.max_line_bytes)
+            .digest(self.content_digest.clone()))
+    }
+
+    // max_columns' rewriting of the input, in the reader's dialect. A
+    // single_column line is one field, whatever its NUL bytes split.
+    fn field_cutter(&self) -> Option<FieldCutter> {
+        match (self.max_columns, &self.single_column) {
+            (Some(max), None) => Some(FieldCutter::new(self.record_scanner(), max)),
+            _ => None,
+        }
+    }
+
+    // distinguish_empty_quoted's tracking of "" fields, in the reader's
+    // dialect
+    fn quote_tracker(&self) -> Option<QuoteTracker> {
+        (self.distinguish_empty_quoted && !self.all_strings)
+            .then(|| QuoteTracker::new(self.record_scanner()))
+    }
+
+    // open_buffered() for a timed read, whose reads count towards
+    // `timings`' read time
+    fn open_timed(&self, timings: Option<&Arc<Timings>>) -> PyResult<BufReader<Input>> {
+        let input = self.open_input()?.timer(timings.map(|t| t.read.clone()));
+        Ok(BufReader::with_capacity(BUF_SIZE, input))
+    }
+
+    // With timings=True, fresh Timings for a read that is starting, which
+    // last_read_stats() reports from now on
+    fn start_timings(&self) -> Option<Arc<Timings>> {
+        if !self.timings {
+            return None;
+        }
+        let timings = Arc::new(Timings::default());
+        *self.last_timings.lock().unwrap() = Some(timings.clone());
+        Some(timings)
+    }
+
+    // Length of the data reads see: the file's, a ZIP entry's, or a
+    // compressed file's uncompressed data. That last one needs the block
+    // index, or, for a file without one, decompressing it all once.
+    fn data_size(&self) -> PyResult<u64> {
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        if let Some(format) = &self.compressed {
+            let file = match files::open(self.data_path()) {
+                Ok(file) => file,
+                Err(e) => return Err(files::io_error("open file", e)),
+            };
+            return format
+                .size(file)
+                .map_err(|e| files::io_error("read file", e));
+        }
+        Ok(self.file_size)
+    }
+
+    fn is_compressed(&self) -> bool {
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        return self.compressed.is_some();
+        #[cfg(not(any(feature = "bgzf", feature = "zstd")))]
+        false
+    }
+
+    // Whether a seek reads all the data before its target: in a ZIP entry,
+    // or a compressed file without a block index
+    fn slow_seeks(&self) -> bool {
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        if self.compressed.as_ref().is_some_and(|c| !c.seekable()) {
+            return true;
+        }
+        self.zip_entry.is_some()
+    }
+
+    // The data file as a plain Input: the ZIP entry, the compressed file's
+    // uncompressed data, or the file itself
+    fn open_source(&self) -> PyResult<Input> {
+        #[cfg(feature = "zip")]
+        if let Some(entry) = &self.archive {
+            return match entry.open() {
+                Ok(reader) => Ok(Input::zip(reader, self.max_bytes_per_sec)),
+                Err(e) => Err(files::io_error("open file", e)),
+            };
+        }
+        let file = files::open(self.data_path()).and_then(|f| {
+            if self.snapshot == SnapshotMode::Lock {
+                files::lock_shared(&f)?;
+            }
+            Ok(f)
+        });
+        let file = match file {
+            Ok(f) => f,
+            Err(e) => return Err(files::io_error("open file", e)),
+        };
+        #[cfg(any(feature = "bgzf", feature = "zstd"))]
+        if let Some(format) = &self.compressed {
+            return match compressed::Reader::new(file, format.clone()) {
+                Ok(reader) => Ok(Input::compressed(reader, self.max_bytes_per_sec)),
+                Err(e) => Err(files::io_error("open file", e)),
+            };
+        }
+        Ok(Input::new(file, self.max_bytes_per_sec))
+    }
+
+    // Column names for `reader`. Without a header row the columns are keyed
+    // by position ("0", "1", ... after index_key_prefix), sized from the
+    // first record.
+    fn read_headers<R: Read>(&self, reader: &mut csv::Reader<R>) -> PyResult<StringRecord> {
+        let headers = match reader.headers() {
+            Ok(h) => h,
+            Err(e) => {
+                if let Some(err) = line_error(&e) {
+                    return Err(err);
+                }
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Failed to read CSV headers: {}",
+                    e
+                )));
+            }
+        };
+
+        if let Some(name) = &self.single_column {
+            return Ok(StringRecord::from(vec![name.as_str()]));
+        }
+
+        let width = headers
+            .len()
+            .max(self.header_width)
+            .min(self.column_limit());
+        if self.has_headers {
+            let mut headers = headers.clone();
+            headers.truncate(width);
+            for i in headers.len()..width {
+                headers.push_field(&format!("unnamed_{}", i));
+            }
+            return Ok(self.clean_headers(headers));
+        }
+
+        Ok((0..width)
+            .map(|i| format!("{}{}", self.index_key_prefix, i))
+            .collect())
+    }
+
+    // normalize_headers, then dedup_headers, always in that order, so names
+    // that only collide once normalized ("First Name" and "first_name")
+    // are made unique as well. A name that normalizes to nothing becomes
+    // unnamed_<position>, like a missing one.
+    fn clean_headers(&self, headers: StringRecord) -> StringRecord {
+        if self.normalize_headers.is_none() && !self.dedup_headers {
+            return headers;
+        }
+        let mut names: Vec<String> = headers.iter().map(String::from).collect();
+        if let Some(style) = self.normalize_headers {
+            for (i, name) in names.iter_mut().enumerate() {
+                *name = style.apply(name);
+                if name.is_empty() {
+                    *name = format!("unnamed_{}", i);
+                }
+            }
+        }
+        if self.dedup_headers {
+            names = names::unique_headers(names.into_iter());
+        }
+        StringRecord::from(names)
+    }
+
+    // Widest of the first EXTEND_HEADERS_SAMPLE records, header included
+    fn sample_width(&self, py: Python) -> PyResult<usize> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().has_headers(false).from_reader(file);
+        let mut record = ByteRecord::new();
+        let mut width = 0;
+        // The trailer after a blank line isn't data
+        let records = match &self.data_end {
+            Some(end) => end.rows.saturating_add(usize::from(self.has_headers)),
+            None => usize::MAX,
+        };
+        for _ in 0..(EXTEND_HEADERS_SAMPLE + 1).min(records) {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => width = width.max(record.len()),
+                Ok(false) => break,
+                Err(e) => {
+                    return Err(self.record_error(py, e));
+                }
+            }
+        }
+        Ok(width)
+    }
+
+    // ValueError for a record the csv reader couldn't parse, with the
+    // record's original text attached, or for a line over max_line_bytes
+    fn record_error(&self, py: Python, e: csv::Error) -> PyErr {
+        if let Some(err) = line_error(&e) {
+            return err;
+        }
+        let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Failed to read CSV record: {}",
+            e
+        ));
+        self.with_raw(py, err, e.position())
+    }
+
+    // record_error's error for a record read as bytes whose field `field`
+    // isn't valid UTF-8 after `valid_up_to` bytes, worded as the csv crate
+    // words it for read_record
+    fn utf8_error(
+        &self,
+        py: Python,
+        record: &ByteRecord,
+        field: usize,
+        valid_up_to: usize,
+    ) -> PyErr {
+        let message = match record.position() {
+            Some(p) => format!(
+                "CSV parse error: record {} (line {}, field: {}, byte: {})",
+                p.record(),
+                p.line(),
+                field,
+                p.byte()
+            ),
+            None => format!("CSV parse error: field {}", field),
+        };
+        let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Failed to read CSV record: {}: invalid utf-8: invalid UTF-8 in field {} near byte index {}",
+            message, field, valid_up_to
+        ));
+        self.with_raw(py, err, record.position())
+    }
+
+    // Attach the original text of the record starting at `position` to
+    // `err` as `raw`, so it can be pasted into a bug report as-is, and its
+    // byte offset as `offset`, for read_rows_at()
+    fn with_raw(&self, py: Python, err: PyErr, position: Option<&csv::Position>) -> PyErr {
+        if let Some(p) = position {
+            let _ = err.value(py).setattr("offset", p.byte());
+        }
+        if self.raw_error_length > 0 {
+            if let Some(raw) = position.and_then(|p| self.raw_record(p.byte())) {
+                let _ = err.value(py).setattr("raw", raw);
+            }
+        }
+        err
+    }
+
+    // Re-read the record starting at byte `start`, quoting and all, decoded
+    // lossily and truncated to raw_error_length characters. Only used on
+    // error paths, so reopening the file is fine.
+    fn raw_record(&self, start: u64) -> Option<String> {
+        let mut file = self.open_input().ok()?;
+        file.seek(SeekFrom::Start(start)).ok()?;
+        let mut reader = self
+            .reader_builder()
+            .has_headers(false)
+            .from_reader(&mut file);
+        let mut record = ByteRecord::new();
+        reader.read_byte_record(&mut record).ok()?;
+        // Relative to `start`, just past the record's terminator
+        let len = reader.position().byte();
+        drop(reader);
+
+        file.seek(SeekFrom::Start(start)).ok()?;
+        let mut bytes = Vec::new();
+        file.take(len.min(self.raw_error_length as u64 * 4))
+            .read_to_end(&mut bytes)
+            .ok()?;
+        // A record's position can sit on the tail of the previous line's
+        // terminator (the \n of \r\n), so trim both ends
+        let text = String::from_utf8_lossy(&bytes);
+        Some(
+            text.trim_matches(['\r', '\n'])
+                .chars()
+                .take(self.raw_error_length)
+                .collect(),
+        )
+    }
+
+    // stop_at_blank_line: where the first blank line after a record is and
+    // how many rows come before it, or None if the file has no such line
+    fn find_data_end(&self) -> PyResult<Option<DataEnd>> {
+        let mut file = self.open_buffered()?;
+        let mut scanner = self.record_scanner();
+        let mut records: usize = 0;
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut base: u64 = 0;
+        loop {
+            let n = match file.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => return Err(files::io_error("read file", e)),
+            };
+            if n == 0 {
+                return Ok(None);
+            }
+            if let Some(offset) = scanner.feed_until_blank(&buf[..n], base, |_| records += 1) {
+                return Ok(Some(DataEnd {
+                    offset,
+                    rows: records.saturating_sub(usize::from(self.has_headers)),
+                }));
+            }
+            base += n as u64;
+        }
+    }
+
+    // Whether the file has a data record, after the header row with
+    // has_headers. Reads only up to the first one.
+    fn has_records(&self) -> PyResult<bool> {
+        let mut reader = self.reader_builder().from_reader(self.open_buffered()?);
+        match reader.read_byte_record(&mut ByteRecord::new()) {
+            Ok(found) => Ok(found),
+            Err(e) => match e.into_kind() {
+                csv::ErrorKind::Io(e) => Err(files::io_error("read file", e)),
+                // Something is there; reading it reports what is wrong
+                _ => Ok(true),
+            },
+        }
+    }
+
+    // strict_quoting: raise ValueError for the first record whose quoting
+    // is malformed (see RecordScanner::feed_strict), with the record's line
+    // number. Data past stop_at_blank_line's end isn't checked.
+    fn check_quoting(&self, py: Python) -> PyResult<()> {
+        let file = self.open_buffered()?;
+        let limit = self.data_end.as_ref().map_or(u64::MAX, |end| end.offset);
+        let mut file = file.take(limit);
+        let mut scanner = self.record_scanner();
+        let mut record_start: u64 = 0;
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut base: u64 = 0;
+        let reason = loop {
+            let n = match file.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => return Err(files::io_error("read file", e)),
+            };
+            if n == 0 {
+                if scanner.in_quoted_field() {
+                    break "quoted field not closed at end of file";
+                }
+                return Ok(());
+            }
+            let bad = scanner.feed_strict(&buf[..n], base, |start| record_start = start);
+            if let Some((_, reason)) = bad {
+                break reason;
+            }
+            base += n as u64;
+        };
+
+        let line = self.line_number(record_start)?;
+        let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Malformed quoting in {} at line {}: {}",
+            self.filename, line, reason
+        ));
+        err.value(py).setattr("line", line)?;
+        let mut position = csv::Position::new();
+        position.set_byte(record_start).set_line(line);
+        Err(self.with_raw(py, err, Some(&position)))
+    }
+
+    // 1-based line number of the byte at `offset`, counting \n, \r\n and
+    // bare \r line breaks, quoted ones included, as an editor would
+    fn line_number(&self, offset: u64) -> PyResult<u64> {
+        let mut prefix = Vec::new();
+        if let Err(e) = self.open_input()?.take(offset).read_to_end(&mut prefix) {
+            return Err(files::io_error("read file", e));
+        }
+        let breaks = prefix
+            .iter()
+            .enumerate()
+            .filter(|&(i, &b)| b == b'\n' || (b == b'\r' && prefix.get(i + 1) != Some(&b'\n')))
+            .count();
+        Ok(breaks as u64 + 1)
+    }
+
+    // For each of the sorted `offsets`, the index (header included) and
+    // start of the first record starting at or after it, or None if no
+    // record does. Scans only as far as the last offset's record.
+    fn locate_records(
+        &self,
+        offsets: &[u64],
+        cancel: &Cancel,
+    ) -> std::io::Result<Vec<Option<(usize, u64)>>> {
+        let mut file = self.open_buffered()?;
+        let mut scanner = self.record_scanner();
+        let mut located = Vec::with_capacity(offsets.len());
+        let mut records: usize = 0;
+        let mut buf = vec![0u8; BUF_SIZE];
+        let mut base: u64 = 0;
+
+        while located.len() < offsets.len() && !cancel.cancelled() {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            scanner.feed(&buf[..n], base, |start| {
+                while located.len() < offsets.len() && offsets[located.len()] <= start {
+                    located.push(Some((records, start)));
+                }
+                records += 1;
+                cancel.check(records);
+            });
+            base += n as u64;
+        }
+        located.resize(offsets.len(), None);
+        Ok(located)
+    }
+
+    // Whether the bytes from `from` up to `to` are all line endings, i.e.
+    // `from` is where the record at `to` starts or a blank line before it
+    fn only_line_endings(&self, file: &mut Input, from: u64, to: u64) -> PyResult<bool> {
+        let mut gap = Vec::new();
+        let read = file
+            .seek(SeekFrom::Start(from))
+            .and_then(|_| file.take(to - from).read_to_end(&mut gap));
+        if let Err(e) = read {
+            return Err(files::io_error("read file", e));
+        }
+        let scanner = self.record_scanner();
+        Ok(gap.iter().all(|&b| scanner.is_terminator(b)))
+    }
+
+    // Offset of the first record that starts at or after `offset`, found by
+    // scanning one buffer from there (see RecordScanner::sync_point)
+    fn record_start_after(&self, offset: u64) -> PyResult<Option<u64>> {
+        let mut file = self.open_buffered()?;
+        let mut window = Vec::with_capacity(BUF_SIZE);
+        let read = file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| file.take(BUF_SIZE as u64).read_to_end(&mut window));
+        if let Err(e) = read {
+            return Err(files::io_error("read file", e));
+        }
+
+        Ok(self
+            .record_scanner()
+            .sync_point(&window)
+            .map(|i| offset + i as u64))
+    }
+
+    // Indices of the columns in which every cell is empty ("" or one of
+    // na_values). One pass over the records without the GIL, looking at
+    // bytes only, that stops once every column has had a non-empty cell. A
+    // row too short to have a column counts as empty there. With no data
+    // rows nothing is known about any column, so none is empty.
+    fn empty_column_indices(&self, py: Python) -> PyResult<Vec<usize>> {
+        let file = self.open_buffered()?;
+        let mut reader = self.reader_builder().from_reader(file);
+        let headers = self.read_headers(&mut reader)?;
+
+        let max_rows = self.max_rows();
+        let cancel = self.cancel();
+        let na_values = &self.na_values;
+        let scanned = files::without_gil(py, || -> csv::Result<(Vec<bool>, usize, usize)> {
+            let mut filled = vec![false; headers.len()];
+            let mut remaining = headers.len();
+            let mut record = ByteRecord::new();
+            let mut rows: usize = 0;
+            while remaining > 0
+                && rows < max_rows
+                && !cancel.check(rows)
+                && reader.read_byte_record(&mut record)?
+            {
+                rows += 1;
+                for (seen, field) in filled.iter_mut().zip(record.iter()) {
+                    if !*seen
+                        && !field.is_empty()
+                        && !na_values.iter().any(|na| na.as_bytes() == field)
+                    {
+                        *seen = true;
+                        remaining -= 1;
+                    }
+                }
+            }
+            Ok((filled, rows, remaining))
+        });
+        cancel.raise()?;
+        let (filled, rows) = match scanned {
+            Ok((filled, rows, remaining)) => {
+                if remaining > 0 {
+                    self.enforce_row_limit(&mut reader, rows)?;
+                }
+                (filled, rows)
+            }
+            Err(e) => return Err(self.record_error(py, e)),
+        };
+        if rows == 0 {
+            return Ok(Vec::new());
+        }
+
+        Ok(filled
+            .iter()
+            .enumerate()
+            .filter(|(_, seen)| !**seen)
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    // seek_strategy="auto": how many rows to skip after a seek that
+    // landed on the record starting at `pos` to reach `start_row`. The row
+    // there is counted (see row_at); if it is past start_row, or more than
+    // SEEK_TOLERANCE of start_row before it, or no record starts there,
+    // this warns, counts a seek fallback and returns None so the caller
+    // reads exactly.
+    fn landing_skip(&self, py: Python, pos: u64, start_row: usize) -> PyResult<Option<usize>> {
+        let landed = self.row_at(py, pos)?;
+        let tolerance = (start_row as f64 * SEEK_TOLERANCE) as usize;
+        let landed = match landed {
+            Some(row) if row <= start_row && start_row - row <= tolerance => {
+                return Ok(Some(start_row - row));
+            }
+            Some(row) => format!("row {}", row),
+            None => "no row start".to_string(),
+        };
+
+        self.seek_fallbacks.fetch_add(1, Ordering::Relaxed);
+        let message = format!(
+            "The seek for row {} of {} landed on {} (byte {}); \
+             read_chunk_optimized will skip rows exactly for this call",
+            start_row, self.filename, landed, pos
+        );
+        self.log_event(py, "warning", message.clone(), &[])?;
+        PyErr::warn(
+            py,
+            py.get_type::<pyo3::exceptions::PyUserWarning>(),
+            &message,
+            1,
+        )?;
+        Ok(None)
+    }
+
+    // The row whose record starts at `pos`, or None if no record starts
+    // there. Record starts are counted with the RecordScanner, without the
+    // GIL, from the nearest anchor before `pos` (see seek_anchors) or the
+    // top of the file, and `pos` becomes an anchor.
+    fn row_at(&self, py: Python, pos: u64) -> PyResult<Option<usize>> {
+        let mut file = self.open_buffered()?;
+        let watch = file.get_ref().watch();
+        let (from, first) = {
+            let mut anchors = self.seek_anchors.lock().unwrap();
+            if anchors
+                .as_ref()
+                .is_some_and(|a| !a.watch.unchanged(self.data_path()))
+            {
+                *anchors = None;
+            }
+            anchors
+                .as_ref()
+                .and_then(|a| a.known.range(..=pos).next_back())
+                .map_or((0, 0), |(&from, &record)| (from, record))
+        };
+        if let Err(e) = file.seek(SeekFrom::Start(from)) {
+            return Err(files::io_error("read file", e));
+        }
+
+        let cancel = self.cancel();
+        let counted = files::without_gil(py, || -> std::io::Result<Option<usize>> {
+            let mut scanner = self.record_scanner();
+            let mut record = first;
+            let mut found = None;
+            let mut buf = vec![0u8; BUF_SIZE];
+            let mut base = from;
+            while base <= pos && !cancel.cancelled() {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                scanner.feed(&buf[..n], base, |start| {
+                    if start == pos {
+                        found = Some(record);
+                    }
+                    if start < pos {
+                        record += 1;
+                        cancel.check(record);
+                    }
+                });
+                base += n as u64;
+            }
+            Ok(found)
+        });
+        cancel.raise()?;
+        let found = match counted {
+            Ok(found) => found,
+            Err(e) => return Err(files::io_error("read file", e)),
+        };
+
+        let skip = usize::from(self.has_headers);
+        if let Some(record) = found {
+            let mut anchors = self.seek_anchors.lock().unwrap();
+            let known = &mut anchors
+                .get_or_insert_with(|| SeekAnchors {
+                    watch,
+                    known: BTreeMap::new(),
+                })
+                .known;
+            known.insert(pos, record);
+            if known.len() > SEEK_ANCHORS {
+                let mut kept = false;
+                known.retain(|_, _| {
+                    kept = !kept;
+                    kept
+                });
+            }
+        }
+        Ok(found.and_then(|record| record.checked_sub(skip)))
+    }
+
+    // Most rows any loop may process: row_limit, or the rows before the
+    // blank line with stop_at_blank_line, whichever is fewer
+    fn max_rows(&self) -> usize {
+        let limit = self.row_limit.unwrap_or(usize::MAX);
+        match &self.data_end {
+            Some(end) => limit.min(end.rows),
+            None => limit,
+        }
+    }
+
+    // Whether reaching max_rows() with rows left should raise: row_limit is
+    // strict and it, not a blank line, is what ends the data
+    fn strict_limit(&self) -> bool {
+        match (self.row_limit, &self.data_end) {
+            (Some(limit), Some(end)) => self.row_limit_strict && limit < end.rows,
+            (Some(_), None) => self.row_limit_strict,
+            (None, _) => false,
+        }
+    }
+
+    // Call after a loop that stopped at `rows` processed rows. In strict
+    // mode, if the loop stopped because of row_limit and the file has
+    // another row, raise; otherwise the result is silently truncated.
+    fn enforce_row_limit<R: Read>(&self, reader: &mut csv::Reader<R>, rows: usize) -> PyResult<()> {
+        if !self.strict_limit() || rows < self.max_rows() {
+            return Ok(());
+        }
+
+        let mut probe = ByteRecord::new();
+        match reader.read_byte_record(&mut probe) {
+            Ok(true) => Err(self.row_limit_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn row_limit_error(&self) -> PyErr {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "{} has more than row_limit={} rows",
+            self.filename,
+            self.max_rows()
+        ))
+    }
+
+    // max_columns, or no limit
+    fn column_limit(&self) -> usize {
+        self.max_columns.unwrap_or(usize::MAX)
+    }
+
+    // Let Ctrl-C interrupt long loops: every signal_check_interval rows, run
+    // Python's signal handlers and propagate any exception they raise.
+    // Returning the error unwinds the caller, which drops (closes) its file.
+    // Every gil_yield_every rows, also release the GIL for a moment: a
+    // thread waiting for it asks for it after the interpreter's switch
+    // interval, so releasing it hands it over, and this loop waits its
+    // turn to take it back.
+    fn check_signals(&self, py: Python, rows: usize) -> PyResult<()> {
+        if self.signal_check_interval > 0 && rows.is_multiple_of(self.signal_check_interval) {
+            py.check_signals()?;
+        }
+        if self.gil_yield_every > 0 && rows.is_multiple_of(self.gil_yield_every) {
+            py.allow_threads(std::thread::yield_now);
+            py.check_signals()?;
+        }
+        Ok(())
+    }
+
+    // check_signals for a scan that runs without the GIL (see Cancel)
+    fn cancel(&self) -> Cancel {
+        Cancel::new(self.signal_check_interval)
+    }
+
+    fn column_index(&self, headers: &StringRecord, column: &str) -> PyResult<usize> {
+        match headers.iter().position(|h| h == column) {
+            Some(i) => Ok(i),
+            None => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Unknown column: {}",
+                column
+            ))),
+        }
+    }
+
+    // join's right side: the names of the `columns` taken from the file at
+    // `path`, and a map from each `on` cell to those cells of every row with
+    // that key. The file is read with this parser's dialect, always with a
+    // header row, whose names are cleaned as read_headers cleans this
+    // file's, so `on` and `columns` name the same columns in both. The
+    // width options are this file's and don't apply. A row too short for a
+    // column gets "" there.
+    fn join_table(
+        &self,
+        path: &str,
+        on: &str,
+        columns: Option<Vec<String>>,
+        cancel: &Cancel,
+    ) -> PyResult<(Vec<String>, JoinTable)> {
+        let file = match files::open(Path::new(path)) {
+            Ok(f) => f,
+            Err(e) => return Err(files::io_error("open file", e)),
+        };
+        let mut reader = self
+            .reader_builder()
+            .has_headers(true)
+            .from_reader(BufReader::with_capacity(BUF_SIZE, file));
+        let failed = |e: csv::Error| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Failed to read {}: {}",
+                path, e
+            ))
+        };
+
+        let headers = self.clean_headers(reader.headers().map_err(failed)?.clone());
+        let key = match headers.iter().position(|h| h == on) {
+            Some(i) => i,
+            None => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Unknown column in {}: {}",
+                    path, on
+                )));
+            }
+        };
+        let names: Vec<String> = match columns {
+            Some(names) => {
+                check_columns(&headers, &names, "other_columns")?;
+                names
+            }
+            None => headers
+                .iter()
+                .filter(|&h| h != on)
+                .map(String::from)
+                .collect(),
+        };
+        let indices: Vec<usize> = names
+            .iter()
+            .map(|n| headers.iter().position(|h| h == n).unwrap())
+            .collect();
+
+        let mut table = JoinTable::new();
+        let mut record = StringRecord::new();
+        let mut rows: usize = 0;
+        while !cancel.check(rows) && reader.read_record(&mut record).map_err(failed)? {
+            rows += 1;
+            let cells = indices
+                .iter()
+                .map(|&i| record.get(i).unwrap_or("").to_string())
+                .collect();
+            table
+                .entry(record.get(key).unwrap_or("").to_string())
+                .or_default()
+                .push(cells);
+        }
+        Ok((names, table))
+    }
+
+    // Emit a structured record on the `csv_reader` logger. Events are only
+    // logged at phase boundaries, never per row. The event name and `fields`
+    // are attached to the LogRecord through `extra`, so handlers can read
+    // e.g. `record.event` and `record.rows`.
+    fn log_event(
+        &self,
+        py: Python,
+        event: &str,
+        message: String,
+        fields: &[(&str, PyObject)],
+    ) -> PyResult<()> {
+        let level = if self.verbose { LOG_INFO } else { LOG_DEBUG };
+        let logger = py
+            .import("logging")?
+            .call_method1("getLogger", ("csv_reader",))?;
+        if !logger.call_method1("isEnabledFor", (level,))?.is_true()? {
+            return Ok(());
+        }
+
+        let extra = PyDict::new(py);
+        extra.set_item("event", event)?;
+        extra.set_item("path", &self.filename)?;
+        for (key, value) in fields {
+            extra.set_item(*key, value)?;
+        }
+
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("extra", extra)?;
+        logger.call_method("log", (level, message), Some(kwargs))?;
+        Ok(())
+    }
+
+    fn log_completion(
+        &self,
+        py: Python,
+        rows: usize,
+        batches: usize,
+        started: Instant,
+        truncated: bool,
+    ) -> PyResult<()> {
+        self.last_read_truncated.store(truncated, Ordering::Relaxed);
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        self.log_event(
+            py,
+            "complete",
+            format!(
+                "Read {} rows in {} batches ({:.1} ms)",
+                rows, batches, elapsed_ms
+            ),
+            &[
+                ("rows", rows.to_object(py)),
+                ("batches", batches.to_object(py)),
+                ("elapsed_ms", elapsed_ms.to_object(py)),
+                ("truncated", truncated.to_object(py)),
+            ],
+        )
+    }
+
+    // Whether the file changed under a streaming read that has got `offset`
+    // bytes and `rows` rows in (see FileWatch::check). A change raises
+    // FileChangedDuringRead, unless tolerate_truncation is set; then it is
+    // logged and true is returned, and the read should stop.
+    fn file_changed(
+        &self,
+        py: Python,
+        watch: &FileWatch,
+        offset: u64,
+        at_eof: bool,
+        rows: usize,
+    ) -> PyResult<bool> {
+        let what = match watch.check(self.data_path(), offset, at_eof) {
+            None => return Ok(false),
+            Some(FileChange::Shrunk(size)) => format!("was truncated to {} bytes", size),
+            Some(FileChange::Replaced) => "was replaced or removed".to_string(),
+        };
+        let message = format!(
+            "{} {} during the read, after {} rows (byte {})",
+            self.filename, what, rows, offset
+        );
+
+        if !self.tolerate_truncation {
+            let err = FileChangedDuringRead::new_err(message);
+            let value = err.value(py);
+            value.setattr("rows", rows)?;
+            value.setattr("offset", offset)?;
+            return Err(err);
+        }
+
+        self.log_event(
+            py,
+            "warning",
+            format!("{}; stopping there", message),
+            &[
+                ("rows", rows.to_object(py)),
+                ("offset", offset.to_object(py)),
+            ],
+        )?;
+        Ok(true)
+    }
+
+    // Resolve the per-column settings against the header once per read, so
+    // the per-row work in build_row is plain indexing.
+    fn row_layout(&self, py: Python, headers: &StringRecord) -> PyResult<RowLayout> {
+        self.query_layout(py, headers, None)
+    }
+
+    // Header indices of the columns rows keep, in order: those `columns`
+    // names, `query_columns` in its place, or all but drop_empty_columns'
+    // dropped ones. None when every column is kept.
+    fn projection(
+        &self,
+        headers: &StringRecord,
+        query_columns: Option<&Vec<String>>,
+    ) -> PyResult<Option<Vec<usize>>> {
+        Ok(match query_columns.or(self.columns.as_ref()) {
+            Some(columns) => {
+                check_columns(headers, columns, "columns")?;
+                Some(
+                    columns
+                        .iter()
+                        .map(|c| headers.iter().position(|h| h == c).unwrap())
+                        .collect(),
+                )
+            }
+            None if !self.dropped_columns.is_empty() => Some(
+                (0..headers.len())
+                    .filter(|i| !self.dropped_columns.contains(i))
+                    .collect(),
+            ),
+            None => None,
+        })
+    }
+
+    // row_layout() with a Query's columns and schema taking the place of
+    // the parser's `columns` and conversions for the columns it names
+    fn query_layout(
+        &self,
+        py: Python,
+        headers: &StringRecord,
+        query: Option<&Query>,
+    ) -> PyResult<RowLayout> {
+        check_columns(headers, self.defaults.keys(), "defaults")?;
+        check_columns(headers, &self.decimal_columns, "decimal_columns")?;
+        check_columns(headers, &self.json_columns, "json_columns")?;
+        check_columns(headers, self.rename.keys(), "rename")?;
+
+        let names: Vec<&str> = headers
+            .iter()
+            .map(|h| self.rename.get(h).map_or(h, |n| n.as_str()))
+            .collect();
+        let keys: Vec<PyObject> = names.iter().map(|n| n.to_object(py)).collect();
+        let defaults = headers
+            .iter()
+            .map(|h| match self.defaults.get(h) {
+                Some(default) if self.all_strings => Ok(Some(as_string(py, default)?)),
+                Some(default) => Ok(Some(default.clone_ref(py))),
+                None => Ok(None),
+            })
+            .collect::<PyResult<_>>()?;
+        let decimal_type = if self.decimal_columns.is_empty() || self.all_strings {
+            None
+        } else {
+            Some(py.import("decimal")?.getattr("Decimal")?.to_object(py))
+        };
+        let kinds = headers
+            .iter()
+            .map(|h| {
+                if self.all_strings {
+                    return ColumnKind::Str;
+                }
+                if let Some(kind) = query.and_then(|q| q.kind(py, h)) {
+                    return kind;
+                }
+                match &decimal_type {
+                    Some(t) if self.decimal_columns.iter().any(|c| c == h) => {
+                        ColumnKind::Decimal(t.clone_ref(py))
+                    }
+                    _ if self.json_columns.iter().any(|c| c == h) => ColumnKind::Json,
+                    _ => ColumnKind::Str,
+                }
+            })
+            .collect();
+
+        let hash = if self.include_row_hash {
+            if names.contains(&ROW_HASH_KEY) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "include_row_hash would overwrite the existing column {}",
+                    ROW_HASH_KEY
+                )));
+            }
+            Some(hash_indices(headers, self.hash_columns.as_ref())?)
+        } else {
+            None
+        };
+
+        let row_number = match &self.add_row_number {
+            Some(key) => {
+                if names.contains(&key.as_str()) || (hash.is_some() && key == ROW_HASH_KEY) {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "add_row_number would overwrite the existing column {}",
+                        key
+                    )));
+                }
+                Some(key.to_object(py))
+            }
+            None => None,
+        };
+
+        let rest = if self.dictreader_compat {
+            let value = if self.all_strings {
+                as_string(py, &self.restval)?
+            } else {
+                self.restval.clone_ref(py)
+            };
+            Some(RestFields {
+                key: self.restkey.clone_ref(py),
+                value,
+            })
+        } else {
+            None
+        };
+
+        let projection = self.projection(headers, query.and_then(|q| q.columns.as_ref()))?;
+
+        // The sparse path decodes only the selected fields, so it can't
+        // serve features that look at the whole record
+        let sparse = match &projection {
+            Some(indices) => {
+                indices.len() * SPARSE_FRACTION <= headers.len() && hash.is_none() && rest.is_none()
+            }
+            None => false,
+        };
+        let path = match (&projection, sparse) {
+            (None, _) => 0,
+            (Some(_), false) => 1,
+            (Some(_), true) => 2,
+        };
+        self.last_projection.store(path, Ordering::Relaxed);
+        if let Some(indices) = &projection {
+            let mode = projection_name(path).unwrap_or_default();
+            self.log_event(
+                py,
+                "path",
+                format!(
+                    "Projecting {} of {} columns ({})",
+                    indices.len(),
+                    headers.len(),
+                    mode
+                ),
+                &[("mode", mode.to_object(py))],
+            )?;
+        }
+
+        // Under the limited API pyo3 can't create a presized dict, but a
+        // copy of a dict that already holds every key has its final size
+        // from the start, so filling it in never resizes. Not used with
+        // sparse=True, where empty cells' keys must stay absent.
+        let selected: Vec<usize> = match &projection {
+            Some(indices) => indices.clone(),
+            None => (0..headers.len()).collect(),
+        };
+
+        // A renamed column must not take the key of another kept column
+        for &i in &selected {
+            if self.rename.contains_key(&headers[i])
+                && selected.iter().any(|&j| j != i && names[j] == names[i])
+            {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "rename gives {} the key {}, which another column already has",
+                    &headers[i], names[i]
+                )));
+            }
+        }
+        let template = if self.omit_empty {
+            None
+        } else {
+            let template = PyDict::new(py);
+            for &i in &selected {
+                template.set_item(&keys[i], py.None())?;
+            }
+            Some(template.into())
+        };
+        let template_width = selected.iter().max().map_or(0, |&i| i + 1);
+
+        Ok(RowLayout {
+            keys,
+            defaults,
+            kinds,
+            rest,
+            hash,
+            row_number,
+            projection,
+            sparse,
+            template,
+            template_width,
+            strings: RefCell::new(StringCache::new(
+                headers.len(),
+                self.intern_max_len,
+                self.intern_max_values,
+            )),
+            timings: None,
+        })
+    }
+
+    // Build the Python dict for one record (the "field helper"). Fields
+    // beyond the header width are dropped, except in dictreader compat mode
+    // where they are kept under restkey and missing fields get restval.
+    // With a `columns` projection only the selected fields are kept, and in
+    // compat mode extra fields aren't collected.
+    fn build_row<'py>(
+        &self,
+        py: Python<'py>,
+        layout: &RowLayout,
+        record: &StringRecord,
+    ) -> PyResult<&'py PyDict> {
+        let row = new_row(py, layout, record.len())?;
+
+        match &layout.projection {
+            None => {
+                for (i, field) in record.iter().enumerate() {
+                    if i < layout.keys.len() {
+                        self.set_field(py, row, layout, i, field, record.position())?;
+                    }
+                }
+            }
+            Some(indices) => {
+                for &i in indices {
+                    if let Some(field) = record.get(i) {
+                        self.set_field(py, row, layout, i, field, record.position())?;
+                    }
+                }
+            }
+        }
+        set_missing_defaults(row, layout, record.len())?;
+
+        if let Some(indices) = &layout.hash {
+            let hash = format!("{:016x}", row_hash(record, indices));
+            row.set_item(ROW_HASH_KEY, hash)?;
+        }
+
+        if let Some(rest) = &layout.rest {
+            match &layout.projection {
+                None => {
+                    let width = layout.keys.len();
+                    let end = record.len().min(self.column_limit());
+                    if end > width {
+                        let extra: Vec<&str> = record.iter().take(end).skip(width).collect();
+                        row.set_item(&rest.key, PyList::new(py, extra))?;
+                    }
+                    for i in record.len()..layout.keys.len() {
+                        if layout.defaults[i].is_none() {
+                            row.set_item(&layout.keys[i], &rest.value)?;
+                        }
+                    }
+                }
+                Some(indices) => {
+                    for &i in indices.iter().filter(|&&i| i >= record.len()) {
+                        if layout.defaults[i].is_none() {
+                            row.set_item(&layout.keys[i], &rest.value)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(row)
+    }
+
+    // build_row for the sparse path: only the projected fields of the raw
+    // record are decoded, the rest are never validated as UTF-8
+    fn build_sparse_row<'py>(
+        &self,
+        py: Python<'py>,
+        layout: &RowLayout,
+        record: &ByteRecord,
+    ) -> PyResult<&'py PyDict> {
+        let row = new_row(py, layout, record.len())?;
+
+        for &i in layout.projection.iter().flatten() {
+            let bytes = match record.get(i) {
+                Some(b) => b,
+                None => continue,
+            };
+            let field = match std::str::from_utf8(bytes) {
+                Ok(f) => f,
+                Err(e) => {
+                    let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Invalid UTF-8 in column {}: {}",
+                        layout.keys[i], e
+                    ));
+                    return Err(self.with_raw(py, err, record.position()));
+                }
+            };
+            self.set_field(py, row, layout, i, field, record.position())?;
+        }
+        set_missing_defaults(row, layout, record.len())?;
+
+        Ok(row)
+    }
+
+    // Field `i`'s text as set_field converts it: without leading zero-width
+    // characters with strip_zero_width, and cleaned for numeric columns
+    // with clean
+    fn cleaned_field<'a>(&self, layout: &RowLayout, i: usize, field: &'a str) -> Cow<'a, str> {
+        let field = if self.strip_zero_width {
+            field.trim_start_matches(ZERO_WIDTH)
+        } else {
+            field
+        };
+        match &layout.kinds[i] {
+            ColumnKind::Decimal(_) | ColumnKind::Int | ColumnKind::Float if self.clean => {
+                clean_field(field, &self.clean_chars)
+            }
+            _ => Cow::Borrowed(field),
+        }
+    }
+
+    // Store field `i` in `row`, applying its default and conversion
+    fn set_field(
+        &self,
+        py: Python,
+        row: &PyDict,
+        layout: &RowLayout,
+        i: usize,
+        field: &str,
+        position: Option<&csv::Position>,
+    ) -> PyResult<()> {
+        let key = &layout.keys[i];
+        // Cleaning happens first, so a cell that is only whitespace counts
+        // as empty and gets the column's default
+        let field = self.cleaned_field(layout, i, field);
+        // Only conversions are timed: a clock read per string cell would
+        // cost about as much as storing it
+        let started = match (&layout.timings, &layout.kinds[i]) {
+            (Some(_), ColumnKind::Str) | (None, _) => None,
+            (Some(_), _) => Some(Instant::now()),
+        };
+        let stored = match (&layout.defaults[i], &layout.kinds[i]) {
+            (Some(default), _) if field.is_empty() => row.set_item(key, default),
+            (None, _) if field.is_empty() && self.omit_empty => Ok(()),
+            // Direct set without unnecessary conversions
+            (_, ColumnKind::Str) => {
+                row.set_item(key, layout.strings.borrow_mut().get(py, i, &field))
+            }
+            (_, ColumnKind::Decimal(decimal_type)) => {
+                let value = to_decimal(py, decimal_type, key, &field)
+                    .map_err(|e| self.with_raw(py, e, position))?;
+                row.set_item(key, value)
+            }
+            (_, ColumnKind::Json) => match json::parse(py, &field)? {
+                Some(value) => row.set_item(key, value),
+                None if self.json_invalid == JsonInvalid::Raw => row.set_item(key, &*field),
+                None => row.set_item(key, py.None()),
+            },
+            (_, ColumnKind::Int) => {
+                let value = to_number(py, Converter::Int, key, &field)
+                    .map_err(|e| self.with_raw(py, e, position))?;
+                row.set_item(key, value)
+            }
+            (_, ColumnKind::Float) => {
+                let value = to_number(py, Converter::Float, key, &field)
+                    .map_err(|e| self.with_raw(py, e, position))?;
+                row.set_item(key, value)
+            }
+        };
+        if let (Some(timings), Some(started)) = (&layout.timings, started) {
+            Timings::add(&timings.convert, started);
+        }
+        stored
+    }
+
+    // Read the next record from `reader` and build its row dict, or None at
+    // the end of the file. Every dict-producing loop goes through here so
+    // the sparse path applies everywhere.
+    fn next_row<'py, R: Read + QuoteSource>(
+        &self,
+        py: Python<'py>,
+        reader: &mut csv::Reader<R>,
+        layout: &RowLayout,
+        buf: &mut RecordBuf,
+    ) -> PyResult<Option<&'py PyDict>> {
+        if let Some(timings) = &layout.timings {
+            let read_before = timings.read.load(Ordering::Relaxed);
+            let started = Instant::now();
+            if !self.next_record(py, reader, layout, buf)? {
+                return Ok(None);
+            }
+            Timings::add_outer(&timings.parse, started, &timings.read, read_before);
+            let convert_before = timings.convert.load(Ordering::Relaxed);
+            let started = Instant::now();
+            let row = self.buffered_row(py, layout, buf)?;
+            Timings::add_outer(&timings.build, started, &timings.convert, convert_before);
+            return Ok(Some(row));
+        }
+
+        if self.next_record(py, reader, layout, buf)? {
+            Ok(Some(self.buffered_row(py, layout, buf)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // The record half of next_row: read the next record into `buf`, with
+    // the single_column and schema_drift handling, and return false at the
+    // end of the file. For loops that look at the record before deciding
+    // whether to build a dict for it.
+    fn next_record<R: Read + QuoteSource>(
+        &self,
+        py: Python,
+        reader: &mut csv::Reader<R>,
+        layout: &RowLayout,
+        buf: &mut RecordBuf,
+    ) -> PyResult<bool> {
+        let read = if layout.sparse {
+            reader.read_byte_record(&mut buf.bytes)
+        } else {
+            reader.read_record(&mut buf.record)
+        };
+
+        match read {
+            Ok(true) => {
+                // Fields the sparse path won't decode must still be valid
+                // UTF-8, as read_record checks, so whether a file reads
+                // doesn't depend on the columns selected
+                if layout.sparse {
+                    if let Some((field, valid_up_to)) = invalid_utf8(&buf.bytes) {
+                        return Err(self.utf8_error(py, &buf.bytes, field, valid_up_to));
+                    }
+                }
+                buf.next_row += 1;
+                if self.single_column.is_some() {
+                    rejoin_line(layout, buf);
+                }
+                if self.schema_drift != SchemaDrift::Ignore {
+                    // A reader started mid-file has no header line to repeat
+                    if buf.header_names.is_none() && reader.has_headers() {
+                        let names = reader.headers().map_err(|e| self.record_error(py, e))?;
+                        buf.header_names =
+                            Some(names.iter().map(|n| n.as_bytes().to_vec()).collect());
+                    }
+                    self.check_drift(py, layout, buf)?;
+                }
+                if self.distinguish_empty_quoted && !self.all_strings {
+                    take_quoted_empty(reader, buf);
+                }
+                Ok(true)
+            }
+            Ok(false) => Ok(false),
+            Err(e) => Err(self.record_error(py, e)),
+        }
+    }
+
+    // Row dict for the record next_record just read into `buf`
+    fn buffered_row<'py>(
+        &self,
+        py: Python<'py>,
+        layout: &RowLayout,
+        buf: &RecordBuf,
+    ) -> PyResult<&'py PyDict> {
+        let row = if layout.sparse {
+            self.build_sparse_row(py, layout, &buf.bytes)?
+        } else {
+            self.build_row(py, layout, &buf.record)?
+        };
+        if let Some(key) = &layout.row_number {
+            row.set_item(key, buf.next_row - 1 + self.row_number_start)?;
+        }
+        if self.distinguish_empty_quoted && !self.all_strings {
+            mark_empty_cells(py, layout, buf, row)?;
+        }
+        Ok(row)
+    }
+
+    // schema_drift: compare the record just read into `buf` with the header
+    // width, and check it isn't a repeated header; raise or warn (once per
+    // read) if it drifted
+    fn check_drift(&self, py: Python, layout: &RowLayout, buf: &mut RecordBuf) -> PyResult<()> {
+        let (found, position) = if layout.sparse {
+            (buf.bytes.len(), buf.bytes.position())
+        } else {
+            (buf.record.len(), buf.record.position())
+        };
+        let expected = layout.keys.len();
+        // Records never have fields past max_columns (see FieldCutter)
+        let width_drifted = found != expected;
+        let names = match width_drifted {
+            true => None,
+            false => self.repeats_header(layout, buf),
+        };
+        let header = names.is_some();
+        if !width_drifted && !header {
+            return Ok(());
+        }
+
+        // The caller's row count, which has counted this record already.
+        // The record's Position can't be used: a reader that seeked counts
+        // records from where it landed.
+        let row = buf.next_row.saturating_sub(1);
+        let message = match names {
+            Some(names) => format!(
+                "Schema drift at row {}: a repeated header, {} of whose {} fields are column names",
+                row, names, found
+            ),
+            None => format!(
+                "Schema drift at row {}: expected {} fields, found {}",
+                row, expected, found
+            ),
+        };
+
+        if self.schema_drift == SchemaDrift::Error {
+            let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(message);
+            let value = err.value(py);
+            value.setattr("row", row)?;
+            value.setattr("expected", expected)?;
+            value.setattr("found", found)?;
+            value.setattr("header", header)?;
+            return Err(self.with_raw(py, err, position));
+        }
+
+        if !buf.drift_warned {
+            buf.drift_warned = true;
+            self.log_event(
+                py,
+                "warning",
+                message.clone(),
+                &[("row", row.to_object(py))],
+            )?;
+            PyErr::warn(
+                py,
+                py.get_type::<pyo3::exceptions::PyUserWarning>(),
+                &message,
+                1,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Whether the record in `buf` looks like a header line: all of its
+    // fields but at most one, and at least two, are names from the file's
+    // header line, in any order, as a second header with a column renamed
+    // or some moved would be. A data row can hold a value that happens to
+    // be a column name. The number of those fields if so.
+    fn repeats_header(&self, layout: &RowLayout, buf: &RecordBuf) -> Option<usize> {
+        let names = buf.header_names.as_ref()?;
+        let matched = if layout.sparse {
+            buf.bytes.iter().filter(|f| names.contains(*f)).count()
+        } else {
+            buf.record
+                .iter()
+                .filter(|f| names.contains(f.as_bytes()))
+                .count()
+        };
+        let found = if layout.sparse {
+            buf.bytes.len()
+        } else {
+            buf.record.len()
+        };
+        (matched >= 2 && matched + 1 >= found).then_some(matched)
+    }
+
+    // Drain `reader` into a list of batch lists of `batch_size` rows each.
+    // With a `watch`, the file is checked for changes after every batch and
+    // at the end. `timings`, for read(), times the rows.
+    fn collect_batches<R: Read + QuoteSource>(
+        &self,
+        py: Python,
+        reader: &mut csv::Reader<R>,
+        estimated_batches: usize,
+        watch: Option<&FileWatch>,
+        timings: Option<&Arc<Timings>>,
+    ) -> PyResult<Vec<PyObject>> {
+        let headers = self.read_headers(reader)?;
+        let mut layout = self.row_layout(py, &headers)?;
+        layout.timings = timings.cloned();
+        let started = Instant::now();
+
+        let mut batches: Vec<PyObject> = Vec::with_capacity(estimated_batches);
+        let mut current_batch = PyList::empty(py);
+        let mut count: usize = 0;
+        let mut total_rows: usize = 0;
+        let mut truncated = false;
+
+        // Process records in batches for better memory usage
+        let mut buf = RecordBuf::default();
+        while total_rows < self.max_rows() {
+            let row = match self.next_row(py, reader, &layout, &mut buf)? {
+                Some(row) => row,
+                None => {
+                    if let Some(watch) = watch {
+                        let offset = reader.position().byte();
+                        truncated = self.file_changed(py, watch, offset, true, total_rows)?;
+                    }
+                    break;
+                }
+            };
+            current_batch.append(row)?;
+            count += 1;
+            total_rows += 1;
+            self.check_signals(py, total_rows)?;
+
+            // When batch is full, add to batches and create new batch
+            if count >= self.batch_size {
+                batches.push(current_batch.to_object(py));
+                current_batch = PyList::empty(py);
+                count = 0;
+
+                if let Some(watch) = watch {
+                    let offset = reader.position().byte();
+                    if self.file_changed(py, watch, offset, false, total_rows)? {
+                        truncated = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Don't forget remaining rows
+        if count > 0 {
+            batches.push(current_batch.to_object(py));
+        }
+        if !truncated {
+            self.enforce_row_limit(reader, total_rows)?;
+        }
+
+        self.log_completion(py, total_rows, batches.len(), started, truncated)?;
+
+        Ok(batches)
+    }
+
+    // read_optimized()'s path for small files: every row into one flat
+    // list, without collect_batches' per-batch bookkeeping, cut into
+    // batch_size batches at the end only if it holds more. The batches are
+    // the ones collect_batches would return.
+    fn collect_flat<R: Read + QuoteSource>(
+        &self,
+        py: Python,
+        reader: &mut csv::Reader<R>,
+        timings: Option<&Arc<Timings>>,
+    ) -> PyResult<Vec<PyObject>> {
+        let headers = self.read_headers(reader)?;
+        let mut layout = self.row_layout(py, &headers)?;
+        layout.timings = timings.cloned();
+        let started = Instant::now();
+
+        let rows = PyList::empty(py);
+        let mut buf = RecordBuf::default();
+        while rows.len() < self.max_rows() {
+            match self.next_row(py, reader, &layout, &mut buf)? {
+                Some(row) => rows.append(row)?,
+                None => break,
+            }
+            self.check_signals(py, rows.len())?;
+        }
+        self.enforce_row_limit(reader, rows.len())?;
+
+        let total = rows.len();
+        let batches = match total {
+            0 => Vec::new(),
+            _ if total <= self.batch_size => vec![rows.to_object(py)],
+            _ => (0..total)
+                .step_by(self.batch_size)
+                .map(|start| rows.get_slice(start, start + self.batch_size).to_object(py))
+                .collect(),
+        };
+        self.log_completion(py, total, batches.len(), started, false)?;
+
+        Ok(batches)
+    }
+
+    // Read `num_rows` rows starting at `start_row`, both in `unit`, by
+    // skipping records, from the pooled reader furthest along that isn't
+    // past `start_row`, or else from the top of the file. Skipped records
+    // are only tokenized, not decoded. A reader that stops short of the end
+    // of the file goes back into the pool for the next call.
+    fn read_chunk_exact(
+        &self,
+        py: Python,
+        unit: ChunkUnit,
+        start_row: usize,
+        num_rows: usize,
+    ) -> PyResult<PyObject> {
+        // Rows at or past row_limit don't exist as far as chunks are concerned
+        let max_rows = self.max_rows();
+        let start = start_row as u64;
+        let end = start.saturating_add(num_rows as u64);
+
+        let first = match unit {
+            ChunkUnit::Records => start.min(max_rows as u64),
+            ChunkUnit::Lines => start,
+        };
+        let mut pooled = match self.take_pooled_reader(unit, first) {
+            Some(pooled) => pooled,
+            None => {
+                // With chunk_unit="lines" every pooled reader counts lines,
+                // since either unit can take it from the pool
+                let mut input = self.open_input()?;
+                if self.chunk_unit == ChunkUnit::Lines {
+                    input = input.count_lines();
+                }
+                let file = BufReader::with_capacity(BUF_SIZE, input);
+                let watch = file.get_ref().watch();
+                let reader = self.reader_builder().from_reader(file);
+                let mut pooled = PooledReader {
+                    reader,
+                    headers: StringRecord::new(),
+                    row: 0,
+                    data_line: 0,
+                    watch,
+                    last_used: 0,
+                };
+                pooled.headers = self.read_headers(&mut pooled.reader)?;
+                // Without a header, reading it took the first record, which
+                // the reader hands out again as row 0
+                if self.has_headers {
+                    pooled.data_line = pooled.line();
+                }
+                pooled
+            }
+        };
+        let layout = self.row_layout(py, &pooled.headers)?;
+
+        let chunk = PyList::empty(py);
+
+        // Skip rows until start_row
+        let mut skipped = ByteRecord::new();
+        while pooled.at(unit) < start && pooled.row < max_rows {
+            match pooled.reader.read_byte_record(&mut skipped) {
+                Ok(true) => {
+                    pooled.row += 1;
+                    self.check_signals(py, pooled.row)?;
+                }
+                // Reached end of file before start_row
+                Ok(false) => return Ok(chunk.to_object(py)),
+                Err(e) => {
+                    return Err(self.record_error(py, e));
+                }
+            }
+        }
+
+        // Read num_rows rows
+        let mut buf = RecordBuf {
+            bytes: skipped,
+            next_row: pooled.row,
+            ..Default::default()
+        };
+        let mut at_end = false;
+        while pooled.at(unit) < end && pooled.row < max_rows {
+            let row = match self.next_row(py, &mut pooled.reader, &layout, &mut buf)? {
+                Some(row) => row,
+                None => {
+                    at_end = true;
+                    break;
+                }
+            };
+            pooled.row += 1;
+            chunk.append(row)?;
+            self.check_signals(py, chunk.len())?;
+        }
+
+        // Only asking for rows past the limit can exceed it. A reader at
+        // the end of the file would miss rows appended later, so it isn't
+        // kept.
+        if pooled.row >= max_rows && pooled.at(unit) < end {
+            self.enforce_row_limit(&mut pooled.reader, max_rows)?;
+        } else if !at_end {
+            self.return_pooled_reader(pooled);
+        }
+
+        Ok(chunk.to_object(py))
+    }
+
+    // Take the pooled reader furthest along that hasn't passed `at`, in
+    // `unit`, and count a hit, or count a miss. Readers of a file that
+    // has changed since they opened it are dropped first.
+    fn take_pooled_reader(&self, unit: ChunkUnit, at: u64) -> Option<PooledReader> {
+        let mut pool = self.chunk_readers.lock().unwrap_or_else(|e| e.into_inner());
+        let path = self.data_path();
+        pool.readers.retain(|pooled| pooled.watch.unchanged(path));
+
+        let best = pool
+            .readers
+            .iter_mut()
+            .map(|pooled| pooled.at(unit))
+            .enumerate()
+            .filter(|&(_, pos)| pos <= at)
+            .max_by_key(|&(_, pos)| pos)
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => {
+                self.pool_hits.fetch_add(1, Ordering::Relaxed);
+                Some(pool.readers.swap_remove(i))
+            }
+            None => {
+                self.pool_misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    // Put `pooled` back, evicting the least recently used reader if the
+    // pool is full
+    fn return_pooled_reader(&self, mut pooled: PooledReader) {
+        let mut pool = self.chunk_readers.lock().unwrap_or_else(|e| e.into_inner());
+        pool.tick += 1;
+        pooled.last_used = pool.tick;
+        if pool.readers.len() >= CHUNK_POOL_SIZE {
+            if let Some(oldest) = (0..pool.readers.len()).min_by_key(|&i| pool.readers[i].last_used)
+            {
+                pool.readers.swap_remove(oldest);
+            }
+        }
+        pool.readers.push(pooled);
+    }
+}
+
+// Readers read_chunk() left positioned, so a paginated caller's next
+// chunk continues from where the last one stopped instead of reopening the
+// file and skipping from the top. get_row() and read_chunk_optimized()'s
+// exact reads share it. iter_rows() and lazy_batches() always start at the
+// top and don't use it: handing an abandoned iterator's reader to the pool
+// would also hand over its timings and file watch, which is left for later.
+#[derive(Default)]
+struct ReaderPool {
+    readers: Vec<PooledReader>,
+    // Incremented on every return, for least-recently-used eviction
+    tick: u64,
+}
+
+// Record starts whose record number (the header being record 0)
+// read_chunk_optimized counted, so the next count starts from the nearest
+// one. At most SEEK_ANCHORS of them; dropped when the file changes.
+struct SeekAnchors {
+    watch: FileWatch,
+    // Record number by offset
+    known: BTreeMap<u64, usize>,
+}
+
+struct PooledReader {
+    reader: csv::Reader<BufReader<Input>>,
+    headers: StringRecord,
+    // Rows read so far, i.e. the number of the next row
+    row: usize,
+    // Lines before the one the data starts on
+    data_line: u64,
+    watch: FileWatch,
+    last_used: u64,
+}
+
+impl PooledReader {
+    // Where the next record starts, in `unit`. Lines are counted in either
+    // unit, so the line breaks the reader has passed don't pile up.
+    fn at(&mut self, unit: ChunkUnit) -> u64 {
+        let line = self.line();
+        match unit {
+            ChunkUnit::Records => self.row as u64,
+            // A headerless reader that hasn't handed out row 0 yet is past
+            // it already
+            ChunkUnit::Lines if self.row == 0 => 0,
+            ChunkUnit::Lines => line - self.data_line,
+        }
+    }
+
+    // Lines before the one the next record starts on; 0 for a reader that
+    // doesn't count them
+    fn line(&mut self) -> u64 {
+        let pos = self.reader.position().byte();
+        self.reader.get_mut().get_mut().lines_before(pos)
+    }
+}
+
+// The first cell read_column_array() couldn't parse
+struct BadCell {
+    row: usize,
+    value: String,
+    position: Option<csv::Position>,
+}
+
+// The max_line_bytes error, if that is what stopped the csv reader
+fn line_error(e: &csv::Error) -> Option<PyErr> {
+    match e.kind() {
+        csv::ErrorKind::Io(io) => files::line_too_long(io),
+        _ => None,
+    }
+}
+
+fn mask_too_long(len: usize, rows: usize) -> PyErr {
+    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+        "Mask has {} entries but the file has only {} rows",
+        len, rows
+    ))
+}
+
+// Header-derived state for building row dicts, one entry per column
+struct RowLayout {
+    // Dict keys, created once instead of once per row
+    keys: Vec<PyObject>,
+    // Replacement for empty cells, if configured for the column
+    defaults: Vec<Option<PyObject>>,
+    // How each field's text becomes a Python value
+    kinds: Vec<ColumnKind>,
+    // Set in dictreader compat mode
+    rest: Option<RestFields>,
+    // Columns fed to row_hash, when include_row_hash is set
+    hash: Option<Vec<usize>>,
+    // Key for the row number, when add_row_number is set
+    row_number: Option<PyObject>,
+    // Header indices selected by `columns`, in the requested order
+    projection: Option<Vec<usize>>,
+    // Read ByteRecords and decode only the projected fields, for a small
+    // projection of a wide file
+    sparse: bool,
+    // Every selected key mapped to None, copied as the start of each row
+    // that has all of them (see new_row)
+    template: Option<Py<PyDict>>,
+    // Fields a record needs for every selected key to be present
+    template_width: usize,
+    // Python strings already made for short cells, reused for repeats
+    strings: RefCell<StringCache>,
+    // The read's Timings, with timings=True and for the reads it times
+    timings: Option<Arc<Timings>>,
+}
+
+// The dict a record of `len` fields is built in. A record that fills
+// every selected key starts from a copy of the layout's template, which
+// already holds each of them mapped to None and so has its final size; a
+// short one, where missing keys must stay absent, starts from an empty
+// dict. Without a template (sparse=True) every row starts empty.
+fn new_row<'py>(py: Python<'py>, layout: &RowLayout, len: usize) -> PyResult<&'py PyDict> {
+    match &layout.template {
+        Some(template) if len >= layout.template_width => {
+            template.clone_ref(py).into_ref(py).copy()
+        }
+        _ => Ok(PyDict::new(py)),
+    }
+}
+
+// Per-column cache of the Python strings made for cells of at most
+// `max_len` bytes, so a column of flags or short codes hands out the same
+// few objects instead of a new one per cell. A column that turns out to
+// have more than `max_values` distinct short values stops being cached,
+// since then most lookups would miss. Lives in the RowLayout, so nothing
+// is shared between columns or between reads.
+struct StringCache {
+    max_len: usize,
+    max_values: usize,
+    // None for a column that isn't cached (any more)
+    columns: Vec<Option<HashMap<u128, PyObject, BuildHasherDefault<PackedHasher>>>>,
+}
+
+impl StringCache {
+    fn new(width: usize, max_len: usize, max_values: usize) -> Self {
+        let cached = max_len > 0 && max_values > 0;
+        StringCache {
+            max_len,
+            max_values,
+            columns: (0..width).map(|_| cached.then(HashMap::default)).collect(),
+        }
+    }
+
+    // `field` as a Python str, from the cache for column `index` if it is
+    // short enough
+    fn get(&mut self, py: Python, index: usize, field: &str) -> PyObject {
+        let column = match self.columns.get_mut(index) {
+            Some(column) if field.len() <= self.max_len => column,
+            _ => return PyString::new(py, field).into(),
+        };
+        let values = match column {
+            Some(values) => values,
+            None => return PyString::new(py, field).into(),
+        };
+
+        // Up to 15 bytes and the length fit in one u128 key
+        let mut packed = [0u8; 16];
+        packed[..field.len()].copy_from_slice(field.as_bytes());
+        packed[15] = field.len() as u8;
+        let key = u128::from_le_bytes(packed);
+
+        if let Some(value) = values.get(&key) {
+            return value.clone_ref(py);
+        }
+        let value: PyObject = PyString::new(py, field).into();
+        if values.len() < self.max_values {
+            values.insert(key, value.clone_ref(py));
+        } else {
+            *column = None;
+        }
+        value
+    }
+}
+
+// Hasher for StringCache's packed keys: one multiply instead of SipHash,
+// which would cost more than creating the string
+#[derive(Default)]
+struct PackedHasher(u64);
+
+impl Hasher for PackedHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0.rotate_left(5) ^ b as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.0 = ((n as u64) ^ ((n >> 64) as u64).rotate_left(32))
+            .wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Record buffers reused across next_row calls; which one is filled depends
+// on RowLayout::sparse
+#[derive(Default)]
+struct RecordBuf {
+    record: StringRecord,
+    bytes: ByteRecord,
+    // Row number the next record read will have; a read that starts past
+    // the first row sets it to where it starts
+    next_row: usize,
+    // schema_drift="warn" has warned during this read
+    drift_warned: bool,
+    // schema_drift: the header line's names as they are in the file, to
+    // spot a repeated header; taken from the reader at the first record
+    header_names: Option<HashSet<Vec<u8>>>,
+    // distinguish_empty_quoted: the record's fields that were written as
+    // "" (see QuoteTracker)
+    quoted_empty: Vec<usize>,
+}
+
+// distinguish_empty_quoted: the "" fields of the record `reader` just
+// read into `buf`, as its input noted them
+fn take_quoted_empty<R: Read + QuoteSource>(reader: &csv::Reader<R>, buf: &mut RecordBuf) {
+    buf.quoted_empty.clear();
+    let found = match reader.get_ref().quoted_empty() {
+        Some(found) => found,
+        None => return,
+    };
+    let end = reader.position().byte();
+    let mut found = found.lock().unwrap();
+    while let Some((record_end, _)) = found.front() {
+        if *record_end > end {
+            break;
+        }
+        let (record_end, fields) = found.pop_front().unwrap();
+        if record_end == end {
+            buf.quoted_empty = fields;
+        }
+    }
+}
+
+// distinguish_empty_quoted: an empty string or JSON cell of the record in
+// `buf` becomes None unless it was written as "", when it is "". A
+// default still applies to both, and a number or decimal cell is None
+// either way, since "" isn't a number.
+fn mark_empty_cells(py: Python, layout: &RowLayout, buf: &RecordBuf, row: &PyDict) -> PyResult<()> {
+    let len = if layout.sparse {
+        buf.bytes.len()
+    } else {
+        buf.record.len()
+    };
+    for i in 0..len.min(layout.keys.len()) {
+        let empty = if layout.sparse {
+            buf.bytes[i].is_empty()
+        } else {
+            buf.record[i].is_empty()
+        };
+        if !empty || layout.defaults[i].is_some() || !row.contains(&layout.keys[i])? {
+            continue;
+        }
+        let quoted = buf.quoted_empty.contains(&i);
+        match layout.kinds[i] {
+            ColumnKind::Str if !quoted => row.set_item(&layout.keys[i], py.None())?,
+            ColumnKind::Json if quoted => row.set_item(&layout.keys[i], "")?,
+            ColumnKind::Json => row.set_item(&layout.keys[i], py.None())?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The columns of a columnar result: the keys read() rows would have, in
+// that order, plus "_hash" with include_row_hash and the row number key
+// with add_row_number
+fn column_names(py: Python, layout: &RowLayout) -> Vec<PyObject> {
+    let mut names: Vec<PyObject> = match &layout.projection {
+        Some(indices) => indices
+            .iter()
+            .map(|&i| layout.keys[i].clone_ref(py))
+            .collect(),
+        None => layout.keys.iter().map(|k| k.clone_ref(py)).collect(),
+    };
+    if layout.hash.is_some() {
+        names.push(ROW_HASH_KEY.to_object(py));
+    }
+    if let Some(key) = &layout.row_number {
+        names.push(key.clone_ref(py));
+    }
+    names
+}
+
+// Append `row`'s value for each of `names` to the matching column, or None
+// where the row lacks the key
+fn append_row(py: Python, columns: &[&PyList], names: &[PyObject], row: &PyDict) -> PyResult<()> {
+    for (column, name) in columns.iter().zip(names) {
+        match row.get_item(name) {
+            Some(value) => column.append(value)?,
+            None => column.append(py.None())?,
+        }
+    }
+    Ok(())
+}
+
+// A short row's missing trailing cells count as empty, so those of the
+// kept columns with a default get it, like an empty cell that is there
+fn set_missing_defaults(row: &PyDict, layout: &RowLayout, len: usize) -> PyResult<()> {
+    if len >= layout.keys.len() {
+        return Ok(());
+    }
+    let set = |i: usize| match &layout.defaults[i] {
+        Some(default) => row.set_item(&layout.keys[i], default),
+        None => Ok(()),
+    };
+    match &layout.projection {
+        None => (len..layout.keys.len()).try_for_each(set),
+        Some(indices) => indices
+            .iter()
+            .copied()
+            .filter(|&i| i >= len)
+            .try_for_each(set),
+    }
+}
+
+// The first field of `record` that isn't valid UTF-8, and how many of its
+// bytes are, as StringRecord validates it
+fn invalid_utf8(record: &ByteRecord) -> Option<(usize, usize)> {
+    if record.as_slice().is_ascii() {
+        return None;
+    }
+    record.iter().enumerate().find_map(|(i, field)| {
+        std::str::from_utf8(field)
+            .err()
+            .map(|e| (i, e.valid_up_to()))
+    })
+}
+
+// last_read_stats()' name for a last_projection value
+fn projection_name(path: u8) -> Option<&'static str> {
+    match path {
+        1 => Some("projection"),
+        2 => Some("sparse"),
+        _ => None,
+    }
+}
+
+// Field `index` of the record next_record just read into `buf`. The sparse
+// path keeps fields as bytes, which next_record has checked are UTF-8.
+fn buffered_field<'a>(layout: &RowLayout, buf: &'a RecordBuf, index: usize) -> Option<&'a str> {
+    if layout.sparse {
+        buf.bytes
+            .get(index)
+            .and_then(|f| std::str::from_utf8(f).ok())
+    } else {
+        buf.record.get(index)
+    }
+}
+
+// read_where_in's set of wanted values. A string matches a field with
+// exactly that text. An int or float matches a field whose text, trimmed,
+// is the same number, compared exactly as Python compares them, so 42
+// matches "42", " 42" and "42.0", while "42" matches only "42".
+#[derive(Default)]
+struct KeySet {
+    text: HashSet<String>,
+    ints: HashSet<i64>,
+    // Ints outside i64, as str() gives them: decimal text, without a "+"
+    // or leading zeros
+    big_ints: HashSet<String>,
+    // f64 bit patterns, with -0.0 stored as 0.0
+    floats: HashSet<u64>,
+}
+
+impl KeySet {
+    fn from_values(values: &PyAny) -> PyResult<Self> {
+        let mut keys = KeySet::default();
+        for value in values.iter()? {
+            let value = value?;
+            if let Ok(s) = value.downcast::<PyString>() {
+                keys.text.insert(s.to_str()?.to_string());
+            } else if value.is_instance_of::<PyLong>() && !value.is_instance_of::<PyBool>() {
+                match value.extract::<i64>() {
+                    Ok(n) => keys.ints.insert(n),
+                    Err(_) => keys.big_ints.insert(value.str()?.to_str()?.to_string()),
+                };
+            } else if value.is_instance_of::<PyFloat>() {
+                let x: f64 = value.extract()?;
+                // NaN equals nothing, not even a "nan" field
+                if !x.is_nan() {
+                    keys.floats.insert(float_key(x));
+                }
+            } else {
+                return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                    "values must be str, int or float, not {}",
+                    value.get_type().name()?
+                )));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn contains(&self, field: &str) -> bool {
+        if self.text.contains(field) {
+            return true;
+        }
+        if self.ints.is_empty() && self.big_ints.is_empty() && self.floats.is_empty() {
+            return false;
+        }
+
+        let field = field.trim();
+        if let Ok(n) = field.parse::<i64>() {
+            // A float key matches only if it is exactly n
+            let x = n as f64;
+            return self.ints.contains(&n)
+                || (x < I64_END && x as i64 == n && self.floats.contains(&float_key(x)));
+        }
+        if let Some(digits) = integer_text(field) {
+            let x: f64 = digits.parse().unwrap_or(f64::INFINITY);
+            return self.big_ints.contains(&digits)
+                || (x.is_finite()
+                    && format!("{:.0}", x) == digits
+                    && self.floats.contains(&float_key(x)));
+        }
+        match field.parse::<f64>() {
+            Ok(x) => {
+                if self.floats.contains(&float_key(x)) {
+                    return true;
+                }
+                if x.fract() != 0.0 || !x.is_finite() {
+                    return false;
+                }
+                if (-I64_END..I64_END).contains(&x) {
+                    self.ints.contains(&(x as i64))
+                } else {
+                    // {:.0} prints the float's exact value
+                    self.big_ints.contains(&format!("{:.0}", x))
+                }
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+// 2^63, the first f64 past i64::MAX
+const I64_END: f64 = 9.223_372_036_854_776e18;
+
+// `field` as KeySet's big_ints stores ints, if it is an optionally signed
+// run of ASCII digits
+fn integer_text(field: &str) -> Option<String> {
+    let (negative, digits) = match field.as_bytes().first()? {
+        b'-' => (true, &field[1..]),
+        b'+' => (false, &field[1..]),
+        _ => (false, field),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits = digits.trim_start_matches('0');
+    Some(match (digits.is_empty(), negative) {
+        (true, _) => "0".to_string(),
+        (false, true) => format!("-{}", digits),
+        (false, false) => digits.to_string(),
+    })
+}
+
+fn float_key(x: f64) -> u64 {
+    if x == 0.0 {
+        0.0f64.to_bits()
+    } else {
+        x.to_bits()
+    }
+}
+
+// read_context's test on one field: `field <op> value`. A number compares
+// numerically with fields that parse as numbers, and fields that don't
+// never match. A string compares with the field text, ordering by code
+// point; "contains" is a substring test and only takes a string.
+struct Condition {
+    op: CompareOp,
+    target: Target,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+enum Target {
+    Text(String),
+    Number(f64),
+}
+
+impl Condition {
+    fn new(op: &str, value: &PyAny) -> PyResult<Self> {
+        let op = match op {
+            "==" => CompareOp::Eq,
+            "!=" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            ">=" => CompareOp::Ge,
+            "contains" => CompareOp::Contains,
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "op must be one of ==, !=, <, <=, >, >= or contains, not {:?}",
+                    other
+                )));
+            }
+        };
+
+        let target = if let Ok(s) = value.downcast::<PyString>() {
+            Target::Text(s.to_str()?.to_string())
+        } else if (value.is_instance_of::<PyLong>() && !value.is_instance_of::<PyBool>())
+            || value.is_instance_of::<PyFloat>()
+        {
+            if op == CompareOp::Contains {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "contains needs a string value".to_string(),
+                ));
+            }
+            Target::Number(value.extract()?)
+        } else {
+            return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                "value must be str, int or float, not {}",
+                value.get_type().name()?
+            )));
+        };
+
+        Ok(Condition { op, target })
+    }
+
+    fn matches(&self, field: &str) -> bool {
+        let ordering = match &self.target {
+            Target::Text(text) if self.op == CompareOp::Contains => {
+                return field.contains(text.as_str());
+            }
+            Target::Text(text) => field.cmp(text.as_str()),
+            Target::Number(x) => match field
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(|f| f.partial_cmp(x))
+            {
+                Some(ordering) => ordering,
+                None => return false,
+            },
+        };
+
+        match self.op {
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+            CompareOp::Contains => unreachable!(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SchemaDrift {
+    Ignore,
+    Warn,
+    Error,
+}
+
+impl SchemaDrift {
+    // The schema_drift argument that selects this mode
+    fn name(&self) -> &'static str {
+        match self {
+            SchemaDrift::Ignore => "ignore",
+            SchemaDrift::Warn => "warn",
+            SchemaDrift::Error => "error",
+        }
+    }
+}
+
+// The `mode` option: which path read() takes
+#[derive(Clone, Copy, PartialEq)]
+enum ReadMode {
+    Auto,
+    Memory = 1,
+    Stream = 2,
+}
+
+impl ReadMode {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "auto" => Ok(ReadMode::Auto),
+            "memory" => Ok(ReadMode::Memory),
+            "stream" => Ok(ReadMode::Stream),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "mode must be \"auto\", \"memory\" or \"stream\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    // The mode argument that selects this path
+    fn name(&self) -> &'static str {
+        match self {
+            ReadMode::Auto => "auto",
+            ReadMode::Memory => "memory",
+            ReadMode::Stream => "stream",
+        }
+    }
+}
+
+// The `seek_strategy` option: how read_chunk_optimized finds a far start
+// row
+#[derive(Clone, Copy, PartialEq)]
+enum SeekStrategy {
+    // Seek by estimate, then count records from the nearest known
+    // position to find the row it landed on and skip to start_row, or
+    // skip exactly from the top if it landed past or far short of it
+    Auto,
+    // Seek by estimate without checking it
+    Estimate,
+    // Skip rows exactly, like read_chunk
+    Exact,
+}
+
+impl SeekStrategy {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "auto" => Ok(SeekStrategy::Auto),
+            "estimate" => Ok(SeekStrategy::Estimate),
+            "exact" => Ok(SeekStrategy::Exact),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "seek_strategy must be \"auto\", \"estimate\" or \"exact\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SeekStrategy::Auto => "auto",
+            SeekStrategy::Estimate => "estimate",
+            SeekStrategy::Exact => "exact",
+        }
+    }
+}
+
+// The `snapshot` option: how a read is kept consistent while another
+// program saves the file
+#[derive(Clone, Copy, PartialEq)]
+enum SnapshotMode {
+    // Read the file as it is
+    Off,
+    // Read a copy made when the parser is created (see files::SnapshotCopy)
+    Copy,
+    // Hold a shared lock on the file while each read has it open (see
+    // files::lock_shared)
+    Lock,
+}
+
+impl SnapshotMode {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "none" => Ok(SnapshotMode::Off),
+            "copy" => Ok(SnapshotMode::Copy),
+            "lock" => Ok(SnapshotMode::Lock),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "snapshot must be \"none\", \"copy\" or \"lock\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SnapshotMode::Off => "none",
+            SnapshotMode::Copy => "copy",
+            SnapshotMode::Lock => "lock",
+        }
+    }
+}
+
+// The `chunk_unit` option: what read_chunk's start_row and num_rows count
+#[derive(Clone, Copy, PartialEq)]
+enum ChunkUnit {
+    // Data records, as rows are numbered everywhere else
+    Records,
+    // Physical lines after the header, ended by "\n", "\r\n" or a lone
+    // "\r" as records are (see files::LineCount). A row belongs to the
+    // line its record starts on; one with quoted line breaks spans the
+    // lines after it too, and blank lines count toward the record after
+    // them. Not available with max_columns.
+    Lines,
+}
+
+impl ChunkUnit {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "records" => Ok(ChunkUnit::Records),
+            "lines" => Ok(ChunkUnit::Lines),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "chunk_unit must be \"records\" or \"lines\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ChunkUnit::Records => "records",
+            ChunkUnit::Lines => "lines",
+        }
+    }
+}
+
+// The `json_invalid` option: what a json_columns cell that isn't valid
+// JSON, including an empty one without a default, becomes
+#[derive(Clone, Copy, PartialEq)]
+enum JsonInvalid {
+    // The cell text, unparsed
+    Raw,
+    None,
+}
+
+impl JsonInvalid {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "raw" => Ok(JsonInvalid::Raw),
+            "none" => Ok(JsonInvalid::None),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "json_invalid must be \"raw\" or \"none\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            JsonInvalid::Raw => "raw",
+            JsonInvalid::None => "none",
+        }
+    }
+}
+
+// How normalize_headers rewrites each header name
+#[derive(Clone, Copy)]
+enum HeaderStyle {
+    // names::snake_case
+    Snake,
+    // Trimmed and lower-cased, otherwise as is
+    Lower,
+}
+
+impl HeaderStyle {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "snake" => Ok(HeaderStyle::Snake),
+            "lower" => Ok(HeaderStyle::Lower),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "normalize_headers must be \"snake\" or \"lower\", not {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            HeaderStyle::Snake => "snake",
+            HeaderStyle::Lower => "lower",
+        }
+    }
+
+    fn apply(&self, header: &str) -> String {
+        match self {
+            HeaderStyle::Snake => names::snake_case(header),
+            HeaderStyle::Lower => header.trim().to_lowercase(),
+        }
+    }
+}
+
+// join's right side: key cell -> the taken cells of each row with it
+type JoinTable = HashMap<String, Vec<Vec<String>>>;
+
+// read_keyed's policy for a key seen on more than one row
+#[derive(Clone, Copy)]
+enum Duplicates {
+    // Raise ValueError at the second row, with `key`, `row`, `offset` and
+    // `raw` attributes
+    Error,
+    // Keep the first or the last row with the key
+    First,
+    Last,
+    // Map every key to a list of its rows, in file order
+    List,
+}
+
+impl Duplicates {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "error" => Ok(Duplicates::Error),
+            "first" => Ok(Duplicates::First),
+            "last" => Ok(Duplicates::Last),
+            "list" => Ok(Duplicates::List),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "duplicates must be \"error\", \"first\", \"last\" or \"list\", not {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+// stop_at_blank_line: the first blank line after a record, and the rows
+// before it
+struct DataEnd {
+    offset: u64,
+    rows: usize,
+}
+
+// csv.DictReader's restkey/restval
+struct RestFields {
+    key: PyObject,
+    value: PyObject,
+}
+
+enum ColumnKind {
+    Str,
+    // Holds decimal.Decimal, looked up once per read
+    Decimal(PyObject),
+    // Parsed with serde_json (see json.rs)
+    Json,
+    // A Query's "int" and "float" schema types
+    Int,
+    Float,
+}
+
+// all_strings: a default or restval as the str it fills a cell with,
+// None as ""
+fn as_string(py: Python, value: &PyObject) -> PyResult<PyObject> {
+    if value.is_none(py) {
+        return Ok("".to_object(py));
+    }
+    Ok(value.as_ref(py).str()?.to_object(py))
+}
+
+// Build a Decimal straight from the field text so no precision is lost.
+// Empty cells (without a default) become None.
+fn to_decimal(
+    py: Python,
+    decimal_type: &PyObject,
+    key: &PyObject,
+    field: &str,
+) -> PyResult<PyObject> {
+    if field.is_empty() {
+        return Ok(py.None());
+    }
+
+    match decimal_type.call1(py, (field,)) {
+        Ok(value) => Ok(value),
+        Err(_) => {
+            let context = ConversionError::new(Converter::Decimal, field);
+            let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid decimal in column {}: {:?} (stopped at character {})",
+                key, field, context.stopped_at
+            ));
+            context.attach(py, &err)?;
+            Err(err)
+        }
+    }
+}
+
+// An int or float cell's text, trimmed, parsed as an i64 or f64 as in
+// read_column_array(). Empty cells (without a default) become None.
+fn to_number(py: Python, converter: Converter, key: &PyObject, field: &str) -> PyResult<PyObject> {
+    let text = field.trim();
+    if text.is_empty() {
+        return Ok(py.None());
+    }
+
+    let value = match converter {
+        Converter::Int => text.parse::<i64>().ok().map(|n| n.to_object(py)),
+        _ => text.parse::<f64>().ok().map(|x| x.to_object(py)),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None => {
+            let context = ConversionError::new(converter, text);
+            let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid {} in column {}: {:?} (stopped at character {})",
+                converter.name(),
+                key,
+                text,
+                context.stopped_at
+            ));
+            context.attach(py, &err)?;
+            Err(err)
+        }
+    }
+}
+
+// Text of a typed cell as it goes to conversion: Unicode whitespace
+// (including non-breaking and thin spaces) trimmed from both ends and every
+// character in `junk` removed, e.g. "$" or "," for "$1,234"
+fn clean_field<'a>(field: &'a str, junk: &str) -> Cow<'a, str> {
+    let trimmed = field.trim();
+    if junk.is_empty() || !trimmed.contains(|c| junk.contains(c)) {
+        return Cow::Borrowed(trimmed);
+    }
+    let kept: String = trimmed.chars().filter(|&c| !junk.contains(c)).collect();
+    Cow::Owned(kept.trim().to_string())
+}
+
+// Row fingerprint used by include_row_hash and hash_rows. People persist
+// these, so the canonical form must never change:
+// xxHash64 (seed 0) over the raw UTF-8 text of the selected fields, in the
+// selected order, each followed by a 0x1F unit separator. Fields are not
+// trimmed or case-folded, defaults/decimal conversion are not applied, and a
+// field missing from a short row hashes as empty.
+fn row_hash(record: &StringRecord, indices: &[usize]) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    for &i in indices {
+        hasher.update(record.get(i).unwrap_or("").as_bytes());
+        hasher.update(&[0x1F]);
+    }
+    hasher.digest()
+}
+
+// Field indices for row_hash: `columns` in the given order, else all
+fn hash_indices(headers: &StringRecord, columns: Option<&Vec<String>>) -> PyResult<Vec<usize>> {
+    match columns {
+        None => Ok((0..headers.len()).collect()),
+        Some(names) => {
+            check_columns(headers, names, "hash_columns")?;
+            Ok(names
+                .iter()
+                .filter_map(|n| headers.iter().position(|h| h == n))
+                .collect())
+        }
+    }
+}
+
+// Raise if any of `names` (given for `option`) is not in the header
+fn check_columns<'a, I>(headers: &StringRecord, names: I, option: &str) -> PyResult<()>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    for name in names {
+        if !headers.iter().any(|h| h == name) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Unknown column in {}: {}",
+                option, name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// single_column: undo the split of a line at NUL bytes, so the whole line
+// is the record's only field
+fn rejoin_line(layout: &RowLayout, buf: &mut RecordBuf) {
+    if layout.sparse {
+        if buf.bytes.len() > 1 {
+            let line = buf.bytes.iter().collect::<Vec<_>>().join(&b'\0');
+            let position = buf.bytes.position().cloned();
+            buf.bytes = ByteRecord::from(vec![line]);
+            buf.bytes.set_position(position);
+        }
+    } else if buf.record.len() > 1 {
+        let line = buf.record.iter().collect::<Vec<_>>().join("\0");
+        let position = buf.record.position().cloned();
+        buf.record = StringRecord::from(vec![line]);
+        buf.record.set_position(position);
+    }
+}
+
+// MemAvailable from /proc/meminfo, where there is one
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// Iterator returned by `CSVParser.iter_rows()`
+#[pyclass]
+struct RowIterator {
+    parser: Py<CSVParser>,
+    // None once closed or exhausted, which releases the file
+    reader: Option<csv::Reader<BufReader<Input>>>,
+    layout: RowLayout,
+    buf: RecordBuf,
+    rows: usize,
+    watch: FileWatch,
+    // Set when tolerate_truncation stopped the iteration early
+    #[pyo3(get)]
+    truncated: bool,
+}
+
+#[pymethods]
+impl RowIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    // An error ends the iteration and releases the file, as the end does
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        let next = self.next_row(py);
+        if next.is_err() {
+            self.reader = None;
+        }
+        next
+    }
+
+    // Release the file now rather than when the iterator is collected.
+    // Iterating a closed iterator stops immediately.
+    fn close(&mut self) {
+        self.reader = None;
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> bool {
+        self.close();
+        false
+    }
+}
+
+impl RowIterator {
+    fn next_row(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        let parser = self.parser.borrow(py);
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        if self.rows >= parser.max_rows() {
+            let limit = parser.enforce_row_limit(reader, self.rows);
+            self.reader = None;
+            limit?;
+            return Ok(None);
+        }
+
+        // The file is checked once per batch_size rows and at the end
+        if self.rows > 0 && self.rows.is_multiple_of(parser.batch_size) {
+            let offset = reader.position().byte();
+            if parser.file_changed(py, &self.watch, offset, false, self.rows)? {
+                self.truncated = true;
+                self.reader = None;
+                parser.last_read_truncated.store(true, Ordering::Relaxed);
+                return Ok(None);
+            }
+        }
+
+        let timings = &self.layout.timings;
+        let started = timings.as_ref().map(|_| Instant::now());
+        let next = parser.next_row(py, reader, &self.layout, &mut self.buf);
+        if let (Some(timings), Some(started)) = (timings, started) {
+            Timings::add(&timings.total, started);
+        }
+        match next? {
+            Some(row) => {
+                self.rows += 1;
+                Ok(Some(row.to_object(py)))
+            }
+            None => {
+                let offset = reader.position().byte();
+                self.reader = None;
+                self.truncated = parser.file_changed(py, &self.watch, offset, true, self.rows)?;
+                parser
+                    .last_read_truncated
+                    .store(self.truncated, Ordering::Relaxed);
+                Ok(None)
+            }
+        }
+    }
+}
+
+// lazy_batches' formats other than "rows", and a Query's columnar outputs
+#[derive(Clone, Copy, PartialEq)]
+enum BatchFormat {
+    // A {column: list} dict
+    Columns,
+    // A pandas DataFrame made from it
+    Pandas,
+    // A pyarrow RecordBatch made from it
+    Arrow,
+}
+
+impl BatchFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "columns" => Some(BatchFormat::Columns),
+            "pandas" => Some(BatchFormat::Pandas),
+            "arrow" => Some(BatchFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+// What a {column: list} dict is passed to for `format`: pandas.DataFrame
+// for "pandas", pyarrow.RecordBatch.from_pydict for "arrow", nothing
+// otherwise. pandas and pyarrow are imported only when asked for.
+fn batch_converter(py: Python, format: BatchFormat) -> PyResult<Option<PyObject>> {
+    let convert = match format {
+        BatchFormat::Pandas => py.import("pandas")?.getattr("DataFrame")?,
+        BatchFormat::Arrow => py
+            .import("pyarrow")?
+            .getattr("RecordBatch")?
+            .getattr("from_pydict")?,
+        BatchFormat::Columns => return Ok(None),
+    };
+    Ok(Some(convert.to_object(py)))
+}
+
+// lazy_batches(format=...) other than "rows": each batch becomes a
+// {column: list} dict, passed to `convert` if set. A batch with no rows
+// still has every column, as an empty list, and the types its columns
+// would hold (see empty_types), so an empty result has the same shape as
+// any other.
+struct ColumnarBatches {
+    names: Vec<PyObject>,
+    convert: Option<PyObject>,
+    empty_types: EmptyTypes,
+}
+
+// How a batch with no rows gets its column types, which can't be inferred
+// from values then
+enum EmptyTypes {
+    // "columns": empty lists have no type
+    None,
+    // "pandas": a {column: dtype} dict for DataFrame.astype()
+    Pandas(PyObject),
+    // "arrow": the schema the RecordBatch is built with
+    Arrow(PyObject),
+}
+
+impl ColumnarBatches {
+    fn new(
+        py: Python,
+        format: BatchFormat,
+        convert: Option<PyObject>,
+        layout: &RowLayout,
+    ) -> PyResult<Self> {
+        let names = column_names(py, layout);
+        let types = column_types(layout);
+        let empty_types = match format {
+            BatchFormat::Columns => EmptyTypes::None,
+            BatchFormat::Pandas => {
+                let dtypes = PyDict::new(py);
+                for (name, t) in names.iter().zip(&types) {
+                    dtypes.set_item(name, t.pandas_dtype())?;
+                }
+                EmptyTypes::Pandas(dtypes.to_object(py))
+            }
+            BatchFormat::Arrow => {
+                let pa = py.import("pyarrow")?;
+                let mut fields: Vec<(PyObject, &PyAny)> = Vec::with_capacity(names.len());
+                for (name, t) in names.iter().zip(&types) {
+                    fields.push((name.clone_ref(py), t.arrow_type(pa)?));
+                }
+                EmptyTypes::Arrow(pa.call_method1("schema", (fields,))?.to_object(py))
+            }
+        };
+        Ok(ColumnarBatches {
+            names,
+            convert,
+            empty_types,
+        })
+    }
+
+    fn convert(
+        &self,
+        py: Python,
+        parser: &CSVParser,
+        batch: &PyList,
+        timings: Option<&Timings>,
+    ) -> PyResult<PyObject> {
+        let columns: Vec<&PyList> = self.names.iter().map(|_| PyList::empty(py)).collect();
+        for (i, row) in batch.iter().enumerate() {
+            append_row(py, &columns, &self.names, row.downcast::<PyDict>()?)?;
+            parser.check_signals(py, i + 1)?;
+        }
+        let dict = PyDict::new(py);
+        for (name, column) in self.names.iter().zip(columns) {
+            dict.set_item(name, column)?;
+        }
+        let started = timings.map(|_| Instant::now());
+        let converted = match (&self.convert, &self.empty_types) {
+            (Some(convert), EmptyTypes::Arrow(schema)) if batch.is_empty() => {
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("schema", schema)?;
+                convert.call(py, (dict,), Some(kwargs))
+            }
+            (Some(convert), EmptyTypes::Pandas(dtypes)) if batch.is_empty() => convert
+                .call1(py, (dict,))
+                .and_then(|frame| frame.call_method1(py, "astype", (dtypes,))),
+            (Some(convert), _) => convert.call1(py, (dict,)),
+            (None, _) => Ok(dict.to_object(py)),
+        };
+        if let (Some(timings), Some(started)) = (timings, started) {
+            Timings::add(&timings.callback, started);
+        }
+        converted
+    }
+}
+
+// What a columnar result's column holds, for the types of an empty one
+#[derive(Clone, Copy)]
+enum ColumnType {
+    Text,
+    Int,
+    Float,
+    Decimal,
+    Json,
+}
+
+impl ColumnType {
+    // Text as pandas stores it, and the objects decimal and JSON columns
+    // hold, are "object"
+    fn pandas_dtype(self) -> &'static str {
+        match self {
+            ColumnType::Int => "int64",
+            ColumnType::Float => "float64",
+            ColumnType::Text | ColumnType::Decimal | ColumnType::Json => "object",
+        }
+    }
+
+    // A decimal column's precision and scale come from its values when it
+    // has any; empty, it is decimal128(38, 18), which holds any value with
+    // up to 20 integer and 18 fractional digits. A JSON column is string,
+    // the type of the text it was parsed from.
+    fn arrow_type(self, pa: &PyModule) -> PyResult<&PyAny> {
+        match self {
+            ColumnType::Text | ColumnType::Json => pa.call_method0("string"),
+            ColumnType::Int => pa.call_method0("int64"),
+            ColumnType::Float => pa.call_method0("float64"),
+            ColumnType::Decimal => pa.call_method1("decimal128", (38, 18)),
+        }
+    }
+}
+
+// The type of each column_names() column: from its ColumnKind, then text
+// for "_hash" and Int for row numbers
+fn column_types(layout: &RowLayout) -> Vec<ColumnType> {
+    let indices: Vec<usize> = match &layout.projection {
+        Some(indices) => indices.clone(),
+        None => (0..layout.keys.len()).collect(),
+    };
+    let mut types: Vec<ColumnType> = indices
+        .into_iter()
+        .map(|i| match layout.kinds[i] {
+            ColumnKind::Str => ColumnType::Text,
+            ColumnKind::Int => ColumnType::Int,
+            ColumnKind::Float => ColumnType::Float,
+            ColumnKind::Decimal(_) => ColumnType::Decimal,
+            ColumnKind::Json => ColumnType::Json,
+        })
+        .collect();
+    if layout.hash.is_some() {
+        types.push(ColumnType::Text);
+    }
+    if layout.row_number.is_some() {
+        types.push(ColumnType::Int);
+    }
+    types
+}
+
+// Generator returned by `CSVParser.lazy_batches()`
+#[pyclass]
+struct BatchIterator {
+    parser: Py<CSVParser>,
+    // None once closed or exhausted, which releases the file
+    reader: Option<csv::Reader<BufReader<Input>>>,
+    layout: RowLayout,
+    // Set unless batches are lists of row dicts
+    columnar: Option<ColumnarBatches>,
+    buf: RecordBuf,
+    done: bool,
+    // Progress so far, for the per-batch and completion log events
+    batches: usize,
+    rows: usize,
+    started: Instant,
+    watch: FileWatch,
+    // Set when tolerate_truncation stopped the iteration early
+    #[pyo3(get)]
+    truncated: bool,
+    // With timings=True, the last batch's time per phase, as in
+    // last_read_stats()["timings"]
+    #[pyo3(get)]
+    batch_timings: Option<PyObject>,
+}
+
+#[pymethods]
+impl BatchIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    // An error ends the generator and releases the file, as the end does
+    fn __next__(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        let next = self.next_batch(py);
+        if next.is_err() {
+            self.done = true;
+            self.reader = None;
+        }
+        next
+    }
+
+    // Release the file now rather than when the generator is collected.
+    // Iterating a closed generator stops immediately.
+    fn close(&mut self) {
+        self.done = true;
+        self.reader = None;
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> bool {
+        self.close();
+        false
+    }
+}
+
+impl BatchIterator {
+    fn next_batch(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        let reader = match &mut self.reader {
+            Some(reader) if !self.done => reader,
+            _ => return Ok(None),
+        };
+
+        let parser = self.parser.borrow(py);
+        let batch = PyList::empty(py);
+        let batch_started = Instant::now();
+        let timings_before = self.layout.timings.as_ref().map(|t| t.snapshot());
+
+        // Between batches is where a rotated log file would show up
+        if self.rows > 0 {
+            let offset = reader.position().byte();
+            if parser.file_changed(py, &self.watch, offset, false, self.rows)? {
+                self.truncated = true;
+                self.done = true;
+            }
+        }
+
+        // Parse only as many records as fit in this batch
+        while !self.done && batch.len() < parser.batch_size {
+            if self.rows + batch.len() >= parser.max_rows() {
+                self.done = true;
+                parser.enforce_row_limit(reader, self.rows + batch.len())?;
+                break;
+            }
+            match parser.next_row(py, reader, &self.layout, &mut self.buf)? {
+                Some(row) => {
+                    batch.append(row)?;
+                    parser.check_signals(py, batch.len())?;
+                }
+                None => {
+                    self.done = true;
+                    let offset = reader.position().byte();
+                    let rows = self.rows + batch.len();
+                    self.truncated = parser.file_changed(py, &self.watch, offset, true, rows)?;
+                    break;
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            self.batches += 1;
+            self.rows += batch.len();
+            let elapsed_ms = batch_started.elapsed().as_secs_f64() * 1000.0;
+            parser.log_event(
+                py,
+                "batch",
+                format!(
+                    "Batch {} parsed: {} rows ({:.1} ms)",
+                    self.batches - 1,
+                    batch.len(),
+                    elapsed_ms
+                ),
+                &[
+                    ("batch_index", (self.batches - 1).to_object(py)),
+                    ("rows", batch.len().to_object(py)),
+                    ("elapsed_ms", elapsed_ms.to_object(py)),
+                ],
+            )?;
+        }
+
+        if self.done {
+            self.reader = None;
+            parser.log_completion(py, self.rows, self.batches, self.started, self.truncated)?;
+        }
+
+        // A columnar read with no rows at all still yields one, empty batch
+        // with every column, as a Query's columnar output returns
+        if batch.is_empty() && (self.columnar.is_none() || self.batches > 0) {
+            return Ok(None);
+        }
+
+        let converted = match &self.columnar {
+            None => batch.to_object(py),
+            Some(columnar) => {
+                columnar.convert(py, &parser, batch, self.layout.timings.as_deref())?
+            }
+        };
+        if let (Some(timings), Some(before)) = (&self.layout.timings, timings_before) {
+            Timings::add(&timings.total, batch_started);
+            self.batch_timings = Some(timings.snapshot().since(&before).to_dict(py)?);
+        }
+        Ok(Some(converted))
+    }
 }
 
 #[pymodule]
-fn csv_reader(_py: Python, m: &PyModule) -> PyResult<()> {
+fn csv_reader(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<CSVParser>()?;
+    m.add_class::<BatchIterator>()?;
+    m.add_class::<RowIterator>()?;
+    m.add_class::<CSVWriter>()?;
+    m.add_class::<Query>()?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(info::features, m)?)?;
+    m.add_function(wrap_pyfunction!(info::build_info, m)?)?;
+    m.add(
+        "FileChangedDuringRead",
+        py.get_type::<FileChangedDuringRead>(),
+    )?;
+    m.add("EmptyFileError", py.get_type::<EmptyFileError>())?;
+    m.add("CSVReaderWarning", py.get_type::<CSVReaderWarning>())?;
     Ok(())
 }
@@ -0,0 +1,141 @@
+// Column names for outputs that restrict them. Header text can be
+// anything, including accents, CJK, emoji, leading digits, blanks and
+// repeats, so every output that needs identifiers derives them here:
+// - python_identifiers(): valid Python identifiers, e.g. for attribute
+//   access or namedtuple fields;
+// - sql_columns(): names a SQL table can have, the original text wherever
+//   it is usable, since quoting allows any other character;
+// - snake_case() and unique_headers(): the header itself, under the
+//   normalize_headers and dedup_headers options.
+// All keep one name per header field, in order, and the lists never repeat
+// a name.
+
+use std::collections::{HashMap, HashSet};
+
+// Python keywords, which are not valid identifiers
+const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+// Runs of characters not allowed in an identifier become one "_", and
+// any at either end are dropped, as are "_"s there: "Größe (cm)" is
+// "Größe_cm" and "年齢" stays as it is. A name starting with a character
+// that may only follow the first, such as a digit or a combining mark,
+// gets a "col_" prefix, since namedtuple rejects a leading "_"; a keyword
+// gets a trailing "_".
+// A name with nothing usable left is "col_<position>". Repeats get "_2",
+// "_3", ... in file order.
+pub fn python_identifiers<'a>(headers: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let names = headers.into_iter().enumerate().map(|(i, header)| {
+        let mut name = String::new();
+        for part in header.split(|c: char| !is_identifier_char(c)) {
+            if !part.is_empty() {
+                if !name.is_empty() {
+                    name.push('_');
+                }
+                name.push_str(part);
+            }
+        }
+        let name = name.trim_matches('_').to_string();
+        if name.is_empty() {
+            format!("col_{}", i)
+        } else if !name.starts_with(unicode_ident::is_xid_start) {
+            format!("col_{}", name)
+        } else if KEYWORDS.contains(&name.as_str()) {
+            name + "_"
+        } else {
+            name
+        }
+    });
+    dedupe(names, |name| name.to_string(), "_", 2)
+}
+
+// Header text as is, except that NUL, which SQLite cuts names at, is
+// dropped, and a blank name becomes "column_<position>". Repeats, which
+// SQLite compares without ASCII case, get "_2", "_3", ... in file order.
+pub fn sql_columns<'a>(headers: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let names = headers.into_iter().enumerate().map(|(i, header)| {
+        let name = header.replace('\0', "");
+        if name.trim().is_empty() {
+            format!("column_{}", i)
+        } else {
+            name
+        }
+    });
+    dedupe(names, |name| name.to_ascii_lowercase(), "_", 2)
+}
+
+// normalize_headers="snake": lower case, with words joined by one "_". A
+// word ends at any character that isn't a letter or digit, and where a
+// lower-case letter or digit is followed by an upper-case one, so
+// "First Name", " first-name " and "firstName" are all "first_name". A
+// run of capitals stays one word: "HTTPServer" is "httpserver". May be
+// empty.
+pub fn snake_case(header: &str) -> String {
+    let mut out = String::new();
+    let mut prev: Option<char> = None;
+    for c in header.chars() {
+        if !c.is_alphanumeric() {
+            prev = None;
+            continue;
+        }
+        let starts_word = match prev {
+            None => true,
+            Some(p) => c.is_uppercase() && (p.is_lowercase() || p.is_numeric()),
+        };
+        if starts_word && !out.is_empty() {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+        prev = Some(c);
+    }
+    out
+}
+
+// dedup_headers: the second and later copies of a name get ".1", ".2", ...
+// in file order, as pandas does, skipping any suffixed name that is
+// already taken. Names are compared exactly.
+pub fn unique_headers(names: impl Iterator<Item = String>) -> Vec<String> {
+    dedupe(names, |name| name.to_string(), ".", 1)
+}
+
+// Whether `c` may be in a Python identifier past its first character:
+// XID_Continue, as str.isidentifier() checks, which includes "_", digits
+// of any script and combining marks
+fn is_identifier_char(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+
+// Give each name whose `key` an earlier name already has the first free
+// "<separator><n>" suffix, counting from `first`. Names compare equal when
+// their keys are; taken keys are kept in a set, and each base name
+// remembers the last suffix it got, so repeats don't retry from `first`.
+fn dedupe(
+    names: impl Iterator<Item = String>,
+    key: fn(&str) -> String,
+    separator: &str,
+    first: usize,
+) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut next: HashMap<String, usize> = HashMap::new();
+    for name in names {
+        let mut unique = name.clone();
+        if taken.contains(&key(&unique)) {
+            let n = next.entry(key(&name)).or_insert(first);
+            loop {
+                unique = format!("{}{}{}", name, separator, n);
+                *n += 1;
+                if !taken.contains(&key(&unique)) {
+                    break;
+                }
+            }
+        }
+        taken.insert(key(&unique));
+        out.push(unique);
+    }
+    out
+}
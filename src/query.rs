@@ -0,0 +1,201 @@
+// csv_reader.Query: which columns to keep, which rows to keep, how to type
+// cells and what to return, checked and compiled once so the same
+// extraction can run against any number of files through
+// CSVParser.execute(). Filter values become Rust sets and comparisons,
+// and decimal.Decimal, json.loads and the output converter are looked up
+// here, so a run only has to match the query to the file's header.
+// Column names are header names as they are in the file, before `rename`.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+
+use super::{batch_converter, BatchFormat, CSVParser, ColumnKind, Condition, KeySet};
+
+// batch_size for Query.run(), which only "batches" output looks at
+const RUN_BATCH_SIZE: usize = 10_000;
+
+#[pyclass]
+pub struct Query {
+    // Columns kept in each row, in this order; None keeps the parser's
+    pub columns: Option<Vec<String>>,
+    // A row is kept when every filter holds
+    pub filters: Vec<Filter>,
+    pub schema: HashMap<String, ValueType>,
+    pub(crate) output: QueryOutput,
+    // decimal.Decimal, if the schema uses it
+    decimal_type: Option<PyObject>,
+    // For "pandas" and "arrow" output, what the {column: list} dict is
+    // passed to
+    pub convert: Option<PyObject>,
+}
+
+pub struct Filter {
+    pub column: String,
+    pub(crate) predicate: Predicate,
+}
+
+// One filter's test on its column's cell text
+pub(crate) enum Predicate {
+    // (column, op, value) with op one of read_context()'s
+    Compare(Condition),
+    // (column, "in", values), matched like read_where_in()
+    In(KeySet),
+}
+
+impl Predicate {
+    pub fn matches(&self, field: &str) -> bool {
+        match self {
+            Predicate::Compare(condition) => condition.matches(field),
+            Predicate::In(keys) => keys.contains(field),
+        }
+    }
+}
+
+// What a schema entry turns a column's cells into
+#[derive(Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Str,
+    // The text, trimmed, parsed as an i64 or f64
+    Int,
+    Float,
+    Decimal,
+    Json,
+}
+
+impl ValueType {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "str" => Ok(ValueType::Str),
+            "int" => Ok(ValueType::Int),
+            "float" => Ok(ValueType::Float),
+            "decimal" => Ok(ValueType::Decimal),
+            "json" => Ok(ValueType::Json),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "schema types must be \"str\", \"int\", \"float\", \"decimal\" or \"json\", not {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum QueryOutput {
+    // A flat list of row dicts
+    Rows,
+    // A list of batch_size lists, like read()
+    Batches,
+    // One {column: list} dict, in `format` (passed to `convert` if set)
+    Columns(BatchFormat),
+}
+
+#[pymethods]
+impl Query {
+    // `filter` is a list of (column, op, value) tuples, op being "==", "!=",
+    // "<", "<=", ">", ">=", "contains" or "in" (value then a list).
+    // `schema` maps columns to "str", "int", "float", "decimal" or "json".
+    // `output` is "rows", "batches", "columns", "pandas" or "arrow".
+    #[new]
+    #[pyo3(signature = (columns=None, filter=None, schema=None, output="rows"))]
+    fn new(
+        py: Python,
+        columns: Option<Vec<String>>,
+        filter: Option<Vec<(String, String, &PyAny)>>,
+        schema: Option<HashMap<String, String>>,
+        output: &str,
+    ) -> PyResult<Self> {
+        let mut filters = Vec::new();
+        for (column, op, value) in filter.unwrap_or_default() {
+            let predicate = if op == "in" {
+                Predicate::In(KeySet::from_values(value)?)
+            } else {
+                Predicate::Compare(Condition::new(&op, value)?)
+            };
+            filters.push(Filter { column, predicate });
+        }
+
+        let mut types = HashMap::new();
+        for (column, name) in schema.unwrap_or_default() {
+            if let Some(columns) = &columns {
+                if !columns.contains(&column) {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "schema types {}, which is not one of the query's columns",
+                        column
+                    )));
+                }
+            }
+            types.insert(column, ValueType::from_name(&name)?);
+        }
+        let uses = |t: ValueType| types.values().any(|&v| v == t);
+        let decimal_type = if uses(ValueType::Decimal) {
+            Some(py.import("decimal")?.getattr("Decimal")?.to_object(py))
+        } else {
+            None
+        };
+
+        let (output, convert) = match output {
+            "rows" => (QueryOutput::Rows, None),
+            "batches" => (QueryOutput::Batches, None),
+            other if BatchFormat::from_name(other).is_some() => {
+                let format = BatchFormat::from_name(other).unwrap();
+                (QueryOutput::Columns(format), batch_converter(py, format)?)
+            }
+            other => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "output must be \"rows\", \"batches\", \"columns\", \"pandas\" or \"arrow\", not {:?}",
+                    other
+                )));
+            }
+        };
+
+        Ok(Query {
+            columns,
+            filters,
+            schema: types,
+            output,
+            decimal_type,
+            convert,
+        })
+    }
+
+    // Run the query against the file at `path`, with a parser built from
+    // the keyword arguments as CSVParser(path, batch_size, **kwargs) would
+    #[pyo3(signature = (path, batch_size=RUN_BATCH_SIZE, **kwargs))]
+    fn run(
+        slf: PyRef<'_, Self>,
+        path: String,
+        batch_size: usize,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let py = slf.py();
+        let parser: &PyCell<CSVParser> = py
+            .get_type::<CSVParser>()
+            .call((path, batch_size), kwargs)?
+            .downcast()?;
+        let parser = parser.borrow();
+        parser.execute(py, slf)
+    }
+}
+
+impl Query {
+    // Every column the query names, for checking a file's header
+    pub fn required_columns(&self) -> impl Iterator<Item = &String> {
+        self.columns
+            .iter()
+            .flatten()
+            .chain(self.filters.iter().map(|f| &f.column))
+            .chain(self.schema.keys())
+    }
+
+    // The conversion the schema gives `column`, if it names it
+    pub(crate) fn kind(&self, py: Python, column: &str) -> Option<ColumnKind> {
+        let kind = match self.schema.get(column)? {
+            ValueType::Str => ColumnKind::Str,
+            ValueType::Int => ColumnKind::Int,
+            ValueType::Float => ColumnKind::Float,
+            ValueType::Decimal => ColumnKind::Decimal(self.decimal_type.as_ref()?.clone_ref(py)),
+            ValueType::Json => ColumnKind::Json,
+        };
+        Some(kind)
+    }
+}
@@ -0,0 +1,478 @@
+// Record boundary detection shared by every feature that works on raw bytes
+// instead of going through the csv reader: record offsets, the header's raw
+// bytes, the line ending report, the seek in read_chunk_optimized, the cut
+// of max_columns and the "" fields of distinguish_empty_quoted. They all
+// have to agree with the csv reader on where records start, including
+// around quoted fields that contain \r, \n or \r\n, so they all use this
+// one state machine.
+
+use memchr::{memchr, memchr2, memchr3};
+
+// Byte-level record boundary scanner. Mirrors the csv-core tokenizer's
+// state machine (quoting with doubled-quote and optional escape-character
+// escaping, \r, \n or \r\n terminators or a custom terminator byte, blank
+// lines skipped) closely enough to find where each record starts without
+// building fields, and keeps its state across calls to `feed` so input can
+// arrive in arbitrary buffer-sized pieces.
+pub struct RecordScanner {
+    delimiter: u8,
+    quote: u8,
+    // When false, quote bytes are ordinary data
+    quoting: bool,
+    escape: Option<u8>,
+    // None means csv's default: any of \r, \n or \r\n
+    terminator: Option<u8>,
+    state: ScanState,
+    // Last byte fed, and whether any record has started; for
+    // feed_until_blank
+    last: Option<u8>,
+    started: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScanState {
+    // Between records, skipping terminators of blank lines
+    StartRecord,
+    StartField,
+    InField,
+    InQuotedField,
+    // Just saw the escape character inside a quoted field
+    InEscapedQuote,
+    // Just saw a quote inside a quoted field: either an escaped quote or
+    // the closing one
+    QuoteInQuotedField,
+}
+
+const ALL_STATES: [ScanState; 6] = [
+    ScanState::StartRecord,
+    ScanState::StartField,
+    ScanState::InField,
+    ScanState::InQuotedField,
+    ScanState::InEscapedQuote,
+    ScanState::QuoteInQuotedField,
+];
+
+impl RecordScanner {
+    pub fn new(delimiter: u8, quote: u8) -> Self {
+        RecordScanner {
+            delimiter,
+            quote,
+            quoting: true,
+            escape: None,
+            terminator: None,
+            state: ScanState::StartRecord,
+            last: None,
+            started: false,
+        }
+    }
+
+    pub fn quoting(mut self, quoting: bool) -> Self {
+        self.quoting = quoting;
+        self
+    }
+
+    pub fn escape(mut self, escape: Option<u8>) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    pub fn terminator(mut self, terminator: Option<u8>) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    // Scan `buf`, which starts at absolute offset `base`, calling
+    // `on_record_start` with the absolute offset of each new record
+    pub fn feed<F: FnMut(u64)>(&mut self, buf: &[u8], base: u64, mut on_record_start: F) {
+        let mut i = 0;
+        while i < buf.len() {
+            let b = buf[i];
+            if self.state == ScanState::StartRecord && !self.is_terminator(b) {
+                on_record_start(base + i as u64);
+            }
+            self.state = self.step(self.state, b);
+            i += 1;
+            i += self.unchanged_run(&buf[i..]);
+        }
+    }
+
+    // Like feed, but stop at the first blank line that follows a record and
+    // return its absolute offset. A blank line is a line ending directly
+    // after a record's own, except the \n of a \r\n pair. Blank lines
+    // before the first record are skipped, as the csv reader does, and line
+    // breaks inside quoted fields are data. Returns None if `buf` has no
+    // blank line; the scan can go on with the next buffer.
+    pub fn feed_until_blank<F: FnMut(u64)>(
+        &mut self,
+        buf: &[u8],
+        base: u64,
+        mut on_record_start: F,
+    ) -> Option<u64> {
+        let mut i = 0;
+        while i < buf.len() {
+            let b = buf[i];
+            if self.state == ScanState::StartRecord {
+                if !self.is_terminator(b) {
+                    self.started = true;
+                    on_record_start(base + i as u64);
+                } else if self.started
+                    && !(self.terminator.is_none() && b == b'\n' && self.last == Some(b'\r'))
+                {
+                    return Some(base + i as u64);
+                }
+            }
+            self.state = self.step(self.state, b);
+            i += 1;
+            i += self.unchanged_run(&buf[i..]);
+            self.last = Some(buf[i - 1]);
+        }
+        None
+    }
+
+    // Like feed, but also check the quoting, and stop at the first byte
+    // that breaks it: a quote inside a field that didn't start with one, or
+    // anything other than a second quote, the delimiter or a line ending
+    // right after a closing quote. Returns that byte's absolute offset and
+    // what is wrong. A quoted field still open at the end of the input is
+    // for the caller to check with in_quoted_field().
+    pub fn feed_strict<F: FnMut(u64)>(
+        &mut self,
+        buf: &[u8],
+        base: u64,
+        mut on_record_start: F,
+    ) -> Option<(u64, &'static str)> {
+        for (i, &b) in buf.iter().enumerate() {
+            if self.state == ScanState::StartRecord && !self.is_terminator(b) {
+                on_record_start(base + i as u64);
+            }
+            if let Some(reason) = self.quoting_error(b) {
+                return Some((base + i as u64, reason));
+            }
+            self.state = self.step(self.state, b);
+        }
+        None
+    }
+
+    // Whether the input fed so far ends inside a quoted field
+    pub fn in_quoted_field(&self) -> bool {
+        matches!(
+            self.state,
+            ScanState::InQuotedField | ScanState::InEscapedQuote
+        )
+    }
+
+    // Whether `b` ends a line as far as records are concerned
+    pub fn is_terminator(&self, b: u8) -> bool {
+        match self.terminator {
+            None => b == b'\n' || b == b'\r',
+            Some(t) => b == t,
+        }
+    }
+
+    // Count the bare "\n", "\r\n" and bare "\r" line endings in `bytes`
+    // that end records or blank lines. Line breaks inside quoted fields are
+    // data and aren't counted. `bytes` is assumed to start at a record
+    // boundary.
+    pub fn count_line_endings(&self, bytes: &[u8]) -> (usize, usize, usize) {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        let mut state = ScanState::StartRecord;
+        let mut i = 0;
+        while i < bytes.len() {
+            let quoted = matches!(state, ScanState::InQuotedField | ScanState::InEscapedQuote);
+            match bytes[i] {
+                _ if quoted => {}
+                b'\n' => lf += 1,
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                    crlf += 1;
+                    state = self.step(state, b'\r');
+                    i += 1;
+                }
+                b'\r' => cr += 1,
+                _ => {}
+            }
+            state = self.step(state, bytes[i]);
+            i += 1;
+        }
+        (lf, crlf, cr)
+    }
+
+    // Offset within `buf` of the first record start that is certain, for a
+    // `buf` that begins at an arbitrary byte (e.g. after a seek), or None
+    // if the window is too short to tell.
+    //
+    // Starting from an unknown position, every scanner state is possible,
+    // including being inside a quoted field. All of them are run side by
+    // side until they reach the same state; from there on the scan is
+    // exact, and the next record start is a real one. A window with no
+    // quote character at all can't rule out a quoted field longer than the
+    // window, but such a field is not plausible, so only the unquoted
+    // states are considered then.
+    pub fn sync_point(&self, buf: &[u8]) -> Option<usize> {
+        let has_quote = self.quoting && buf.contains(&self.quote);
+        let mut states: Vec<ScanState> = ALL_STATES
+            .iter()
+            .copied()
+            .filter(|s| {
+                has_quote
+                    || !matches!(
+                        s,
+                        ScanState::InQuotedField
+                            | ScanState::InEscapedQuote
+                            | ScanState::QuoteInQuotedField
+                    )
+            })
+            .collect();
+
+        for (i, &b) in buf.iter().enumerate() {
+            if states.iter().all(|&s| s == states[0]) {
+                let state = states[0];
+                if state == ScanState::StartRecord && !self.is_terminator(b) {
+                    return Some(i);
+                }
+                states.truncate(1);
+            }
+            for s in states.iter_mut() {
+                *s = self.step(*s, b);
+            }
+        }
+        None
+    }
+
+    // How many bytes at the start of `rest` leave the current state as it
+    // is, so feed can skip them: the rest of an unquoted field up to a
+    // delimiter or line ending, or of a quoted field up to a quote or the
+    // escape character. Found with memchr, which compares many bytes at a
+    // time, so long fields cost little more than short ones.
+    fn unchanged_run(&self, rest: &[u8]) -> usize {
+        let next = match self.state {
+            ScanState::InField => match self.terminator {
+                None => memchr3(self.delimiter, b'\n', b'\r', rest),
+                Some(t) => memchr2(self.delimiter, t, rest),
+            },
+            ScanState::InQuotedField => match self.escape {
+                None => memchr(self.quote, rest),
+                Some(e) => memchr2(self.quote, e, rest),
+            },
+            _ => return 0,
+        };
+        next.unwrap_or(rest.len())
+    }
+
+    // What is wrong with `b` coming next, if the quoting is strict
+    fn quoting_error(&self, b: u8) -> Option<&'static str> {
+        if !self.quoting || b == self.delimiter || self.is_terminator(b) {
+            return None;
+        }
+        match self.state {
+            ScanState::InField if b == self.quote => Some("quote inside an unquoted field"),
+            ScanState::QuoteInQuotedField if b != self.quote => Some("text after a closing quote"),
+            _ => None,
+        }
+    }
+
+    fn step(&self, state: ScanState, b: u8) -> ScanState {
+        match state {
+            ScanState::StartRecord if self.is_terminator(b) => ScanState::StartRecord,
+            ScanState::StartRecord | ScanState::StartField => self.start_field(b),
+            ScanState::InField => self.after_field_byte(b),
+            ScanState::InQuotedField if b == self.quote => ScanState::QuoteInQuotedField,
+            ScanState::InQuotedField if Some(b) == self.escape => ScanState::InEscapedQuote,
+            ScanState::InQuotedField => ScanState::InQuotedField,
+            ScanState::InEscapedQuote => ScanState::InQuotedField,
+            ScanState::QuoteInQuotedField if b == self.quote => ScanState::InQuotedField,
+            ScanState::QuoteInQuotedField => self.after_field_byte(b),
+        }
+    }
+
+    fn start_field(&self, b: u8) -> ScanState {
+        if self.quoting && b == self.quote {
+            ScanState::InQuotedField
+        } else {
+            self.after_field_byte(b)
+        }
+    }
+
+    fn after_field_byte(&self, b: u8) -> ScanState {
+        if b == self.delimiter {
+            ScanState::StartField
+        } else if self.is_terminator(b) {
+            ScanState::StartRecord
+        } else {
+            ScanState::InField
+        }
+    }
+}
+
+// max_columns: rewrites the input in place so the csv reader ends every
+// record after its first `max` fields and never holds the rest. The
+// delimiter that would start field max + 1 becomes a \r, which ends the
+// record there, and so does every later byte up to the record's own line
+// ending, except \n, which stays so line numbers don't change; the reader
+// skips those bytes as blank lines. No byte is added or removed, so offsets
+// are still the file's. The csv position of a record after a cut one is
+// right after the cut delimiter, inside the dropped bytes; restart() is
+// told whether a seek landed on such a position.
+pub struct FieldCutter {
+    scanner: RecordScanner,
+    max: usize,
+    // Delimiters seen so far in the current record
+    delimiters: usize,
+    // Inside the dropped fields of a cut record
+    cutting: bool,
+}
+
+impl FieldCutter {
+    pub fn new(scanner: RecordScanner, max: usize) -> Self {
+        FieldCutter {
+            scanner,
+            max,
+            delimiters: 0,
+            cutting: false,
+        }
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.scanner.delimiter
+    }
+
+    // Start over at a seek target: a record start, or, if
+    // `after_delimiter`, the first dropped byte of a cut record
+    pub fn restart(&mut self, after_delimiter: bool) {
+        self.scanner.state = if after_delimiter {
+            ScanState::StartField
+        } else {
+            ScanState::StartRecord
+        };
+        self.delimiters = 0;
+        self.cutting = after_delimiter;
+    }
+
+    // Rewrite `buf`, the next bytes of the input
+    pub fn cut(&mut self, buf: &mut [u8]) {
+        let mut i = 0;
+        while i < buf.len() {
+            let before = self.scanner.state;
+            let b = buf[i];
+            self.scanner.state = self.scanner.step(before, b);
+            i += 1;
+            if self.cutting {
+                if self.scanner.state == ScanState::StartRecord && before != ScanState::StartRecord
+                {
+                    // The cut record's own line ending
+                    self.cutting = false;
+                    self.delimiters = 0;
+                } else if b != b'\n' {
+                    buf[i - 1] = b'\r';
+                }
+                continue;
+            }
+            match self.scanner.state {
+                ScanState::StartField => {
+                    self.delimiters += 1;
+                    if self.delimiters >= self.max {
+                        self.cutting = true;
+                        buf[i - 1] = b'\r';
+                        continue;
+                    }
+                }
+                ScanState::StartRecord => self.delimiters = 0,
+                _ => {}
+            }
+            i += self.scanner.unchanged_run(&buf[i..]);
+        }
+    }
+}
+
+// distinguish_empty_quoted: follows the input as it is read and reports,
+// for each record with any, which of its fields were written as "". The
+// csv reader drops quotes, so this is the only place the difference is
+// still visible. Records are identified by the offset just past their
+// last byte, which is where the csv reader's position is once it has read
+// the record.
+pub struct QuoteTracker {
+    scanner: RecordScanner,
+    // Offset of the next byte to be fed
+    pos: u64,
+    // Index of the current field in its record
+    field: usize,
+    // The current field opened with a quote and has had nothing since but
+    // its closing quote
+    empty_quoted: bool,
+    found: Vec<usize>,
+}
+
+impl QuoteTracker {
+    pub fn new(scanner: RecordScanner) -> Self {
+        QuoteTracker {
+            scanner,
+            pos: 0,
+            field: 0,
+            empty_quoted: false,
+            found: Vec::new(),
+        }
+    }
+
+    // Start over at `pos`, a record start
+    pub fn restart(&mut self, pos: u64) {
+        self.scanner.state = ScanState::StartRecord;
+        self.pos = pos;
+        self.field = 0;
+        self.empty_quoted = false;
+        self.found.clear();
+    }
+
+    // Scan `buf`, the next bytes of the input, calling `on_record` with the
+    // end offset and the "" fields of each record that has any
+    pub fn feed<F: FnMut(u64, Vec<usize>)>(&mut self, buf: &[u8], mut on_record: F) {
+        let mut i = 0;
+        while i < buf.len() {
+            let before = self.scanner.state;
+            let after = self.scanner.step(before, buf[i]);
+            self.scanner.state = after;
+            i += 1;
+            match (before, after) {
+                (ScanState::StartRecord | ScanState::StartField, ScanState::InQuotedField) => {
+                    self.empty_quoted = true;
+                }
+                (ScanState::StartRecord, ScanState::StartRecord) => {}
+                (ScanState::InQuotedField, ScanState::QuoteInQuotedField) => {}
+                (_, ScanState::StartField) => self.end_field(),
+                (_, ScanState::StartRecord) => {
+                    self.end_field();
+                    self.end_record(self.pos + i as u64, &mut on_record);
+                }
+                _ => self.empty_quoted = false,
+            }
+            let skipped = self.scanner.unchanged_run(&buf[i..]);
+            if skipped > 0 && after == ScanState::InQuotedField {
+                self.empty_quoted = false;
+            }
+            i += skipped;
+        }
+        self.pos += buf.len() as u64;
+    }
+
+    // The input has ended: a last record without a line ending ends here
+    pub fn finish<F: FnMut(u64, Vec<usize>)>(&mut self, mut on_record: F) {
+        if self.scanner.state != ScanState::StartRecord {
+            self.scanner.state = ScanState::StartRecord;
+            self.end_field();
+            self.end_record(self.pos, &mut on_record);
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.empty_quoted {
+            self.found.push(self.field);
+        }
+        self.field += 1;
+        self.empty_quoted = false;
+    }
+
+    fn end_record<F: FnMut(u64, Vec<usize>)>(&mut self, end: u64, on_record: &mut F) {
+        if !self.found.is_empty() {
+            on_record(end, std::mem::take(&mut self.found));
+        }
+        self.field = 0;
+    }
+}
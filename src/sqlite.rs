@@ -0,0 +1,177 @@
+// CSVParser.to_sqlite(): load a CSV file into a SQLite table. Only built
+// with the "sqlite" cargo feature. Works on field text only, so the whole
+// load runs without the GIL.
+
+use crate::cancel::Cancel;
+use csv::StringRecord;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use std::io::Read;
+
+// Declared type of a column, inferred from a sample of its cells
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn sql(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+pub enum ExportError {
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        ExportError::Csv(e)
+    }
+}
+
+impl From<rusqlite::Error> for ExportError {
+    fn from(e: rusqlite::Error) -> Self {
+        ExportError::Sqlite(e)
+    }
+}
+
+// The type of each field in `fields` (record indices): INTEGER if every
+// non-empty sampled cell parses as an i64, REAL if every one parses as a
+// number, TEXT otherwise. A column with no non-empty cells in the sample
+// is TEXT.
+pub fn infer_types<R: Read>(
+    reader: &mut csv::Reader<R>,
+    fields: &[usize],
+    sample_rows: usize,
+) -> csv::Result<Vec<ColumnType>> {
+    let mut types: Vec<Option<ColumnType>> = vec![None; fields.len()];
+    let mut record = StringRecord::new();
+    let mut rows = 0;
+
+    while rows < sample_rows && reader.read_record(&mut record)? {
+        rows += 1;
+        for (slot, &i) in types.iter_mut().zip(fields) {
+            let field = record.get(i).unwrap_or("");
+            if field.is_empty() {
+                continue;
+            }
+            let seen = if field.parse::<i64>().is_ok() {
+                ColumnType::Integer
+            } else if field.parse::<f64>().is_ok() {
+                ColumnType::Real
+            } else {
+                ColumnType::Text
+            };
+            *slot = Some(match (*slot, seen) {
+                (None, t) => t,
+                (Some(ColumnType::Text), _) | (_, ColumnType::Text) => ColumnType::Text,
+                (Some(ColumnType::Real), _) | (_, ColumnType::Real) => ColumnType::Real,
+                _ => ColumnType::Integer,
+            });
+        }
+    }
+
+    Ok(types
+        .into_iter()
+        .map(|t| t.unwrap_or(ColumnType::Text))
+        .collect())
+}
+
+// Create `table` if needed and insert fields `fields` of every remaining
+// record of `reader` (up to `max_rows`) as `columns`, typed `types`, with
+// one prepared statement, committing every `batch_commit` rows. Returns
+// the number of rows inserted. Stops early, keeping the rows inserted so
+// far, once `cancel` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn write_table<R: Read>(
+    reader: &mut csv::Reader<R>,
+    fields: &[usize],
+    columns: &[String],
+    types: &[ColumnType],
+    db_path: &str,
+    table: &str,
+    batch_commit: usize,
+    max_rows: usize,
+    cancel: &Cancel,
+) -> Result<usize, ExportError> {
+    let mut conn = Connection::open(db_path)?;
+
+    let definitions: Vec<String> = columns
+        .iter()
+        .zip(types)
+        .map(|(name, t)| format!("{} {}", quote_identifier(name), t.sql()))
+        .collect();
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            quote_identifier(table),
+            definitions.join(", ")
+        ),
+        [],
+    )?;
+
+    let insert = format!(
+        "INSERT INTO {} VALUES ({})",
+        quote_identifier(table),
+        vec!["?"; columns.len()].join(", ")
+    );
+
+    let mut record = StringRecord::new();
+    let mut rows = 0;
+    let mut done = false;
+    while !done {
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare_cached(&insert)?;
+            let mut in_batch = 0;
+            while in_batch < batch_commit {
+                if rows >= max_rows || cancel.check(rows) || !reader.read_record(&mut record)? {
+                    done = true;
+                    break;
+                }
+                let values = fields
+                    .iter()
+                    .zip(types)
+                    .map(|(&i, t)| to_value(record.get(i).unwrap_or(""), *t));
+                statement.execute(params_from_iter(values))?;
+                in_batch += 1;
+                rows += 1;
+            }
+        }
+        tx.commit()?;
+    }
+
+    Ok(rows)
+}
+
+// Empty cells are NULL. A cell that doesn't fit its column's inferred type
+// (the sample missed it) is stored as text rather than failing the load;
+// SQLite allows that.
+fn to_value(field: &str, column: ColumnType) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    match column {
+        ColumnType::Integer => match field.parse::<i64>() {
+            Ok(n) => Value::Integer(n),
+            Err(_) => Value::Text(field.to_string()),
+        },
+        ColumnType::Real => match field.parse::<f64>() {
+            Ok(x) => Value::Real(x),
+            Err(_) => Value::Text(field.to_string()),
+        },
+        ColumnType::Text => Value::Text(field.to_string()),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,60 @@
+// CSVParser.to_table(): rows as an aligned plain-text table for terminal
+// previews. Widths are counted in characters, so wide (e.g. CJK)
+// characters can still push a column out of line.
+
+// Between columns
+const GAP: &str = "  ";
+
+// Header, a rule of dashes under it, then one line per row, each column
+// as wide as its widest cell. Cells longer than `max_width` characters
+// are cut to fit, ending in "…". Line breaks and tabs inside a cell are
+// shown escaped so each row stays on one line. Lines end without padding.
+pub fn render(header: &[String], rows: &[Vec<String>], max_width: usize) -> String {
+    let fit = |cell: &str| shorten(&escape(cell), max_width);
+    let header: Vec<String> = header.iter().map(|h| fit(h)).collect();
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(|c| fit(c)).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+
+    let mut out = String::new();
+    for line in std::iter::once(&header)
+        .chain(std::iter::once(&rule))
+        .chain(&rows)
+    {
+        let mut text = String::new();
+        for (i, (cell, width)) in line.iter().zip(&widths).enumerate() {
+            if i > 0 {
+                text.push_str(GAP);
+            }
+            text.push_str(cell);
+            text.push_str(&" ".repeat(width - cell.chars().count()));
+        }
+        out.push_str(text.trim_end_matches(' '));
+        out.push('\n');
+    }
+    out
+}
+
+fn escape(cell: &str) -> String {
+    cell.replace('\r', "\\r")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+fn shorten(cell: &str, max_width: usize) -> String {
+    if cell.chars().count() <= max_width {
+        return cell.to_string();
+    }
+    let mut cut: String = cell.chars().take(max_width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
@@ -0,0 +1,1424 @@
+// The Python API, driven through the embedded interpreter: each test
+// writes its CSV to a temp file and runs a Python snippet against the
+// csv_reader module, with the file's path as `path`.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyModule};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex, OnceLock};
+
+// A temp file holding `content`, removed when dropped
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(suffix: &str, content: &[u8]) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "csv_reader_test_{}_{}{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            suffix
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// read_optimized() builds files up to this size as one flat list; run()
+// sets it so every snippet goes through that path and the batched one
+pub static SMALL_FILE_SIZE: AtomicUsize = AtomicUsize::new(crate::SMALL_FILE_SIZE);
+
+// Run `code` with the module's names in scope and `path` naming a file
+// holding `csv`; a failing assert fails the test with the traceback. It
+// runs twice, once with small files read as one flat list and once
+// through the batched path, with the file as it was before each run.
+pub fn run(csv: &str, code: &str) {
+    let file = TempFile::new(".csv", csv.as_bytes());
+    run_with(&file, code);
+}
+
+pub fn run_with(file: &TempFile, code: &str) {
+    let content = std::fs::read(&file.0).unwrap();
+    for small_file_size in [usize::MAX, 0] {
+        std::fs::write(&file.0, &content).unwrap();
+        run_once(file, code, small_file_size);
+    }
+}
+
+fn run_once(file: &TempFile, code: &str, small_file_size: usize) {
+    let path = file.0.to_string_lossy().into_owned();
+    let code = dedent(code);
+    let failure = on_python_thread(Box::new(move || {
+        SMALL_FILE_SIZE.store(small_file_size, Ordering::Relaxed);
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "csv_reader").unwrap();
+            crate::csv_reader(py, module).unwrap();
+            let globals = PyDict::new(py);
+            globals
+                .set_item("__builtins__", py.import("builtins").unwrap())
+                .unwrap();
+            globals.set_item("csv_reader", module).unwrap();
+            for (name, value) in module.dict() {
+                if !name.to_string().starts_with("__") {
+                    globals.set_item(name, value).unwrap();
+                }
+            }
+            globals.set_item("path", &path).unwrap();
+            py.run(&code, Some(globals), None).err().map(|e| {
+                e.print(py);
+                e.to_string()
+            })
+        })
+    }));
+    if let Some(failure) = failure {
+        panic!("{} (small_file_size={})", failure, small_file_size);
+    }
+}
+
+type Job = Box<dyn FnOnce() -> Option<String> + Send>;
+// Jobs, each with where to send its failure
+type Jobs = mpsc::Sender<(Job, mpsc::Sender<Option<String>>)>;
+
+// Run `job` on the thread that started the interpreter, which is the one
+// Python runs signal handlers on, so tests can interrupt a read. Jobs run
+// one at a time.
+fn on_python_thread(job: Job) -> Option<String> {
+    static JOBS: OnceLock<Mutex<Jobs>> = OnceLock::new();
+    let jobs = JOBS.get_or_init(|| {
+        let (send, receive): (Jobs, _) = mpsc::channel();
+        std::thread::spawn(move || {
+            pyo3::prepare_freethreaded_python();
+            for (job, done) in receive {
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job))
+                    .unwrap_or_else(|_| Some("the test job panicked".to_string()));
+                let _ = done.send(result);
+            }
+        });
+        Mutex::new(send)
+    });
+    let (done, result) = mpsc::channel();
+    jobs.lock().unwrap().send((job, done)).unwrap();
+    result.recv().unwrap()
+}
+
+// Strip the indentation shared by every non-blank line, so snippets can
+// be indented along with the Rust around them
+fn dedent(code: &str) -> String {
+    let indent = code
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    code.lines()
+        .map(|line| line.get(indent..).unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn timings_are_reported_per_read() {
+    run(
+        "a,b\n1,2\n3,4\n",
+        r#"
+        p = CSVParser(path, 100, timings=True)
+        p.read()
+        t = p.last_read_stats()["timings"]
+        assert set(t) == {"read", "parse", "convert", "build", "callback", "other", "total"}
+        assert t["total"] > 0
+
+        assert "timings" not in (CSVParser(path, 100).last_read_stats() or {})
+        "#,
+    );
+}
+
+#[test]
+fn concurrent_reads_keep_their_own_timings() {
+    run(
+        "a,b\n1,2\n3,4\n5,6\n",
+        r#"
+        p = CSVParser(path, batch_size=1, timings=True)
+        rows = p.iter_rows()
+        next(rows)
+        batches = p.lazy_batches()
+        assert len(list(batches)) == 3
+        assert batches.batch_timings["total"] > 0
+        after_batches = p.last_read_stats()["timings"]
+        # Finishing the row iterator adds to its own totals only
+        assert len(list(rows)) == 2
+        assert p.last_read_stats()["timings"] == after_batches
+        "#,
+    );
+}
+
+#[test]
+fn ctrl_c_stops_scans_without_the_gil() {
+    let rows: String = (0..200_000).map(|i| format!("{},{}\n", i, i * 2)).collect();
+    let file = TempFile::new(".csv", format!("a,b\n{}", rows).as_bytes());
+    let out = TempFile::new(".txt", b"");
+    run_with(
+        &file,
+        &format!(
+            r#"
+            import os, signal, sys, threading
+
+            def interrupt(*_):
+                raise KeyboardInterrupt
+
+            # The thread only gets the GIL once the scan releases it, and
+            # the signal then arrives while the scan runs
+            def interrupt_scan(start):
+                start.wait()
+                os.kill(os.getpid(), signal.SIGUSR1)
+
+            def interrupted(scan):
+                start = threading.Event()
+                thread = threading.Thread(target=interrupt_scan, args=(start,))
+                thread.start()
+                start.set()
+                try:
+                    scan()
+                except KeyboardInterrupt:
+                    return True
+                finally:
+                    thread.join()
+                return False
+
+            old = signal.signal(signal.SIGUSR1, interrupt)
+            switch = sys.getswitchinterval()
+            sys.setswitchinterval(1000)
+            try:
+                p = CSVParser(path, 1000, signal_check_interval=100)
+                assert interrupted(lambda: p.to_fixed_width({out:?}, [6, 6]))
+                with open({out:?}) as f:
+                    assert len(f.readlines()) < 200_000
+                assert interrupted(lambda: p.analyze({{"a": ["sum"]}}))
+                assert interrupted(lambda: p.hash_rows())
+                assert interrupted(lambda: p.quantile("a", 0.5))
+                assert interrupted(lambda: p.quantile("a", 0.5, approximate=False))
+            finally:
+                sys.setswitchinterval(switch)
+                signal.signal(signal.SIGUSR1, old)
+            "#,
+            out = out.0.to_string_lossy()
+        ),
+    );
+}
+
+#[test]
+fn unique_rows_count_each_distinct_row() {
+    run(
+        "a,b\n1,x\n2,y\n1,x\n1,y\n1,x\n",
+        r#"
+        p = CSVParser(path, 100)
+        assert p.unique_rows_with_counts() == [
+            ({"a": "1", "b": "x"}, 3),
+            ({"a": "2", "b": "y"}, 1),
+            ({"a": "1", "b": "y"}, 1),
+        ]
+        assert p.unique_rows_with_counts(subset=["a"]) == [({"a": "1"}, 4), ({"a": "2"}, 1)]
+        "#,
+    );
+}
+
+#[test]
+fn unique_rows_subset_follows_rename() {
+    run(
+        "a,b\n1,x\n1,y\n",
+        r#"
+        p = CSVParser(path, 100, rename={"a": "id"})
+        assert p.unique_rows_with_counts(subset=["a"]) == [({"id": "1"}, 2)]
+        "#,
+    );
+}
+
+#[test]
+fn read_keyed_uses_row_keys_and_cleaned_cells() {
+    run(
+        "id,name\n\u{feff}\u{200b}7,x\n8,y\n",
+        r#"
+        p = CSVParser(path, 100, rename={"name": "label"}, strip_zero_width=True)
+        assert p.read_keyed("id", subset=["name"]) == {"7": {"label": "x"}, "8": {"label": "y"}}
+        "#,
+    );
+}
+
+#[test]
+fn json_columns_arrive_as_python_objects() {
+    run(
+        "id,payload\n1,\"{\"\"user\"\": {\"\"id\"\": 42}, \"\"tags\"\": [\"\"a\"\", null, true, 1.5]}\"\n2,123456789012345678901234567890\n3,{oops\n4,\n",
+        r#"
+        rows = CSVParser(path, 100, json_columns=["payload"]).read()[0]
+        assert rows[0]["payload"] == {"user": {"id": 42}, "tags": ["a", None, True, 1.5]}
+        assert rows[1]["payload"] == 123456789012345678901234567890
+        assert rows[2]["payload"] == "{oops"
+        assert rows[3]["payload"] == ""
+
+        rows = CSVParser(path, 100, json_columns=["payload"], json_invalid="none").read()[0]
+        assert [r["payload"] for r in rows[2:]] == [None, None]
+        "#,
+    );
+}
+
+#[test]
+fn auto_seek_returns_exact_rows_when_widths_vary() {
+    // Narrow rows at the top, where the width is estimated, then wide ones
+    let mut csv = String::from("id,text\n");
+    for i in 0..20_000 {
+        let width = if i < 200 { 1 } else { 40 + i % 7 };
+        csv.push_str(&format!("{},{}\n", i, "x".repeat(width)));
+    }
+    run(
+        &csv,
+        r#"
+        import warnings
+
+        def ids(rows):
+            return [int(r["id"]) for r in rows]
+
+        with warnings.catch_warnings(record=True):
+            warnings.simplefilter("always")
+            auto = CSVParser(path, 100)
+            assert ids(auto.read_chunk_optimized(15_000, 10)) == list(range(15_000, 15_010))
+            assert auto.last_read_stats()["seek_fallbacks"] == 1
+
+            estimate = CSVParser(path, 100, seek_strategy="estimate")
+            assert ids(estimate.read_chunk_optimized(15_000, 10)) != list(range(15_000, 15_010))
+
+        # Even widths: the seek lands close, and the rest is skipped exactly
+        # from there, also on a second call counting from the first's anchor
+        with open(path, "w") as f:
+            f.write("id,text\n")
+            f.writelines(f"{i},{'y' * (20 + i % 3)}\n" for i in range(20_000))
+        auto = CSVParser(path, 100)
+        for start in (12_345, 17_000):
+            assert ids(auto.read_chunk_optimized(start, 5)) == list(range(start, start + 5))
+        assert (auto.last_read_stats() or {}).get("seek_fallbacks", 0) == 0
+        "#,
+    );
+}
+
+#[test]
+fn fixed_width_keeps_multiline_cells_on_one_line() {
+    let out = TempFile::new(".txt", b"");
+    run(
+        "id,note\n1,\"two\nlines\"\n2,\"crlf\r\nand\rcr\"\n3,plain\n",
+        &format!(
+            r#"
+            p = CSVParser(path, 100)
+            assert p.to_fixed_width({out:?}, [2, 14], include_header=True) == 3
+            with open({out:?}, newline="") as f:
+                assert f.read() == (
+                    "idnote          \n"
+                    "1 two lines     \n"
+                    "2 crlf and cr   \n"
+                    "3 plain         \n"
+                )
+            "#,
+            out = out.0.to_string_lossy()
+        ),
+    );
+}
+
+#[test]
+fn empty_columns_count_na_values_and_need_rows() {
+    run(
+        "a,b,c,d\n1,,NA,x\n2,,,\n",
+        r#"
+        assert CSVParser(path, 100).empty_columns() == ["b"]
+        assert CSVParser(path, 100, na_values=["NA"]).empty_columns() == ["b", "c"]
+        "#,
+    );
+    run(
+        "a,b\n",
+        r#"
+        assert CSVParser(path, 100).empty_columns() == []
+        assert CSVParser(path, 100, drop_empty_columns=True).read() == []
+        "#,
+    );
+}
+
+#[test]
+fn drop_empty_columns_drops_by_position() {
+    // Only the second "x" is empty
+    run(
+        "x,y,x\n1,2,\n3,4,\n",
+        r#"
+        p = CSVParser(path, 100, drop_empty_columns=True)
+        assert p.empty_columns() == ["x"]
+        assert p.read()[0] == [{"x": "1", "y": "2"}, {"x": "3", "y": "4"}]
+        "#,
+    );
+}
+
+#[test]
+fn every_read_method_numbers_rows_alike() {
+    // Blank lines, quoted line breaks, CRLF and a short row
+    run(
+        "id,note\r\n0,a\r\n\r\n1,\"two\nlines\"\n\n\n2,\"x,y\"\n3\n4,\"\"\"q\"\"\"\r\n5,last",
+        r#"
+        for has_headers in (True, False):
+            def parser(**options):
+                return CSVParser(path, 3, has_headers=has_headers, **options)
+
+            full = [r for batch in parser().read() for r in batch]
+            assert len(full) == (6 if has_headers else 7)
+            assert [r for batch in parser().read_optimized() for r in batch] == full
+            assert list(parser().iter_rows()) == full
+            assert [r for batch in parser().lazy_batches() for r in batch] == full
+            assert parser().count_rows() == len(full)
+            assert parser().read_mask([i % 2 == 0 for i in range(len(full))]) == full[::2]
+
+            p = parser()
+            for size in (1, 2, 4):
+                pages = [p.read_chunk(start, size) for start in range(0, len(full) + size, size)]
+                assert [r for page in pages for r in page] == full
+            exact = parser(seek_strategy="exact")
+            assert exact.read_chunk_optimized(2, 3) == full[2:5]
+            for i, row in enumerate(full):
+                assert p.get_row(i) == row
+            try:
+                p.get_row(len(full))
+                raise AssertionError("get_row past the end")
+            except IndexError:
+                pass
+
+            numbered = [r for batch in parser(add_row_number="n").read() for r in batch]
+            assert [r.pop("n") for r in numbered] == list(range(len(full)))
+            assert numbered == full
+            numbered = parser(add_row_number="n")
+            assert [r["n"] for r in numbered.iter_rows()] == list(range(len(full)))
+            assert [r["n"] for r in numbered.read_chunk(3, 2)] == [3, 4]
+            assert numbered.get_row(4)["n"] == 4
+        "#,
+    );
+}
+
+#[test]
+fn quoted_headers_give_the_same_keys_on_every_path() {
+    run(
+        "",
+        r#"
+        for eol in ["\n", "\r\n"]:
+            keys = ["Last, First", f"multi{eol}line", 'q"x', "age"]
+            with open(path, "w", newline="") as f:
+                f.write(f'"Last, First","multi{eol}line","q""x",age{eol}')
+                for i in range(3000):
+                    f.write(f'"{i:04d}, x","{i:04d}{eol}y",q,{i % 90:02d}{eol}')
+            want = [
+                dict(zip(keys, [f"{i:04d}, x", f"{i:04d}{eol}y", "q", f"{i % 90:02d}"]))
+                for i in range(3000)
+            ]
+
+            p = CSVParser(path, 100)
+            assert p.get_file_info()["headers"] == keys, eol
+            assert [r for b in p.read() for r in b] == want, eol
+            assert p.read_chunk(10, 3) == want[10:13], eol
+            # The exact branch, which pages through pooled readers
+            assert p.read_chunk_optimized(20, 3) == want[20:23], eol
+            # The seek branch, with its own header reader; it doesn't touch
+            # the pool or fall back
+            before = p.last_read_stats()
+            assert p.read_chunk_optimized(2500, 3) == want[2500:2503], eol
+            after = p.last_read_stats()
+            for stat in ("pool_hits", "pool_misses", "seek_fallbacks"):
+                assert after[stat] == before[stat], (eol, stat, before, after)
+        "#,
+    );
+}
+
+#[test]
+fn every_method_agrees_on_each_dialect() {
+    run(
+        "",
+        r#"
+        def row(i, sep=",", eol="\n"):
+            # Quoted delimiters and line breaks in every dialect
+            return f'{i}{sep}"v{sep}{i}"{sep}"a{eol}b"{eol}'
+
+        fixtures = {
+            "ragged": ("id,v,note\n" + "".join(
+                row(i) if i % 7 else f"{i},short\n" if i % 2 else f"{i},x,y,extra\n"
+                for i in range(1500)), {}),
+            "crlf": ("id,v,note\r\n" + "".join(row(i, eol="\r\n") for i in range(1500)), {}),
+            "bom": ("\ufeffid,v,note\n" + "".join(row(i) for i in range(1500)), {}),
+            "semicolon": ("id;v;note\n" + "".join(row(i, sep=";") for i in range(1500)),
+                          {"fallback_delimiters": [";"]}),
+        }
+        for name, (text, options) in fixtures.items():
+            with open(path, "w", encoding="utf-8", newline="") as f:
+                f.write(text)
+            p = CSVParser(path, 7, **options)
+            full = [r for b in p.read() for r in b]
+            assert len(full) == 1500 and list(full[1]) == ["id", "v", "note"], (name, full[1])
+            assert p.count_rows() == len(full), name
+            assert [r for b in p.read_optimized() for r in b] == full, name
+            assert list(p.iter_rows()) == full, name
+            for start, n in [(0, 5), (13, 20), (1200, 50), (1497, 10)]:
+                assert p.read_chunk(start, n) == full[start:start + n], (name, start)
+                assert p.read_chunk_optimized(start, n) == full[start:start + n], (name, start)
+            for i in (0, 14, 777, 1499):
+                assert p.get_row(i) == full[i], (name, i)
+        "#,
+    );
+}
+
+#[test]
+fn small_files_come_back_in_the_same_batches() {
+    run(
+        "a\n1\n2\n3\n4\n5\n",
+        r#"
+        assert [len(b) for b in CSVParser(path, 2).read()] == [2, 2, 1]
+        assert [len(b) for b in CSVParser(path, 5).read()] == [5]
+        assert [len(b) for b in CSVParser(path, 2, row_limit=4).read()] == [2, 2]
+        "#,
+    );
+    run(
+        "a\n",
+        r#"
+        assert CSVParser(path, 2).read() == []
+        "#,
+    );
+}
+
+#[test]
+fn read_chunk_pool_counts_hits_and_misses() {
+    let rows: String = (0..1000).map(|i| format!("{}\n", i)).collect();
+    run(
+        &format!("id\n{}", rows),
+        r#"
+        import random
+
+        def ids(rows):
+            return [int(r["id"]) for r in rows]
+
+        def counters(p):
+            stats = p.last_read_stats()
+            return stats["pool_hits"], stats["pool_misses"]
+
+        # Paging forward: only the first call starts from the top
+        p = CSVParser(path, 100)
+        for start in range(0, 1000, 50):
+            assert ids(p.read_chunk(start, 50)) == list(range(start, start + 50))
+        assert counters(p) == (19, 1)
+
+        # Going back misses; any later start then hits a pooled reader
+        assert ids(p.read_chunk(10, 5)) == list(range(10, 15))
+        assert counters(p) == (19, 2)
+        random.seed(7)
+        for start in random.sample(range(15, 990), 30):
+            assert ids(p.read_chunk(start, 10)) == list(range(start, start + 10))
+        hits, misses = counters(p)
+        assert hits + misses == 51 and hits > 19
+
+        # Iterators neither take from nor feed the pool
+        list(p.iter_rows())
+        assert counters(p) == (hits, misses)
+
+        # A changed file drops the pooled readers
+        with open(path, "a") as f:
+            f.write("1000\n")
+        assert ids(p.read_chunk(995, 10)) == list(range(995, 1001))
+        assert counters(p) == (hits, misses + 1)
+        "#,
+    );
+}
+
+#[test]
+fn allow_empty_counts_data_rows_in_both_header_modes() {
+    for (csv, empty_with_header, empty_without) in [
+        ("", true, true),
+        ("\n\r\n\n", true, true),
+        ("a,b\n", true, false),
+        ("a,b\n\n", true, false),
+        ("a,b\n1,2\n", false, false),
+    ] {
+        run(
+            csv,
+            &format!(
+                r#"
+                def empty(has_headers):
+                    try:
+                        CSVParser(path, 100, has_headers=has_headers, allow_empty=False)
+                    except EmptyFileError:
+                        return True
+                    return False
+
+                assert empty(True) == {}
+                assert empty(False) == {}
+                assert CSVParser(path, 100, allow_empty=True).count_rows() == {}
+                "#,
+                if empty_with_header { "True" } else { "False" },
+                if empty_without { "True" } else { "False" },
+                if empty_with_header { 0 } else { 1 },
+            ),
+        );
+    }
+}
+
+#[test]
+fn column_identifiers_are_python_identifiers() {
+    run(
+        "Größe (cm),年齢,1st,\u{0301}e,x²,a\u{00b7}b,٣٤,class,,😀,Größe (cm)\n",
+        r#"
+        ids = list(CSVParser(path, 100).column_identifiers())
+        assert ids == [
+            "Größe_cm", "年齢", "col_1st", "col_\u0301e", "x", "a\u00b7b",
+            "col_٣٤", "class_", "col_8", "col_9", "Größe_cm_2",
+        ]
+        assert all(i.isidentifier() for i in ids)
+        "#,
+    );
+}
+
+#[test]
+fn empty_results_keep_their_shape_in_every_output() {
+    // pandas and pyarrow stand-ins that record what they were built with
+    let stubs = r#"
+        import sys, types
+
+        class DataFrame:
+            def __init__(self, data):
+                self.data = data
+                self.dtypes = {name: "object" for name in data}
+
+            def astype(self, dtypes):
+                frame = DataFrame(self.data)
+                frame.dtypes = dict(dtypes)
+                return frame
+
+        class RecordBatch:
+            @staticmethod
+            def from_pydict(data, schema=None):
+                return data, schema
+
+        pd = types.ModuleType("pandas")
+        pd.DataFrame = DataFrame
+        pa = types.ModuleType("pyarrow")
+        pa.string = lambda: "string"
+        pa.int64 = lambda: "int64"
+        pa.float64 = lambda: "float64"
+        pa.decimal128 = lambda precision, scale: f"decimal128({precision}, {scale})"
+        pa.schema = list
+        pa.RecordBatch = RecordBatch
+        saved = {name: sys.modules.get(name) for name in ("pandas", "pyarrow")}
+        sys.modules.update(pandas=pd, pyarrow=pa)
+    "#;
+    let checks = r#"
+        try:
+            names = ["i", "f", "d", "j", "s"]
+            schema = {"i": "int", "f": "float", "d": "decimal", "j": "json", "s": "str"}
+            dtypes = {"i": "int64", "f": "float64", "d": "object", "j": "object", "s": "object"}
+            types = ["int64", "float64", "decimal128(38, 18)", "string", "string"]
+
+            def query(output):
+                q = Query(filter=FILTER, schema=schema, output=output)
+                return q.run(path, 100)
+
+            assert query("rows") == []
+            assert query("batches") == []
+            assert query("columns") == {n: [] for n in names}
+            frame = query("pandas")
+            assert frame.data == {n: [] for n in names} and frame.dtypes == dtypes
+            batch, arrow_schema = query("arrow")
+            assert batch == {n: [] for n in names}
+            assert arrow_schema == list(zip(names, types))
+
+            opts = dict(decimal_columns=["d"], json_columns=["j"], add_row_number="n")
+            if not FILTER:
+                p = CSVParser(path, 100, **opts)
+                assert p.read() == []
+                assert list(p.lazy_batches()) == []
+                assert list(p.lazy_batches("columns")) == [{**{n: [] for n in names}, "n": []}]
+                (frame,) = p.lazy_batches("pandas")
+                assert frame.dtypes == {
+                    "i": "object", "f": "object", "d": "object", "j": "object",
+                    "s": "object", "n": "int64",
+                }
+                ((batch, arrow_schema),) = p.lazy_batches("arrow")
+                assert [t for _, t in arrow_schema] == [
+                    "string", "string", "decimal128(38, 18)", "string", "string", "int64",
+                ]
+                columns = {}
+                assert p.append_to_columns(columns) == 0
+                assert columns == {**{n: [] for n in names}, "n": []}
+                array = p.read_column_array("i", "q")
+                assert array.typecode == "q" and len(array) == 0
+        finally:
+            for name, module in saved.items():
+                if module is None:
+                    del sys.modules[name]
+                else:
+                    sys.modules[name] = module
+    "#;
+    let header = "i,f,d,j,s\n";
+    for (csv, filter) in [
+        (header.to_string(), "None"),
+        (
+            format!("{}1,2.5,3.1,{{}},x\n", header),
+            "[(\"s\", \"==\", \"nope\")]",
+        ),
+    ] {
+        let code = format!("{}\nFILTER = {}\n{}", dedent(stubs), filter, dedent(checks));
+        run(&csv, &code);
+    }
+}
+
+#[test]
+fn repeated_names_get_the_first_free_suffix() {
+    run(
+        "a,a,a.1,a,b,B,b\n",
+        r#"
+        assert CSVParser(path, 100, dedup_headers=True).get_file_info()["headers"] == [
+            "a", "a.1", "a.1.1", "a.2", "b", "B", "b.1",
+        ]
+        p = CSVParser(path, 100)
+        assert list(p.column_identifiers()) == ["a", "a_2", "a_1", "a_3", "b", "B", "b_2"]
+        assert list(p.column_identifiers("sql")) == ["a", "a_2", "a.1", "a_3", "b", "B_2", "b_3"]
+        "#,
+    );
+    // Thousands of copies of one name stay fast and unique
+    let header = vec!["x"; 20_000].join(",");
+    run(
+        &format!("{}\n", header),
+        r#"
+        names = CSVParser(path, 100, dedup_headers=True).get_file_info()["headers"]
+        assert names[:3] == ["x", "x.1", "x.2"] and len(set(names)) == 20_000
+        "#,
+    );
+}
+
+#[test]
+fn iterators_release_the_file_when_a_read_fails() {
+    let file = TempFile::new(".csv", b"a,b\n1,2\n\xff\xfe,3\n4,5\n");
+    run_with(
+        &file,
+        r#"
+        import os
+
+        def open_handles():
+            fds = os.listdir("/proc/self/fd")
+            return sum(1 for fd in fds if os.path.realpath(f"/proc/self/fd/{fd}") == os.path.realpath(path))
+
+        for make in (
+            lambda p: p.iter_rows(),
+            lambda p: p.lazy_batches(),
+            lambda p: p.lazy_batches("columns"),
+        ):
+            it = make(CSVParser(path, 1))
+            next(it)
+            assert open_handles() >= 1
+            try:
+                next(it)
+                raise AssertionError("the invalid row was read")
+            except ValueError:
+                pass
+            assert open_handles() == 0
+            assert next(it, None) is None
+
+        # The file shrinking between batches
+        for make in (lambda p: p.iter_rows(), lambda p: p.lazy_batches()):
+            with open(path, "w") as f:
+                f.writelines(f"{i},{i}\n" for i in range(10_000))
+            it = make(CSVParser(path, 1))
+            next(it)
+            with open(path, "w") as f:
+                f.write("a,b\n")
+            try:
+                for _ in it:
+                    pass
+                raise AssertionError("the truncation went unnoticed")
+            except FileChangedDuringRead:
+                pass
+            assert open_handles() == 0
+        "#,
+    );
+}
+
+#[test]
+fn throttled_reads_let_other_threads_run() {
+    // 256KB, four throttle chunks, at 1MB/s
+    let rows: String = (0..16_384)
+        .map(|i| format!("{:07},{:06}\n", i, i))
+        .collect();
+    run(
+        &format!("a,b\n{}", rows),
+        r#"
+        import threading, time
+
+        def ticks_during(scan):
+            ticks = [0]
+            done = threading.Event()
+            def tick():
+                while not done.is_set():
+                    ticks[0] += 1
+                    time.sleep(0.005)
+            thread = threading.Thread(target=tick)
+            thread.start()
+            started = time.perf_counter()
+            try:
+                scan()
+            finally:
+                done.set()
+                thread.join()
+            return ticks[0], time.perf_counter() - started
+
+        p = CSVParser(path, 1000, max_bytes_per_sec=1_000_000, signal_check_interval=0)
+        # hash_rows reads without the GIL, read() with it
+        for scan in (p.hash_rows, p.read):
+            ticks, elapsed = ticks_during(scan)
+            assert elapsed >= 0.2, elapsed
+            assert ticks >= 10, ticks
+        "#,
+    );
+}
+
+#[test]
+fn tolerated_truncation_shows_in_read_stats() {
+    let file = TempFile::new(".csv", b"a,b\n");
+    run_with(
+        &file,
+        r#"
+        for make in (lambda p: p.iter_rows(), lambda p: p.lazy_batches()):
+            with open(path, "w") as f:
+                f.write("a,b\n")
+                f.writelines(f"{i},{i}\n" for i in range(10_000))
+            p = CSVParser(path, 1, tolerate_truncation=True)
+            it = make(p)
+            next(it)
+            with open(path, "w") as f:
+                f.write("a,b\n")
+            rest = list(it)
+            assert it.truncated
+            assert len(rest) < 9_999, len(rest)
+            assert p.last_read_stats()["truncated"] is True
+
+            # A later read that gets to the end clears it
+            p.read()
+            assert p.last_read_stats()["truncated"] is False
+        "#,
+    );
+}
+
+#[test]
+fn where_in_compares_large_ints_exactly() {
+    run(
+        "id\n100000000000000000000\n100000000000000000001\n+000100000000000000000001\n1e20\n9007199254740993\n-9223372036854775809\n",
+        r#"
+        def ids(values):
+            return [r["id"] for r in CSVParser(path, 2).read_where_in("id", values)]
+
+        assert ids([10**20 + 1]) == ["100000000000000000001", "+000100000000000000000001"]
+        assert ids([10**20]) == ["100000000000000000000", "1e20"]
+        assert ids([1e20]) == ["100000000000000000000", "1e20"]
+        # 2**53 + 1 isn't a float, so the float 2**53 doesn't match it
+        assert ids([float(2**53)]) == []
+        assert ids([2**53 + 1]) == ["9007199254740993"]
+        assert ids([-(2**63) - 1]) == ["-9223372036854775809"]
+        "#,
+    );
+}
+
+#[test]
+fn sparse_projection_checks_every_field() {
+    let header: Vec<String> = (0..40).map(|i| format!("c{}", i)).collect();
+    let good: Vec<String> = (0..40).map(|i| i.to_string()).collect();
+    let mut csv = format!("{}\n{}\n", header.join(","), good.join(",")).into_bytes();
+    let mut bad = good.clone();
+    bad[30] = "x".to_string();
+    csv.extend(bad.join(",").replace(",x,", ",\u{1}\u{2},").bytes());
+    let at = csv.iter().position(|&b| b == 1).unwrap();
+    csv[at] = 0xc3;
+    csv[at + 1] = b'(';
+    let file = TempFile::new(".csv", &csv);
+    run_with(
+        &file,
+        r#"
+        def error(columns):
+            p = CSVParser(path, 10, columns=columns)
+            try:
+                p.read()
+                raise AssertionError("the invalid field went unnoticed")
+            except ValueError as e:
+                return str(e), p.last_read_stats()["projection"]
+
+        sparse = error(["c0", "c1"])
+        plain = error([f"c{i}" for i in range(20)])
+        assert sparse[1] == "sparse" and plain[1] == "projection", (sparse, plain)
+        assert sparse[0] == plain[0], (sparse, plain)
+        assert "field: 30" in sparse[0], sparse
+
+        p = CSVParser(path, 10)
+        assert p.last_read_stats() is None
+        "#,
+    );
+}
+
+#[test]
+fn defaults_fill_the_missing_cells_of_short_rows() {
+    run(
+        "a,b,c,d\n1,,3,4\n5\n6,7\n",
+        r#"
+        defaults = {"b": 0, "c": "n/a"}
+        rows = [r for batch in CSVParser(path, 2, defaults=defaults).read() for r in batch]
+        assert rows == [
+            {"a": "1", "b": 0, "c": "3", "d": "4"},
+            {"a": "5", "b": 0, "c": "n/a"},
+            {"a": "6", "b": "7", "c": "n/a"},
+        ], rows
+
+        rows = [r for batch in CSVParser(path, 2, defaults=defaults, sparse=True).read() for r in batch]
+        assert rows[1] == {"a": "5", "b": 0, "c": "n/a"}, rows
+
+        p = CSVParser(path, 2, defaults=defaults, compat="dictreader", restval="-")
+        rows = [r for batch in p.read() for r in batch]
+        assert rows[1] == {"a": "5", "b": 0, "c": "n/a", "d": "-"}, rows
+
+        p = CSVParser(path, 2, defaults=defaults, columns=["a", "c"])
+        assert [r["c"] for r in p.iter_rows()] == ["3", "n/a", "n/a"]
+        "#,
+    );
+}
+
+#[test]
+fn schema_drift_catches_a_repeated_header_of_the_same_width() {
+    run(
+        "id,name,email\n1,ann,a@x\n2,bob,b@x\nid,full_name,email\n3,cy,c@x\n",
+        r#"
+        import warnings
+
+        for columns in (None, ["id"]):
+            p = CSVParser(path, 10, schema_drift="error", normalize_headers="snake", columns=columns)
+            try:
+                p.read()
+                raise AssertionError("the repeated header went unnoticed")
+            except ValueError as e:
+                assert (e.row, e.expected, e.found, e.header) == (2, 3, 3, True), e
+
+        with warnings.catch_warnings(record=True) as caught:
+            warnings.simplefilter("always")
+            rows = list(CSVParser(path, 10, schema_drift="warn").iter_rows())
+        assert len(rows) == 4
+        assert len(caught) == 1 and "repeated header" in str(caught[0].message), caught
+
+        # A data row holding one column name isn't a header
+        with open(path, "w") as f:
+            f.write("id,name,email\n1,name,a@x\n")
+        assert len(list(CSVParser(path, 10, schema_drift="error").iter_rows())) == 1
+
+        # Field count drift still reports header=False
+        with open(path, "w") as f:
+            f.write("id,name,email\n1,ann\n")
+        try:
+            CSVParser(path, 10, schema_drift="error").read()
+            raise AssertionError("the short row went unnoticed")
+        except ValueError as e:
+            assert (e.expected, e.found, e.header) == (3, 2, False), e
+        "#,
+    );
+}
+
+#[test]
+fn schema_drift_lets_rows_holding_column_names_through() {
+    run(
+        "name,kind\nalice,name\nbob,kind\nkind,x\n",
+        r#"
+        rows = [r for b in CSVParser(path, 10, schema_drift="error").read() for r in b]
+        assert rows == [
+            {"name": "alice", "kind": "name"},
+            {"name": "bob", "kind": "kind"},
+            {"name": "kind", "kind": "x"},
+        ], rows
+
+        # Two names of three is a header with a column renamed; one isn't
+        with open(path, "w") as f:
+            f.write("id,name,email\n1,name,email\n2,id,x\n")
+        try:
+            CSVParser(path, 10, schema_drift="error").read()
+            raise AssertionError("the repeated header went unnoticed")
+        except ValueError as e:
+            assert (e.row, e.header) == (0, True), e
+        with open(path, "w") as f:
+            f.write("id,name,email\n2,id,x\n")
+        assert len(list(CSVParser(path, 10, schema_drift="error").iter_rows())) == 1
+        "#,
+    );
+}
+
+#[test]
+fn schema_drift_counts_rows_from_the_top_after_a_seek() {
+    let mut csv = String::from("id,v\n");
+    for i in 0..3000 {
+        match i < 1100 {
+            true => csv.push_str(&format!("{},{}\n", i, i)),
+            false => csv.push_str(&format!("{},{},x\n", i, i)),
+        }
+    }
+    run(
+        &csv,
+        r#"
+        import warnings
+
+        for strategy in ("estimate", "auto", "exact"):
+            p = CSVParser(path, 10, schema_drift="error", seek_strategy=strategy)
+            # auto warns that its landing was too far off and falls back
+            with warnings.catch_warnings():
+                warnings.simplefilter("ignore")
+                try:
+                    p.read_chunk_optimized(2000, 5)
+                    raise AssertionError("the wide rows went unnoticed")
+                except ValueError as e:
+                    # The first row read is the one reported, numbered from the
+                    # top of the file; an estimated landing is taken to be
+                    # start_row
+                    assert (e.row, e.expected, e.found, e.header) == (2000, 2, 3, False), (strategy, e)
+        "#,
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn to_sqlite_loads_the_selected_columns_under_their_keys() {
+    run(
+        "id,name,score,note\n1,ann,2.5,x\n2,bob,,y\n3,cy,4,z\n",
+        r#"
+        import os, sqlite3, tempfile
+
+        db = os.path.join(tempfile.mkdtemp(), "out.db")
+        p = CSVParser(path, 10, columns=["score", "id"], rename={"id": "ID"})
+        assert p.to_sqlite(db, "t") == 3
+        with sqlite3.connect(db) as conn:
+            info = conn.execute("PRAGMA table_info(t)").fetchall()
+            assert [(c[1], c[2]) for c in info] == [("score", "REAL"), ("ID", "INTEGER")], info
+            rows = conn.execute("SELECT * FROM t ORDER BY ID").fetchall()
+        assert rows == [(2.5, 1), (None, 2), (4.0, 3)], rows
+
+        db = os.path.join(tempfile.mkdtemp(), "all.db")
+        assert CSVParser(path, 10).to_sqlite(db, "t", batch_commit=2) == 3
+        with sqlite3.connect(db) as conn:
+            rows = conn.execute("SELECT id, name, note FROM t ORDER BY id").fetchall()
+        assert rows == [(1, "ann", "x"), (2, "bob", "y"), (3, "cy", "z")], rows
+        "#,
+    );
+}
+
+#[test]
+fn max_columns_cuts_records_as_they_are_read() {
+    run(
+        "a,b,c\n1,2,3,4,5\n\"x,\ny\",\"p\nq\",\"r,\ns\",t\n6,7\n8,9,10\n",
+        r#"
+        p = CSVParser(path, 2, max_columns=2, schema_drift="error")
+        rows = [r for batch in p.read() for r in batch]
+        assert rows == [
+            {"a": "1", "b": "2"},
+            {"a": "x,\ny", "b": "p\nq"},
+            {"a": "6", "b": "7"},
+            {"a": "8", "b": "9"},
+        ], rows
+        # Positions, seeks and row numbers still line up after a cut record
+        got = [p.get_row(i) for i in range(4)]
+        assert got == rows, got
+        got = p.read_chunk(1, 3)
+        assert got == rows[1:], got
+        assert p.count_rows() == 4, p.count_rows()
+        got = p.read_chunk_optimized(3, 1)
+        assert got == rows[3:], got
+        offsets = p.scan_record_offsets()
+        assert [e["row"] for e in p.read_rows_at(offsets)] == rows, offsets
+
+        # A hostile line: many millions of fields take no memory past the kept ones
+        import resource
+        with open(path, "w") as f:
+            f.write("a,b\n" + "," * 30_000_000 + "\n1,2\n")
+        before = resource.getrusage(resource.RUSAGE_SELF).ru_maxrss
+        rows = list(CSVParser(path, 2, max_columns=2).iter_rows())
+        grown_mb = (resource.getrusage(resource.RUSAGE_SELF).ru_maxrss - before) / 1024
+        assert rows == [{"a": "", "b": ""}, {"a": "1", "b": "2"}], rows
+        assert grown_mb < 100, grown_mb
+        "#,
+    );
+}
+
+#[test]
+fn content_hash_is_the_xxh64_of_a_user_read_only() {
+    run(
+        "\u{feff}id,name,empty\n1,ann,\n2,bob,\n3,cy,\n4,dee,\n5,ed,\n",
+        r#"
+        # xxHash64, written out from the specification
+        M = 2**64 - 1
+        P1, P2, P3 = 11400714785074694791, 14029467366897019727, 1609587929392839161
+        P4, P5 = 9650029242287828579, 2870177450012600261
+        rotl = lambda x, r: ((x << r) | (x >> (64 - r))) & M
+        le = lambda b: int.from_bytes(b, "little")
+        def rnd(acc, lane):
+            return rotl((acc + lane * P2) & M, 31) * P1 & M
+        def xxh64(data):
+            n, i = len(data), 0
+            if n >= 32:
+                v = [(P1 + P2) & M, P2, 0, (-P1) & M]
+                while i + 32 <= n:
+                    v = [rnd(v[j], le(data[i + 8 * j:i + 8 * j + 8])) for j in range(4)]
+                    i += 32
+                h = (rotl(v[0], 1) + rotl(v[1], 7) + rotl(v[2], 12) + rotl(v[3], 18)) & M
+                for lane in v:
+                    h = ((h ^ rnd(0, lane)) * P1 + P4) & M
+            else:
+                h = P5
+            h = (h + n) & M
+            while i + 8 <= n:
+                h = (rotl(h ^ rnd(0, le(data[i:i + 8])), 27) * P1 + P4) & M
+                i += 8
+            if i + 4 <= n:
+                h = (rotl(h ^ (le(data[i:i + 4]) * P1 & M), 23) * P2 + P3) & M
+                i += 4
+            for b in data[i:]:
+                h = rotl(h ^ (b * P5 & M), 11) * P1 & M
+            h = (h ^ (h >> 33)) * P2 & M
+            h = (h ^ (h >> 29)) * P3 & M
+            return "%016x" % (h ^ (h >> 32))
+        assert xxh64(b"") == "ef46db3751d8e999"
+        expected = xxh64(open(path, "rb").read())
+
+        # drop_empty_columns reads the whole file in the constructor; that
+        # isn't a read of the caller's
+        p = CSVParser(path, 2, hash_content=True, drop_empty_columns=True)
+        assert p.content_hash() is None, p.content_hash()
+        p.read_chunk_optimized(1, 2)
+        assert p.content_hash() is None, p.content_hash()
+        rows = [r for batch in p.read() for r in batch]
+        assert rows[0] == {"id": "1", "name": "ann"}, rows
+        assert p.content_hash() == expected, (p.content_hash(), expected)
+        assert CSVParser(path, 2).content_hash() is None
+        "#,
+    );
+}
+
+#[test]
+fn distinguish_empty_quoted_tells_quoted_from_missing_cells() {
+    run(
+        "s,j,d,t\r\n,,,x\r\n\"\",\"\",\"\",x\r\n\r\n\"\"\"\",1,\"\",\r\n\"p\r\nq\",\"\",2,\"\"",
+        r#"
+        from decimal import Decimal
+        p = CSVParser(path, 2, distinguish_empty_quoted=True, json_columns=["j"], decimal_columns=["d"])
+        want = [
+            {"s": None, "j": None, "d": None, "t": "x"},
+            {"s": "", "j": "", "d": None, "t": "x"},
+            {"s": '"', "j": 1, "d": None, "t": None},
+            {"s": "p\r\nq", "j": "", "d": Decimal("2"), "t": ""},
+        ]
+        rows = [r for batch in p.read() for r in batch]
+        assert rows == want, rows
+        assert list(p.iter_rows()) == want
+        # Reads that seek to a record
+        got = [p.get_row(i) for i in range(4)]
+        assert got == want, got
+        got = p.read_chunk_optimized(2, 2)
+        assert got == want[2:], got
+        offsets = p.scan_record_offsets()
+        got = [e["row"] for e in p.read_rows_at([offsets[3], offsets[1]])]
+        assert got == [want[1], want[3]], got
+        batches = list(p.lazy_batches("columns"))
+        assert [v for b in batches for v in b["s"]] == [None, "", '"', "p\r\nq"], batches
+        assert [v for b in batches for v in b["t"]] == ["x", "x", None, ""], batches
+
+        # Without the option, and with all_strings, both are ""
+        rows = list(CSVParser(path, 2).iter_rows())
+        assert [r["s"] for r in rows] == ["", "", '"', "p\r\nq"], rows
+        rows = list(CSVParser(path, 2, distinguish_empty_quoted=True, all_strings=True).iter_rows())
+        assert [r["t"] for r in rows] == ["x", "x", "", ""], rows
+
+        # Records across many buffers, read as a stream and from memory
+        with open(path, "w") as f:
+            f.write("n,s\n" + "".join(f'{i},{"" if i % 3 else chr(34) * 2}\n' for i in range(300_000)))
+        p = CSVParser(path, 1000, distinguish_empty_quoted=True)
+        want = [None if i % 3 else "" for i in range(300_000)]
+        for mode in ("stream", "memory"):
+            got = [r["s"] for batch in p.read(mode) for r in batch]
+            assert got == want, mode
+        "#,
+    );
+}
+
+#[test]
+fn snapshot_copy_survives_a_save_during_the_read() {
+    run(
+        "id,v\n",
+        r#"
+        import fcntl, gc, glob, os, subprocess, tempfile
+        old = "id,v\n" + "".join(f"{i:06d},old\n" for i in range(20_000))
+        def values(snapshot):
+            with open(path, "w") as f:
+                f.write(old)
+            p = CSVParser(path, 100, snapshot=snapshot)
+            rows = p.iter_rows()
+            seen = [next(rows)["v"] for _ in range(10)]
+            # Saved in place, at the same size, while the read is under way
+            with open(path, "r+") as f:
+                f.write(old.replace("old", "new"))
+            return seen + [r["v"] for r in rows]
+        assert set(values("copy")) == {"old"}
+        # Without a copy the read is torn; an advisory lock doesn't stop a
+        # writer that doesn't lock
+        assert set(values("none")) == {"old", "new"}
+        assert set(values("lock")) == {"old", "new"}
+
+        # Copies a killed process left behind go when the next one is made;
+        # those of a process that holds its lock file stay, as do files
+        # the crate didn't make
+        tmp = tempfile.gettempdir()
+        others = [os.path.join(tmp, n) for n in ("csv_reader-2024-report.csv", "csv_reader-2024.lock")]
+        for name in others:
+            open(name, "w").close()
+        dead = subprocess.Popen(["true"])
+        dead.wait()
+        live = 4_000_000 + os.getpid() % 100_000
+        made = {}
+        for pid in (dead.pid, live):
+            made[pid] = [
+                os.path.join(tmp, f"csv_reader-{pid}-0-x.csv.csv_reader-snapshot"),
+                os.path.join(tmp, f"csv_reader-{pid}.csv_reader-lock"),
+            ]
+            for name in made[pid]:
+                open(name, "w").close()
+        held = open(made[live][1], "w")
+        fcntl.flock(held, fcntl.LOCK_EX)
+        try:
+            p = CSVParser(path, 100, snapshot="copy")
+            assert not any(os.path.exists(n) for n in made[dead.pid]), made
+            assert all(os.path.exists(n) for n in made[live]), made
+            assert all(os.path.exists(n) for n in others), others
+            ours = glob.glob(os.path.join(tmp, f"csv_reader-{os.getpid()}*"))
+            assert len(ours) == 2, ours
+            del p
+            gc.collect()
+            ours = glob.glob(os.path.join(tmp, f"csv_reader-{os.getpid()}*"))
+            assert ours == [], ours
+        finally:
+            held.close()
+            for name in made[live] + others:
+                os.remove(name)
+        "#,
+    );
+}
+
+// Checks a parser `p` over a compressed copy of the CSV at `plain` against
+// a full read of the plain file, for the compressed-file tests below
+#[cfg(any(feature = "bgzf", feature = "zstd"))]
+const MATCHES_PLAIN: &str = r#"
+        def matches_plain(p, plain):
+            want = [r for b in CSVParser(plain, 7).read() for r in b]
+            assert len(want) > 2000, len(want)
+            assert [r for b in p.read() for r in b] == want
+            for start, n in [(0, 5), (13, 20), (150, 400), (2001, 30), (len(want) - 3, 10)]:
+                got = p.read_chunk(start, n)
+                assert got == want[start:start + n], (start, got)
+                got = p.read_chunk_optimized(start, n)
+                assert got == want[start:start + n], (start, got)
+            for i in (0, 77, 1999, len(want) - 1):
+                assert p.get_row(i) == want[i], i
+            offsets = p.scan_record_offsets()
+            assert len(offsets) == len(want)
+            assert [e["row"] for e in p.read_rows_at(offsets[::37])] == want[::37]
+            assert list(p.read_reversed())[:3] == want[::-1][:3]
+            assert p.get_file_info()["size_bytes"] == os.path.getsize(plain)
+"#;
+
+#[cfg(feature = "bgzf")]
+#[test]
+fn bgzf_and_gzip_reads_match_the_plain_file() {
+    run(
+        "",
+        &format!(
+            "{}{}",
+            MATCHES_PLAIN,
+            r#"
+        import gzip, os, struct, warnings, zlib
+
+        # A BGZF block: raw deflate in a gzip member whose "BC" extra field
+        # gives the member's size
+        def block(data):
+            c = zlib.compressobj(6, zlib.DEFLATED, -15)
+            body = c.compress(data) + c.flush()
+            header = b"\x1f\x8b\x08\x04\0\0\0\0\0\xff\x06\0BC\x02\0"
+            size = struct.pack("<H", len(header) + 2 + len(body) + 8 - 1)
+            return header + size + body + struct.pack("<II", zlib.crc32(data), len(data))
+
+        def bgzf(data, size):
+            blocks = [block(data[i:i + size]) for i in range(0, len(data), size)]
+            return blocks + [block(b"")]
+
+        # Records cross block boundaries, some inside a quoted field
+        text = "id,name,v\r\n" + "".join(f'{i},"n\n{i}",{i * 3}\r\n' for i in range(3000))
+        plain = path + ".plain"
+        with open(plain, "w", newline="") as f:
+            f.write(text)
+
+        with open(path, "wb") as f:
+            f.write(b"".join(bgzf(text.encode(), 100)))
+        with warnings.catch_warnings():
+            warnings.simplefilter("error")
+            p = CSVParser(path, 7)
+        matches_plain(p, plain)
+
+        # Plain gzip reads sequentially, with a warning
+        with open(path, "wb") as f:
+            f.write(gzip.compress(text.encode()))
+        with warnings.catch_warnings(record=True) as caught:
+            warnings.simplefilter("always")
+            p = CSVParser(path, 7)
+        assert [w.category for w in caught] == [CSVReaderWarning], caught
+        assert "read sequentially" in str(caught[0].message), caught[0].message
+        matches_plain(p, plain)
+
+        # The index is built by the first seek, not when the parser is made,
+        # so a bad block far into the file only fails the seek
+        big = "id,v\n" + "".join(f"{i},{i * 7}\n" for i in range(200_000))
+        blocks = bgzf(big.encode(), 4096)
+        blocks[-10] = b"\0" * len(blocks[-10])
+        with open(path, "wb") as f:
+            f.write(b"".join(blocks))
+        p = CSVParser(path, 7)
+        assert p.read_chunk(5, 2) == [{"id": "5", "v": "35"}, {"id": "6", "v": "42"}]
+        try:
+            p.read_chunk_optimized(150_000, 1)
+            raise AssertionError("a seek read the bad block's header")
+        except OSError as e:
+            assert "BGZF block" in str(e), e
+        os.remove(plain)
+        "#
+        ),
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn seekable_and_plain_zstd_reads_match_the_plain_file() {
+    let text: String = std::iter::once("id,name,v\r\n".to_string())
+        .chain((0..3000).map(|i| format!("{},\"n\n{}\",{}\r\n", i, i, i * 3)))
+        .collect();
+    let plain = TempFile::new(".csv", text.as_bytes());
+
+    // Frames of 100 bytes each, then the seek table: a skippable frame of
+    // (compressed, uncompressed) sizes and the footer
+    let frames: Vec<(Vec<u8>, usize)> = text
+        .as_bytes()
+        .chunks(100)
+        .map(|chunk| (zstd::bulk::compress(chunk, 3).unwrap(), chunk.len()))
+        .collect();
+    let mut seekable = Vec::new();
+    let mut table = Vec::new();
+    for (frame, len) in &frames {
+        seekable.extend_from_slice(frame);
+        table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(*len as u32).to_le_bytes());
+    }
+    seekable.extend_from_slice(&0x184D_2A5Eu32.to_le_bytes());
+    seekable.extend_from_slice(&(table.len() as u32 + 9).to_le_bytes());
+    seekable.extend_from_slice(&table);
+    seekable.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    seekable.push(0);
+    seekable.extend_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+    let single = TempFile::new(
+        ".csv.zst",
+        &zstd::bulk::compress(text.as_bytes(), 3).unwrap(),
+    );
+
+    let code = format!(
+        "{}\n        import os, warnings\n        plain = {:?}\n        zstd_path = {:?}\n{}",
+        MATCHES_PLAIN,
+        plain.0.to_string_lossy(),
+        single.0.to_string_lossy(),
+        r#"
+        with warnings.catch_warnings():
+            warnings.simplefilter("error")
+            p = CSVParser(path, 7)
+        matches_plain(p, plain)
+
+        # A single frame has no seek table, so it reads sequentially
+        with warnings.catch_warnings(record=True) as caught:
+            warnings.simplefilter("always")
+            p = CSVParser(zstd_path, 7)
+        assert [w.category for w in caught] == [CSVReaderWarning], caught
+        matches_plain(p, plain)
+        "#
+    );
+    run_with(&TempFile::new(".csv.zst", &seekable), &code);
+}
+
+#[test]
+fn chunk_lines_count_every_line_ending() {
+    run(
+        "",
+        r#"
+        # (id, note lines, blank lines before the record)
+        shape = [(0, 1, 0), (1, 3, 0), (2, 1, 1), (3, 2, 0), (4, 1, 2), (5, 4, 0), (6, 1, 0)]
+        for eol in ["\n", "\r\n", "\r"]:
+            text, want, line = "id,note" + eol, [], 0
+            for i, lines, blanks in shape:
+                note = eol.join(f"n{i}.{j}" for j in range(lines))
+                text += eol * blanks + f'{i},"{note}"' + eol
+                want.append((line, {"id": str(i), "note": note}))
+                line += blanks + lines
+            with open(path, "w", newline="") as f:
+                f.write(text)
+
+            p = CSVParser(path, 3, chunk_unit="lines")
+            for start in range(line + 1):
+                for n in (1, 2, 5):
+                    got = p.read_chunk(start, n)
+                    expect = [row for at, row in want if start <= at < start + n]
+                    assert got == expect, (eol, start, n, got)
+            # Records and lines share the pool
+            assert p.get_row(4) == want[4][1]
+            assert p.read_chunk(want[5][0], 1) == [want[5][1]], eol
+            chunks = [r for s in range(0, line, 3) for r in p.read_chunk(s, 3)]
+            assert chunks == [row for _, row in want], eol
+
+        try:
+            CSVParser(path, 3, chunk_unit="lines", max_columns=2)
+            raise AssertionError("chunk_unit=\"lines\" took max_columns")
+        except ValueError as e:
+            assert "max_columns" in str(e), e
+        "#,
+    );
+}
+
+#[test]
+fn join_cleans_the_other_files_header_names() {
+    run(
+        "Customer ID,Amount\n1,10\n2,20\n3,30\n",
+        r#"
+        import os
+        other = path + ".other.csv"
+        with open(other, "w", encoding="utf-8") as f:
+            f.write("\ufeffcustomerId,Name,name,Country\n1,ann,A,x\n2,bob,B,y\n1,cy,C,z\n")
+        p = CSVParser(path, 10, normalize_headers="snake", dedup_headers=True)
+        rows = p.join(other, on="customer_id", other_columns=["name.1", "country"])
+        assert rows == [
+            {"customer_id": "1", "amount": "10", "name.1": "A", "country": "x"},
+            {"customer_id": "1", "amount": "10", "name.1": "C", "country": "z"},
+            {"customer_id": "2", "amount": "20", "name.1": "B", "country": "y"},
+        ], rows
+        rows = p.join(other, on="customer_id", how="left")
+        assert [sorted(r) for r in rows] == [["amount", "country", "customer_id", "name", "name.1"]] * 4, rows
+        assert rows[-1]["name"] is None and rows[0]["name"] == "ann", rows
+
+        # Without the options both files keep their names as they are
+        p = CSVParser(path, 10)
+        try:
+            p.join(other, on="Customer ID")
+            raise AssertionError("joined on a column the other file doesn't have")
+        except ValueError as e:
+            assert "Unknown column" in str(e), e
+        os.remove(other)
+        "#,
+    );
+}
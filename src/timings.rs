@@ -0,0 +1,108 @@
+// timings=True: where a read's time goes, so a slow read can be pinned on
+// I/O, parsing, type conversion or building Python objects. The read loops
+// only take Instants when the option is on; each phase is a running total
+// in nanoseconds, and every read starts from a new Timings.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Default)]
+pub struct Timings {
+    // Reading bytes from the file, throttling included; shared with the
+    // read's Input (see files::Input::timer)
+    pub read: Arc<AtomicU64>,
+    // Splitting records, less the reads they waited on
+    pub parse: AtomicU64,
+    // decimal, JSON, int and float cell conversions
+    pub convert: AtomicU64,
+    // Building row dicts, less the conversions
+    pub build: AtomicU64,
+    // Python called on whole batches: pandas.DataFrame or
+    // pyarrow.RecordBatch.from_pydict for lazy_batches(format=...)
+    pub callback: AtomicU64,
+    // Wall time of the read, counting only time spent inside the parser
+    pub total: AtomicU64,
+}
+
+// The totals at one moment, in the order of Timings' fields
+#[derive(Clone, Copy)]
+pub struct Snapshot([u64; 6]);
+
+impl Timings {
+    pub fn add(counter: &AtomicU64, started: Instant) {
+        counter.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Add the time since `started` to `counter`, less what `inner` (a
+    // phase nested in it) gained since it read `inner_before`
+    pub fn add_outer(counter: &AtomicU64, started: Instant, inner: &AtomicU64, inner_before: u64) {
+        let elapsed = started.elapsed().as_nanos() as u64;
+        let nested = inner.load(Ordering::Relaxed).saturating_sub(inner_before);
+        counter.fetch_add(elapsed.saturating_sub(nested), Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let mut values = [0; 6];
+        for (value, counter) in values.iter_mut().zip(self.counters()) {
+            *value = counter.load(Ordering::Relaxed);
+        }
+        Snapshot(values)
+    }
+
+    fn counters(&self) -> [&AtomicU64; 6] {
+        [
+            &*self.read,
+            &self.parse,
+            &self.convert,
+            &self.build,
+            &self.callback,
+            &self.total,
+        ]
+    }
+}
+
+impl Snapshot {
+    // Nothing has been timed
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&ns| ns == 0)
+    }
+
+    // Time between `earlier` and this snapshot
+    pub fn since(&self, earlier: &Snapshot) -> Snapshot {
+        let mut values = [0; 6];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.0[i].saturating_sub(earlier.0[i]);
+        }
+        Snapshot(values)
+    }
+
+    // {"read", "parse", "convert", "build", "callback", "other", "total"}
+    // in seconds. The phases don't overlap, and "other" is the rest of
+    // "total", so together they add up to "total".
+    pub fn to_dict(self, py: Python) -> PyResult<PyObject> {
+        let [read, parse, convert, build, callback, total] = self.0;
+        let phases = [
+            ("read", read),
+            ("parse", parse),
+            ("convert", convert),
+            ("build", build),
+            ("callback", callback),
+        ];
+        let counted: u64 = phases.iter().map(|(_, ns)| ns).sum();
+
+        let dict = PyDict::new(py);
+        for (name, ns) in phases {
+            dict.set_item(name, seconds(ns))?;
+        }
+        dict.set_item("other", seconds(total.saturating_sub(counted)))?;
+        dict.set_item("total", seconds(total))?;
+        Ok(dict.to_object(py))
+    }
+}
+
+fn seconds(ns: u64) -> f64 {
+    ns as f64 / 1e9
+}
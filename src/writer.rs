@@ -0,0 +1,391 @@
+// Writing rows back out. CSVWriter accepts exactly what CSVParser.read()
+// returns (a list of batch lists of row dicts), or any iterable of batches
+// such as lazy_batches(), and streams it to disk row by row.
+//
+// Each record is encoded on its own before it goes into the file buffer,
+// so the writer knows its exact size. That is what bytes_written() and
+// flush_every_bytes count.
+
+use csv::{ReaderBuilder, StringRecord, Writer};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::files;
+use crate::format::ValueFormat;
+
+// Custom buffer size for faster I/O, same as the reader's
+const BUF_SIZE: usize = 64 * 1024;
+
+// Rows validate_round_trip keeps and compares with what is read back
+const ROUND_TRIP_SAMPLE: usize = 1000;
+
+#[pyclass]
+pub struct CSVWriter {
+    filename: String,
+    // Column order; taken from the first row's keys when not given
+    header: Option<Vec<String>>,
+    // None once closed
+    file: Option<BufWriter<File>>,
+    // Encodes one record at a time into `pending`, which is emptied into
+    // `file` after each
+    encoder: Writer<Pending>,
+    pending: Pending,
+    header_written: bool,
+    // Fields of the first ROUND_TRIP_SAMPLE rows, kept for the check on
+    // close when validate_round_trip is set
+    sample: Option<Vec<Vec<String>>>,
+    format: ValueFormat,
+    // Flush once this many rows or bytes have been written since the
+    // last flush
+    flush_every_rows: Option<usize>,
+    flush_every_bytes: Option<u64>,
+    // fsync the file on close, after the final flush
+    fsync_on_close: bool,
+    // Data rows and bytes (header included) written so far, and since the
+    // last flush
+    rows_written: usize,
+    bytes_written: u64,
+    unflushed_rows: usize,
+    unflushed_bytes: u64,
+}
+
+// The bytes of the record the encoder just wrote, shared between the
+// encoder and the writer, which takes them out after every record
+#[derive(Clone, Default)]
+struct Pending(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Pending {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut bytes = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl CSVWriter {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        filename,
+        header=None,
+        validate_round_trip=false,
+        float_format=None,
+        datetime_format=None,
+        bool_format=None,
+        null_format=None,
+        flush_every_rows=None,
+        flush_every_bytes=None,
+        fsync_on_close=false
+    ))]
+    fn new(
+        filename: String,
+        header: Option<Vec<String>>,
+        validate_round_trip: bool,
+        float_format: Option<String>,
+        datetime_format: Option<String>,
+        bool_format: Option<(String, String)>,
+        null_format: Option<String>,
+        flush_every_rows: Option<usize>,
+        flush_every_bytes: Option<u64>,
+        fsync_on_close: bool,
+    ) -> PyResult<Self> {
+        if flush_every_rows == Some(0) || flush_every_bytes == Some(0) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "flush_every_rows and flush_every_bytes must be positive",
+            ));
+        }
+
+        let file = match files::create(Path::new(&filename)) {
+            Ok(f) => f,
+            Err(e) => return Err(files::io_error("create file", e)),
+        };
+
+        let pending = Pending::default();
+        Ok(CSVWriter {
+            filename,
+            header,
+            file: Some(BufWriter::with_capacity(BUF_SIZE, file)),
+            encoder: Writer::from_writer(pending.clone()),
+            pending,
+            header_written: false,
+            sample: validate_round_trip.then(Vec::new),
+            format: ValueFormat::new(float_format, datetime_format, bool_format, null_format),
+            flush_every_rows,
+            flush_every_bytes,
+            fsync_on_close,
+            rows_written: 0,
+            bytes_written: 0,
+            unflushed_rows: 0,
+            unflushed_bytes: 0,
+        })
+    }
+
+    // Write every row of every batch and return how many rows were written.
+    // Batches are consumed one at a time, so a generator of batches never
+    // has to be materialized. Can be called repeatedly to append.
+    fn write_batches(&mut self, batches: &PyAny) -> PyResult<usize> {
+        let mut rows = 0;
+        for batch in batches.iter()? {
+            for row in batch?.iter()? {
+                let row: &PyDict = row?.downcast()?;
+                self.write_row(row)?;
+                rows += 1;
+            }
+        }
+        // An explicit header is written even when there were no rows
+        if !self.header_written && self.header.is_some() {
+            self.write_fields(None)?;
+        }
+        self.flush_buffered()?;
+        Ok(rows)
+    }
+
+    // Hand everything written so far to the operating system, so the rows
+    // are visible to other readers of the file. With `fsync`, also wait
+    // until they are on disk. Raises once the writer is closed.
+    #[pyo3(signature = (fsync=false))]
+    fn flush(&mut self, fsync: bool) -> PyResult<()> {
+        self.open_file()?;
+        self.flush_buffered()?;
+        if fsync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // Data rows written so far, not counting the header
+    fn rows_written(&self) -> usize {
+        self.rows_written
+    }
+
+    // Bytes written so far, header included. Rows not flushed yet are
+    // counted too.
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    // Flush and close the file, with an fsync first when fsync_on_close is
+    // set, so a close that returns means the data is on disk. Further
+    // writes raise. With validate_round_trip, the file is then read back
+    // (see check_round_trip).
+    fn close(&mut self) -> PyResult<()> {
+        self.flush_buffered()?;
+        if self.fsync_on_close && self.file.is_some() {
+            self.sync()?;
+        }
+        if self.file.take().is_some() && self.header_written {
+            if let Some(sample) = self.sample.take() {
+                self.check_round_trip(&sample)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<bool> {
+        self.close()?;
+        Ok(false)
+    }
+}
+
+impl CSVWriter {
+    fn write_row(&mut self, row: &PyDict) -> PyResult<()> {
+        if self.header.is_none() {
+            let keys = row
+                .keys()
+                .iter()
+                .map(|k| k.extract::<String>())
+                .collect::<PyResult<Vec<_>>>()?;
+            self.header = Some(keys);
+        }
+        let header = self.header.as_ref().unwrap();
+
+        // Like csv.DictWriter, a key outside the header is an error rather
+        // than silently dropped data
+        if let Some(extra) = row.keys().iter().find(|k| !header_has(header, k)) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Row has a key that is not in the header: {}",
+                extra
+            )));
+        }
+
+        let mut fields: Vec<String> = Vec::with_capacity(header.len());
+        for name in header {
+            fields.push(match row.get_item(name) {
+                Some(value) => self.format.format(value)?,
+                None => String::new(),
+            });
+        }
+        self.write_fields(Some(&fields))?;
+        if let Some(sample) = &mut self.sample {
+            if sample.len() < ROUND_TRIP_SAMPLE {
+                sample.push(fields);
+            }
+        }
+
+        self.rows_written += 1;
+        self.unflushed_rows += 1;
+        let due = self
+            .flush_every_rows
+            .is_some_and(|n| self.unflushed_rows >= n)
+            || self
+                .flush_every_bytes
+                .is_some_and(|n| self.unflushed_bytes >= n);
+        if due {
+            self.flush_buffered()?;
+        }
+        Ok(())
+    }
+
+    // Write one record (the header first, if it hasn't been yet)
+    fn write_fields(&mut self, fields: Option<&[String]>) -> PyResult<()> {
+        self.open_file()?;
+        let header = self.header.as_deref().unwrap_or_default();
+
+        let mut encoded = Ok(());
+        if !self.header_written {
+            encoded = self.encoder.write_record(header);
+            self.header_written = true;
+        }
+        if let Some(fields) = fields {
+            encoded = encoded.and_then(|_| self.encoder.write_record(fields));
+        }
+        let encoded = encoded.and_then(|_| self.encoder.flush().map_err(csv::Error::from));
+
+        let file = self.file.as_mut().unwrap();
+        let mut bytes = self.pending.0.lock().unwrap_or_else(|e| e.into_inner());
+        let written = encoded.and_then(|_| file.write_all(&bytes).map_err(csv::Error::from));
+        let len = bytes.len() as u64;
+        bytes.clear();
+        drop(bytes);
+
+        written.map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Failed to write row: {}", e))
+        })?;
+        self.bytes_written += len;
+        self.unflushed_bytes += len;
+        Ok(())
+    }
+
+    // The file, or an error once the writer is closed
+    fn open_file(&mut self) -> PyResult<&mut BufWriter<File>> {
+        match &mut self.file {
+            Some(f) => Ok(f),
+            None => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Writer for {} is closed",
+                self.filename
+            ))),
+        }
+    }
+
+    // Read the closed file back the way CSVParser does with default
+    // options and raise if the keys or the sampled rows a parser would
+    // produce differ from what was written. Catches header names that
+    // collapse into one dict key, and text the reader changes, such as a
+    // leading byte order mark on the first name.
+    fn check_round_trip(&self, sample: &[Vec<String>]) -> PyResult<()> {
+        let failed = |detail: String| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Round-trip check failed for {}: {}",
+                self.filename, detail
+            ))
+        };
+
+        let header = self.header.as_deref().unwrap_or_default();
+        for (i, name) in header.iter().enumerate() {
+            if header[..i].contains(name) {
+                return Err(failed(format!(
+                    "column {:?} is written more than once, so a parser keeps only one",
+                    name
+                )));
+            }
+        }
+
+        let file = match files::open(Path::new(&self.filename)) {
+            Ok(f) => f,
+            Err(e) => return Err(files::io_error("open file", e)),
+        };
+        let mut reader = ReaderBuilder::new().flexible(true).from_reader(file);
+        let read_error = |e: csv::Error| failed(format!("reading it back: {}", e));
+
+        let read_header = reader.headers().map_err(read_error)?;
+        if read_header.iter().ne(header.iter()) {
+            return Err(failed(format!(
+                "header was written as {:?} but reads back as {:?}",
+                header,
+                read_header.iter().collect::<Vec<_>>()
+            )));
+        }
+
+        let mut record = StringRecord::new();
+        for (row, fields) in sample.iter().enumerate() {
+            if !reader.read_record(&mut record).map_err(read_error)? {
+                return Err(failed(format!(
+                    "{} rows were written but only {} read back",
+                    sample.len(),
+                    row
+                )));
+            }
+            if record.iter().ne(fields.iter()) {
+                return Err(failed(format!(
+                    "row {} was written as {:?} but reads back as {:?}",
+                    row,
+                    fields,
+                    record.iter().collect::<Vec<_>>()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Flush the file buffer, if the file is still open
+    fn flush_buffered(&mut self) -> PyResult<()> {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.flush() {
+                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                    "Failed to write file: {}",
+                    e
+                )));
+            }
+        }
+        self.unflushed_rows = 0;
+        self.unflushed_bytes = 0;
+        Ok(())
+    }
+
+    // fsync the open file; call after flush_buffered
+    fn sync(&mut self) -> PyResult<()> {
+        let file = self.open_file()?;
+        if let Err(e) = file.get_ref().sync_all() {
+            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to sync file: {}",
+                e
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn header_has(header: &[String], key: &PyAny) -> bool {
+    match key.extract::<&str>() {
+        Ok(k) => header.iter().any(|h| h == k),
+        Err(_) => false,
+    }
+}